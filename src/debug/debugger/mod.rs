@@ -7,6 +7,15 @@ use rust_imgui::ImVec4;
 use rust_imgui::imstr::ImStr;
 use ::gba::Gba;
 use ::gba::core::memory::*;
+use ::gba::hw::lcd::color::COLOR_PROFILES;
+use ::gba::device::filter::{SCALERS, LCD_OVERLAYS};
+use ::gba::device::resampler::RESAMPLING_QUALITIES;
+use ::gba::device::audio_filter::AUDIO_OUTPUT_FILTERS;
+use ::gba::device::audio::AUDIO_BACKENDS;
+use ::gba::device::pacer::{FAST_FORWARD_SPEEDS, FAST_FORWARD_AUDIO_MODES};
+use ::gba::serialization::{SAVE_STATE_SLOTS, read_save_state_info};
+use ::gba::device::bindings;
+use ::gba::device::bindings::{Action, ACTIONS};
 use self::console::ImGuiConsole;
 use self::memory_editor::MemoryEditor;
 use self::profiler::ProfilerGUI;
@@ -55,6 +64,7 @@ pub struct DebugData {
 
 	pub ioreg_window_opened: bool,
 	pub pyrite_settings_window_opened: bool,
+	pub key_bindings_window_opened: bool,
 	pub delay_saving_pyrite_settings: bool,
 
 	pub second_acc: f32,
@@ -87,19 +97,20 @@ impl DebugData {
 			emulator_delay_plot: DataPlot::new("Delay", "Frame Delay", 64, 0.0, 100.0),
 
 			sound_info_window_opened: false,
-			sound_plot: DataPlot::with_skip("Signal", "Sound Output", 128, -32768.0, 32767.0, 16),
-			sound_channel_1_plot: DataPlot::with_skip("Signal", "Channel 1", 128, -32768.0, 32767.0, 16),
-			sound_channel_2_plot: DataPlot::with_skip("Signal", "Channel 2", 128, -32768.0, 32767.0, 16),
-			sound_channel_3_plot: DataPlot::with_skip("Signal", "Channel 3", 128, -32768.0, 32767.0, 16),
-			sound_channel_4_plot: DataPlot::with_skip("Signal", "Channel 4", 128, -32768.0, 32767.0, 16),
-			sound_channel_a_plot: DataPlot::with_skip("Signal", "Channel A", 128, -32768.0, 32767.0, 16),
-			sound_channel_b_plot: DataPlot::with_skip("Signal", "Channel B", 128, -32768.0, 32767.0, 16),
+			sound_plot: DataPlot::new("Signal", "Sound Output", 128, -32768.0, 32767.0),
+			sound_channel_1_plot: DataPlot::new("Signal", "Channel 1", 128, -32768.0, 32767.0),
+			sound_channel_2_plot: DataPlot::new("Signal", "Channel 2", 128, -32768.0, 32767.0),
+			sound_channel_3_plot: DataPlot::new("Signal", "Channel 3", 128, -32768.0, 32767.0),
+			sound_channel_4_plot: DataPlot::new("Signal", "Channel 4", 128, -32768.0, 32767.0),
+			sound_channel_a_plot: DataPlot::new("Signal", "Channel A", 128, -32768.0, 32767.0),
+			sound_channel_b_plot: DataPlot::new("Signal", "Channel B", 128, -32768.0, 32767.0),
 
 			profiler_window_opened: false,
 			profiler_gui: ProfilerGUI::new(),
 
 			ioreg_window_opened: false,
 			pyrite_settings_window_opened: false,
+			key_bindings_window_opened: false,
 			delay_saving_pyrite_settings: false,
 
 			second_acc: 0.0,
@@ -130,7 +141,7 @@ fn every_second(gba: &mut Gba, debugger: &mut DebugData) {
 
 	if gba.extras.paused { return }
 	console_log!("FIFOA: {}sm/s | {}sm/s", debugger.sample_counter,
-		gba.cpu.memory.internal_regs.audio_fifo_a.frequency);
+		gba.cpu.memory.internal_regs.get_timer_frequency(gba.cpu.memory.internal_regs.audio_fifo_a.timer as usize));
 	console_log!("{} tmr/s", debugger.timer_ov_counter);
 	console_log!("{} dma/s [{} trnfs/s]", debugger.dma_counter, debugger.dma_transfer_counter);
 	console_log!("{}, {}, {}, {}",
@@ -205,6 +216,50 @@ pub fn render_debugger(gba: &mut Gba) {
 			debugger.pyrite_settings_window_opened = true;
 		}
 
+		if imgui::menu_item(imstr!("Key Bindings")) {
+			debugger.key_bindings_window_opened = true;
+		}
+
+		if imgui::menu_item(imstr!("Screenshot")) {
+			gba.extras.request_screenshot = true;
+		}
+
+		if imgui::menu_item(imstr!("Save State ({})", hotkey(Action::SaveState))) {
+			gba.extras.request_save_state = true;
+		}
+
+		if imgui::menu_item(imstr!("Load State ({})", hotkey(Action::LoadState))) {
+			gba.extras.request_load_state = true;
+		}
+
+		// Slots can also be cycled through with F6.
+		for slot in 0..SAVE_STATE_SLOTS {
+			let marker = if slot == gba.extras.save_state_slot { ">" } else { " " };
+			let saved = match read_save_state_info(&gba.save_state_path(slot)) {
+				Ok(info) => info.time_string(),
+				Err(_) => "Empty".to_string(),
+			};
+			if imgui::menu_item(imstr!("{} Slot {} ({})", marker, slot, saved)) {
+				gba.extras.save_state_slot = slot;
+			}
+		}
+
+		if gba.device.recorder.is_some() {
+			if imgui::menu_item(imstr!("Stop Recording")) {
+				gba.extras.request_recording_toggle = true;
+			}
+		} else if imgui::menu_item(imstr!("Start Recording")) {
+			gba.extras.request_recording_toggle = true;
+		}
+
+		if gba.device.movie.is_some() {
+			if imgui::menu_item(imstr!("Stop Movie ({})", hotkey(Action::Movie))) {
+				gba.extras.request_movie_toggle = true;
+			}
+		} else if imgui::menu_item(imstr!("Start Movie Recording ({})", hotkey(Action::Movie))) {
+			gba.extras.request_movie_toggle = true;
+		}
+
 		imgui::end_popup();
 	}
 
@@ -219,6 +274,12 @@ pub fn render_debugger(gba: &mut Gba) {
 		imgui::end();
 	}
 
+	if debugger.key_bindings_window_opened {
+		imgui::begin(imstr!("Key Bindings"), &mut debugger.key_bindings_window_opened, imgui::ImGuiWindowFlags_None);
+		render_key_bindings(gba, debugger);
+		imgui::end();
+	}
+
 	if debugger.profiler_window_opened {
 		imgui::begin(imstr!("Profiler"), &mut debugger.profiler_window_opened, imgui::ImGuiWindowFlags_None);
 		debugger.profiler_gui.render();
@@ -246,6 +307,13 @@ pub fn render_debugger(gba: &mut Gba) {
 		imgui::text(imstr!("Frame Build Time: {:.2}ms", debugger.frame_build_time));
 		imgui::text(imstr!("Frame Render Time: {:.2}ms", debugger.frame_render_time));
 		imgui::text(imstr!("Frame Time: {:.2}ms", debugger.full_frame_time));
+		imgui::text(imstr!("Speed: {:.0}%", gba.device.pacer.measured_speed * 100.0));
+		imgui::text(imstr!("Rewind: {} snapshots, {:.2}MB", gba.device.rewind.len(), gba.device.rewind.memory() as f64 / (1024.0 * 1024.0)));
+		if let Some(ref mut movie) = gba.device.movie {
+			imgui::text(imstr!("Movie ({:?}): frame {}/{}, {} lag frames, {} rerecords, {} desyncs",
+				movie.mode, movie.frame, movie.frame_count(), movie.lag_frames, movie.rerecords, movie.desyncs));
+			imgui::checkbox(imstr!("Movie Read-Only"), &mut movie.read_only);
+		}
 		
 		debugger.emulator_delay_plot.render_histogram();
 
@@ -294,6 +362,20 @@ pub fn render_debugger(gba: &mut Gba) {
 
 	if debugger.sound_info_window_opened {
 		imgui::begin(imstr!("Emulator Sound"), &mut debugger.sound_info_window_opened, imgui::ImGuiWindowFlags_None);
+		if let Some(ref dump) = gba.device.audio_dump {
+			imgui::text(imstr!("Dumping to {} ({} samples)", dump.path().display(), dump.samples));
+			if imgui::button_def(imstr!("Stop Audio Dump")) {
+				gba.extras.request_audio_dump_toggle = true;
+			}
+		} else if imgui::button_def(imstr!("Start Audio Dump")) {
+			gba.extras.request_audio_dump_toggle = true;
+		}
+
+		imgui::text(imstr!("Output: {}", gba.device.audio.output_name()));
+		imgui::text(imstr!("Buffer: {:.0}% of {} buffers ({} ms target)",
+			gba.device.audio.buffer_fill * 100.0, gba.device.audio.ring_buffer.capacity(), gba.device.audio.target_latency));
+		imgui::text(imstr!("Rate Adjustment: {:+.3}%", gba.device.audio.rate_adjustment * 100.0));
+		imgui::text(imstr!("Underruns: {} | Overruns: {}", gba.device.audio.underruns(), gba.device.audio.overruns()));
 		debugger.sound_plot.render_lines();
 
 		if imgui::collapsing_header(imstr!("Channel 1"), imstr!("sc1_clpshr"), true, false) {
@@ -325,6 +407,47 @@ pub fn render_debugger(gba: &mut Gba) {
 
 pub fn render_pyrite_settings(debugger: &mut DebugData) {
 	let mut sc = false; // true if setting changed.
+	if imgui::collapsing_header(imstr!("Video Settings"), imstr!("settings_video_clpshr"), false, true) {
+		imgui::text(imstr!("Color Correction"));
+		let current_profile = psetting!(color_profile);
+		for profile in COLOR_PROFILES.iter() {
+			let mut selected = *profile == current_profile;
+			if imgui::checkbox(imstr!("{}", profile.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(color_profile, *profile);
+			}
+		}
+
+		imgui::text(imstr!("Scaling"));
+		let current_scaler = psetting!(scaler);
+		for scaler in SCALERS.iter() {
+			let mut selected = *scaler == current_scaler;
+			if imgui::checkbox(imstr!("{}", scaler.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(scaler, *scaler);
+			}
+		}
+		let mut scale = psetting!(scale) as i32;
+		if imgui::slider_int(imstr!("Scale"), &mut scale, 1, 4, imstr!("%.0fx")) {
+			sc |= true;
+			psetting!(scale, scale as u32);
+		}
+
+		imgui::text(imstr!("Overlay"));
+		let current_overlay = psetting!(lcd_overlay);
+		for overlay in LCD_OVERLAYS.iter() {
+			let mut selected = *overlay == current_overlay;
+			if imgui::checkbox(imstr!("{}", overlay.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(lcd_overlay, *overlay);
+			}
+		}
+
+		sc |= imgui::checkbox(imstr!("Frame Blending"), psetting_ptr!(frame_blending));
+		sc |= imgui::checkbox(imstr!("Filtered Screenshots"), psetting_ptr!(screenshot_filtered));
+		sc |= imgui::checkbox(imstr!("Filtered Recordings"), psetting_ptr!(recording_filtered));
+	}
+
 	if imgui::collapsing_header(imstr!("Audio Settings"), imstr!("settings_sound_clpshr"), false, true) {
 		let mut volume_percentage = (psetting!(master_volume) * 100.0) as i32;
 		if imgui::slider_int(imstr!("Master Volume"), &mut volume_percentage, 0, 100, imstr!("%.0f%%")) {
@@ -334,13 +457,158 @@ pub fn render_pyrite_settings(debugger: &mut DebugData) {
 		}
 
 		sc |= imgui::checkbox(imstr!("Sound Enabled"), psetting_ptr!(sound_enabled));
+
+		imgui::text(imstr!("Audio Output"));
+		let current_backend = psetting!(audio_backend);
+		for backend in AUDIO_BACKENDS.iter() {
+			let mut selected = *backend == current_backend;
+			if imgui::checkbox(imstr!("{}", backend.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(audio_backend, *backend);
+			}
+		}
+
+		let mut latency = psetting!(audio_latency) as i32;
+		if imgui::slider_int(imstr!("Target Latency"), &mut latency, 8, 200, imstr!("%.0f ms")) {
+			sc |= true;
+			psetting!(audio_latency, latency as u32);
+		}
+
+		imgui::text(imstr!("Resampling Quality"));
+		let current_quality = psetting!(resampling_quality);
+		for quality in RESAMPLING_QUALITIES.iter() {
+			let mut selected = *quality == current_quality;
+			if imgui::checkbox(imstr!("{}", quality.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(resampling_quality, *quality);
+			}
+		}
+
+		imgui::text(imstr!("Output Filter"));
+		let current_filter = psetting!(audio_output_filter);
+		for filter in AUDIO_OUTPUT_FILTERS.iter() {
+			let mut selected = *filter == current_filter;
+			if imgui::checkbox(imstr!("{}", filter.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(audio_output_filter, *filter);
+			}
+		}
+
 		sc |= imgui::checkbox(imstr!("Channel 1 Enabled"), psetting_ptr!(channel1_enabled));
 		sc |= imgui::checkbox(imstr!("Channel 2 Enabled"), psetting_ptr!(channel2_enabled));
 		sc |= imgui::checkbox(imstr!("Channel 3 Enabled"), psetting_ptr!(channel3_enabled));
 		sc |= imgui::checkbox(imstr!("Channel 4 Enabled"), psetting_ptr!(channel4_enabled));
 		sc |= imgui::checkbox(imstr!("Channel A Enabled"), psetting_ptr!(channela_enabled));
 		sc |= imgui::checkbox(imstr!("Channel B Enabled"), psetting_ptr!(channelb_enabled));
+		sc |= imgui::checkbox(imstr!("Dump Channel Stems"), psetting_ptr!(audio_dump_stems));
+	}
+
+	if imgui::collapsing_header(imstr!("Speed Settings"), imstr!("settings_speed_clpshr"), false, true) {
+		imgui::text(imstr!("Fast-Forward Speed (Hold {})", hotkey(Action::FastForward)));
+		let current_speed = psetting!(fast_forward_speed);
+		for speed in FAST_FORWARD_SPEEDS.iter() {
+			let mut selected = *speed == current_speed;
+			if imgui::checkbox(imstr!("{}", speed.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(fast_forward_speed, *speed);
+			}
+		}
+
+		imgui::text(imstr!("Fast-Forward Audio"));
+		let current_mode = psetting!(fast_forward_audio);
+		for mode in FAST_FORWARD_AUDIO_MODES.iter() {
+			let mut selected = *mode == current_mode;
+			if imgui::checkbox(imstr!("{}", mode.name()), &mut selected) && selected {
+				sc |= true;
+				psetting!(fast_forward_audio, *mode);
+			}
+		}
+	}
+
+	if imgui::collapsing_header(imstr!("Rewind Settings"), imstr!("settings_rewind_clpshr"), false, true) {
+		sc |= imgui::checkbox(imstr!("Rewind Enabled (Hold {})", hotkey(Action::Rewind)), psetting_ptr!(rewind_enabled));
+
+		let mut interval = psetting!(rewind_interval) as i32;
+		if imgui::slider_int(imstr!("Snapshot Interval"), &mut interval, 1, 30, imstr!("%.0f frames")) {
+			sc |= true;
+			psetting!(rewind_interval, interval as u32);
+		}
+
+		let mut length = psetting!(rewind_length) as i32;
+		if imgui::slider_int(imstr!("Length"), &mut length, 1, 300, imstr!("%.0f s")) {
+			sc |= true;
+			psetting!(rewind_length, length as u32);
+		}
+
+		let mut memory = psetting!(rewind_memory) as i32;
+		if imgui::slider_int(imstr!("Memory Limit"), &mut memory, 8, 1024, imstr!("%.0f MB")) {
+			sc |= true;
+			psetting!(rewind_memory, memory as u32);
+		}
+	}
+	if sc {
+		debugger.delay_saving_pyrite_settings = true;
+		::pyrite::settings_changed()
 	}
+}
+
+/// The keys bound to `action`, for showing next to the things they do.
+fn hotkey(action: Action) -> String {
+	bindings::keys_for_action(&::pyrite::get_settings().key_bindings, action)
+}
+
+pub fn render_key_bindings(gba: &mut Gba, debugger: &mut DebugData) {
+	let mut sc = false; // true if setting changed.
+
+	let mut turbo_rate = psetting!(turbo_rate) as i32;
+	if imgui::slider_int(imstr!("Turbo Rate"), &mut turbo_rate, 1, 30, imstr!("%.0f frames")) {
+		sc |= true;
+		psetting!(turbo_rate, turbo_rate as u32);
+	}
+
+	if imgui::button_def(imstr!("Reset to Defaults")) {
+		sc |= true;
+		psetting!(key_bindings, bindings::default_bindings());
+	}
+
+	imgui::columns_noid(3, true);
+	imgui::text(imstr!("Action"));
+	imgui::next_column();
+	imgui::text(imstr!("Keys"));
+	imgui::next_column();
+	imgui::next_column();
+
+	for action in ACTIONS.iter() {
+		let key_bindings = &mut ::pyrite::get_settings().key_bindings;
+		imgui::text(imstr!("{}", action.name()));
+		imgui::next_column();
+
+		if gba.extras.rebinding == Some(*action) {
+			imgui::text(imstr!("Press a key... (Escape to cancel)"));
+		} else {
+			imgui::text(imstr!("{}", bindings::keys_for_action(key_bindings, *action)));
+		}
+		let conflicts = bindings::conflicts(key_bindings, *action);
+		if !conflicts.is_empty() {
+			let names: Vec<&str> = conflicts.iter().map(|a| a.name()).collect();
+			imgui::push_style_color(imgui::ImGuiCol::Text, CONSOLE_COLOR_WARNING);
+			imgui::text(imstr!("Also bound to {}", names.join(", ")));
+			imgui::pop_style_color(1);
+		}
+		imgui::next_column();
+
+		if imgui::button_def(imstr!("Add##bind_{:?}", action)) {
+			gba.extras.rebinding = Some(*action);
+		}
+		imgui::same_line();
+		if imgui::button_def(imstr!("Clear##unbind_{:?}", action)) {
+			sc |= true;
+			bindings::unbind_action(key_bindings, *action);
+		}
+		imgui::next_column();
+	}
+	imgui::columns_noid(1, false);
+
 	if sc {
 		debugger.delay_saving_pyrite_settings = true;
 		::pyrite::settings_changed()
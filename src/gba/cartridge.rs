@@ -0,0 +1,65 @@
+//! Identifies the cartridge that is loaded so that save states (and anything
+//! else that only makes sense for one game) can tell games apart.
+
+use ::util::png::crc32;
+
+/// Where the 12 character game title is in the cartridge header.
+const TITLE_OFFSET: usize = 0xA0;
+
+/// Where the 4 character game code (e.g. AXVE) is in the cartridge header.
+const GAME_CODE_OFFSET: usize = 0xAC;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeInfo {
+	pub title: String,
+	pub game_code: String,
+
+	/// CRC-32 of the entire ROM.
+	pub crc: u32,
+}
+
+impl CartridgeInfo {
+	/// Info for when there is no cartridge (e.g. booting from multiboot).
+	pub fn none() -> CartridgeInfo {
+		CartridgeInfo {
+			title: String::new(),
+			game_code: String::new(),
+			crc: 0,
+		}
+	}
+
+	pub fn new(rom: &[u8]) -> CartridgeInfo {
+		if rom.is_empty() {
+			return CartridgeInfo::none()
+		}
+
+		CartridgeInfo {
+			title: header_string(rom, TITLE_OFFSET, 12),
+			game_code: header_string(rom, GAME_CODE_OFFSET, 4),
+			crc: crc32(rom),
+		}
+	}
+
+	/// Something to name files after, e.g. `POKEMON EMER-BPEE-1F1C08FB`.
+	pub fn file_name(&self) -> String {
+		if self.title.is_empty() && self.game_code.is_empty() {
+			format!("{:08X}", self.crc)
+		} else {
+			let name = format!("{}-{}-{:08X}", self.title, self.game_code, self.crc);
+			name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == ' ' { c } else { '_' }).collect()
+		}
+	}
+}
+
+/// Reads an ASCII string out of the header without the padding at the end.
+fn header_string(rom: &[u8], offset: usize, len: usize) -> String {
+	if rom.len() < offset + len {
+		return String::new()
+	}
+	rom[offset..(offset + len)].iter()
+		.take_while(|&&b| b != 0)
+		.map(|&b| if b >= 0x20 && b < 0x7F { b as char } else { '?' })
+		.collect::<String>()
+		.trim_right()
+		.to_string()
+}
@@ -96,6 +96,7 @@ impl ArmCpu {
 	/// all of the correct data ends up on the correct data bus (basically byte)
 	pub fn mread8_al(&self, address: u32) -> u32 {
 		// # TODO alignment shouldn't be necessary on these so I should remove it. (maybe)
+		self.memory.on_read(address, 1);
 		self.memory.read8(address) as u32
 	}
 
@@ -103,6 +104,7 @@ impl ArmCpu {
 	/// all of the correct data ends up on the correct data bus (basically byte)
 	pub fn mread8_signed_al(&self, address: u32) -> u32 {
 		// # TODO alignment shouldn't be necessary on these so I should remove it.(maybe)
+		self.memory.on_read(address, 1);
 		((self.memory.read8(address) as i8) as i32) as u32
 	}
 
@@ -110,6 +112,7 @@ impl ArmCpu {
 	/// all of the correct data ends up on the correct data bus (basically byte)
 	pub fn mread16_al(&self, address: u32) -> u32 {
 		// # TODO alignment shouldn't be necessary on these so I should remove it.(maybe)
+		self.memory.on_read(address & 0xFFFFFFFE, 2);
 		let data = self.memory.read16(address & 0xFFFFFFFE); // make sure what we retrieve is word aligned.
 		let offset = (address & 0x1) * 8; // offset from the word boundary in bits.
 		((data << (16 - offset)) | (data >> offset)) as u32 // rotate right by offset.
@@ -120,12 +123,14 @@ impl ArmCpu {
 	/// all of the correct data ends up on the correct data bus (basically byte)
 	pub fn mread16_signed_al(&self, address: u32) -> u32 {
 		// # TODO alignment shouldn't be necessary on these so I should remove it.(maybe)
+		self.memory.on_read(address, 2);
 		((self.memory.read16(address) as i16) as i32) as u32
 	}
 
 	/// Reads an unsigned 32 bit value from memory and makes sure that
 	/// all of the correct data ends up on the correct data bus (basically byte)
 	pub fn mread32_al(&self, address: u32) -> u32 {
+		self.memory.on_read(address & 0xFFFFFFFC, 4);
 		let data = self.memory.read32(address & 0xFFFFFFFC); // make sure what we retrieve is word aligned.
 		let offset = (address & 0x3) * 8; // offset from the word boundary in bits.
 		(data << (32 - offset)) | (data >> offset) // rotate right by offset.
@@ -7,25 +7,51 @@ pub const FIFO_OUT_BUFFER_SIZE: usize = 0x4000;
 pub const FIFO_OUT_BUFFER_MASK: usize = 0x3fff;
 
 pub struct FifoOutArray {
-	pub data: [i8; FIFO_OUT_BUFFER_SIZE]	
+	pub data: [i8; FIFO_OUT_BUFFER_SIZE],
+	pub cycles: [u64; FIFO_OUT_BUFFER_SIZE],
 }
 
 impl Default for FifoOutArray {
 	fn default() -> FifoOutArray {
 		FifoOutArray {
-			data: [0i8; FIFO_OUT_BUFFER_SIZE]
+			data: [0i8; FIFO_OUT_BUFFER_SIZE],
+			cycles: [0u64; FIFO_OUT_BUFFER_SIZE],
 		}
 	}
 }
 
 impl Decodable for FifoOutArray {
-	fn decode<D: Decoder>(_: &mut D) -> Result<FifoOutArray, D::Error> {
-		Ok(Default::default())
+	fn decode<D: Decoder>(d: &mut D) -> Result<FifoOutArray, D::Error> {
+		d.read_seq(|d, len| {
+			if len != FIFO_OUT_BUFFER_SIZE {
+				return Err(d.error("FIFO output buffer has the wrong size."))
+			}
+			let mut array: FifoOutArray = Default::default();
+			for index in 0..len {
+				let entry: Result<(i8, u64), D::Error> = d.read_seq_elt(index, |d| Decodable::decode(d));
+				match entry {
+					Ok((sample, cycle)) => {
+						array.data[index] = sample;
+						array.cycles[index] = cycle;
+					},
+					Err(e) => return Err(e),
+				}
+			}
+			Ok(array)
+		})
 	}
 }
 
 impl Encodable for FifoOutArray {
-	fn encode<S: Encoder>(&self, _: &mut S) -> Result<(), S::Error> {
-		Ok(())
+	fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+		s.emit_seq(FIFO_OUT_BUFFER_SIZE, |s| {
+			for index in 0..FIFO_OUT_BUFFER_SIZE {
+				let entry = (self.data[index], self.cycles[index]);
+				if let Err(e) = s.emit_seq_elt(index, |s| entry.encode(s)) {
+					return Err(e)
+				}
+			}
+			Ok(())
+		})
 	}
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use super::compat::*;
 pub use super::*;
 
@@ -15,6 +16,7 @@ pub struct IORegister32(pub usize);
 pub const POSTFLG: IORegister8 = IORegister8(0x0000300);
 pub const HALTCNT: IORegister8 = IORegister8(0x0000301);
 pub const DISPCNT: IORegister16 = IORegister16(0x0000000);
+pub const GREENSWAP: IORegister16 = IORegister16(0x0000002);
 pub const DISPSTAT: IORegister16 = IORegister16(0x0000004);
 pub const VCOUNT: IORegister16 = IORegister16(0x0000006);
 pub const BG0CNT: IORegister16 = IORegister16(0x0000008);
@@ -95,6 +97,7 @@ pub const SIOMULTI2: IORegister16 = IORegister16(0x0000124);
 pub const SIOMULTI3: IORegister16 = IORegister16(0x0000126);
 pub const SIOCNT: IORegister16 = IORegister16(0x0000128);
 pub const SIOMLT_SEND: IORegister16 = IORegister16(0x000012a);
+pub const SIODATA8: IORegister16 = IORegister16(0x000012a);
 pub const KEYINPUT: IORegister16 = IORegister16(0x0000130);
 pub const KEYCNT: IORegister16 = IORegister16(0x0000132);
 pub const RCNT: IORegister16 = IORegister16(0x0000134);
@@ -231,9 +234,7 @@ pub struct TimerInternalReg {
 
 #[derive(Default, RustcEncodable, RustcDecodable)]
 pub struct GbaChannel1 {
-	pub freq_len: f32,
-	pub freq_len_duty: f32,
-
+	/// Sweep steps (128Hz) since the frequency last changed.
 	pub sweep_time_acc: u32,
 
 	// 4000060h - SOUND1CNT_L (NR10) - Channel 1 Sweep register (R/W)
@@ -265,26 +266,29 @@ pub struct GbaChannel1 {
 	/// This is the volume that's actually changing through the
 	/// envelope function.
 	pub current_volume: u16,
+
+	/// Envelope steps (64Hz) since the volume last changed.
 	pub envelope_time_acc: u32,
 
-	pub sound_length_time_acc: u32,
+	/// Length steps (256Hz) left until the sound stops if the length flag is set.
+	pub length_counter: u32,
 
 	// 4000064h - SOUND1CNT_X (NR13, NR14) - Channel 1 Frequency/Control (R/W)
 	pub frequency: u16,            // 0-10  W    Frequency; 131072/(2048-n)Hz  (0-2047)
 	pub length_flag: bool,         // 14    R/W  Length Flag  (1=Stop output when length in NR11 expires)
 	pub initial: bool,             // 15    W    Initial      (1=Restart Sound)
 
-	pub frequency_f: f32,
-	pub frequency_step: f32,
+	/// Cycles until the next step through the duty pattern.
+	pub frequency_timer: u32,
+
+	/// Position in the 8 step duty pattern.
+	pub duty_step: u16,
 
 	pub playing: bool
 }
 
 #[derive(Default, RustcEncodable, RustcDecodable)]
 pub struct GbaChannel2 {
-	pub freq_len: f32,
-	pub freq_len_duty: f32,
-
 	// 4000062h - SOUND1CNT_H (NR11, NR12) - Channel 1 Duty/Len/Envelope (R/W)
 	pub sound_length: u16,        // 0-5   W    Sound length; units of (64-n)/256s  (0-63)
 	pub wave_pattern_duty: u16,   // 6-7   R/W  Wave Pattern Duty                   (0-3, see below)
@@ -301,17 +305,23 @@ pub struct GbaChannel2 {
 	/// This is the volume that's actually changing through the
 	/// envelope function.
 	pub current_volume: u16,
+
+	/// Envelope steps (64Hz) since the volume last changed.
 	pub envelope_time_acc: u32,
 
-	pub sound_length_time_acc: u32,
+	/// Length steps (256Hz) left until the sound stops if the length flag is set.
+	pub length_counter: u32,
 
 	// 4000064h - SOUND1CNT_X (NR13, NR14) - Channel 1 Frequency/Control (R/W)
 	pub frequency: u16,            // 0-10  W    Frequency; 131072/(2048-n)Hz  (0-2047)
 	pub length_flag: bool,         // 14    R/W  Length Flag  (1=Stop output when length in NR11 expires)
 	pub initial: bool,             // 15    W    Initial      (1=Restart Sound)
 
-	pub frequency_f: f32,
-	pub frequency_step: f32,
+	/// Cycles until the next step through the duty pattern.
+	pub frequency_timer: u32,
+
+	/// Position in the 8 step duty pattern.
+	pub duty_step: u16,
 
 	pub playing: bool
 }
@@ -345,13 +355,13 @@ pub struct GbaChannel3 {
 	//   F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0  1048576/(2048-n) Hz
 
 	pub wav_ram: [[u16; 8]; 2],
-	pub sound_length_time_acc: u32,
-	pub current_wav_index: usize,
 
-	pub freq_inc: f32,
-	pub freq_acc: f32,
+	/// Length steps (256Hz) left until the sound stops if the length flag is set.
+	pub length_counter: u32,
+	pub current_wav_index: usize,
 
-	pub c3_volume_multiplier: f32,
+	/// Cycles until the next digit in wave RAM is played.
+	pub frequency_timer: u32,
 
 	pub playing: bool,
 }
@@ -372,8 +382,12 @@ pub struct GbaChannel4 {
 	pub initial: bool,             // 15    W    Initial      (1=Restart Sound)
 
 	pub current_volume: u16,
+
+	/// Envelope steps (64Hz) since the volume last changed.
 	pub envelope_time_acc: u32,
-	pub sound_length_time_acc: u32,
+
+	/// Length steps (256Hz) left until the sound stops if the length flag is set.
+	pub length_counter: u32,
 
 	// Noise Random Generator (aka Polynomial Counter)
 	// Noise randomly switches between HIGH and LOW levels, the output levels are calculated by a shift register (X), at the selected frequency, as such:
@@ -384,9 +398,8 @@ pub struct GbaChannel4 {
 	pub lfsr_mask: u16,
 	pub lfsr_xor: u16,
 
-	pub freq_inc: f32,
-	pub freq_acc: f32,
-	pub intermediate_freq: f32,
+	/// Cycles until the shift register is clocked again.
+	pub frequency_timer: u32,
 
 	pub playing: bool,
 }
@@ -397,13 +410,12 @@ pub struct GbaChannelFIFO {
 	pub enable_right: bool,
 	pub enable_left: bool,
 
+	/// The sample that is currently being played.
 	pub sample: i8,
 
-	pub frequency: f32,
-	pub freq_acc: f32,
-	pub freq_inc: f32,
-
-	// #TODO do I really want to store this on save?
+	/// Samples that were popped at timer overflows along with the cycle they were popped at,
+	/// waiting for the APU to catch up to them. These are saved too, the APU runs behind
+	/// the CPU so there's usually something in here when a state is saved.
 	out_data: FifoOutArray,
 	out_write_cursor: usize,
 	out_read_cursor: usize,
@@ -424,33 +436,28 @@ impl GbaChannelFIFO {
 		self.write_cursor = 0;
 		self.read_cursor = 0;
 		self.size = 0;
-		self.freq_acc = 0.0;
 	}
 
-	pub fn out_push(&mut self, sample: i8) {
+	/// Queues a sample that started playing at `cycle`.
+	pub fn out_push(&mut self, sample: i8, cycle: u64) {
 		if self.out_write_cursor == self.out_read_cursor && self.out_remaining() > 0 {
 			self.out_read_cursor = (self.out_write_cursor + 1) & FIFO_OUT_BUFFER_MASK;
 		}
 		self.out_data.data[self.out_write_cursor] = sample;
+		self.out_data.cycles[self.out_write_cursor] = cycle;
 		self.out_write_cursor = (self.out_write_cursor + 1) & FIFO_OUT_BUFFER_MASK;
 		self.out_size = min!(self.out_size + 1, FIFO_OUT_BUFFER_SIZE);
 	}
 
-	pub fn out_pop(&mut self) -> i8 {
-		if self.out_remaining() > 0 {
-			let sample = self.out_data.data[self.out_read_cursor];
+	/// Returns the sample that is playing at `cycle`,
+	/// dropping anything queued before that.
+	pub fn sample_at(&mut self, cycle: u64) -> i8 {
+		while self.out_remaining() > 0 && self.out_data.cycles[self.out_read_cursor] <= cycle {
+			self.sample = self.out_data.data[self.out_read_cursor];
 			self.out_read_cursor = (self.out_read_cursor + 1) & FIFO_OUT_BUFFER_MASK;
 			self.out_size -= 1;
-			return sample;
-		} else {
-			return 0;
 		}
-	}
-
-	pub fn next_sample(&mut self) -> i8 {
-		let sample = self.out_pop();
-		self.sample = sample;
-		return sample;
+		return self.sample;
 	}
 
 	pub fn out_remaining(&self) -> usize {
@@ -494,14 +501,77 @@ impl GbaChannelFIFO {
 	}
 }
 
+/// Keeps track of where the APU is in emulated time.
+#[derive(Default, RustcEncodable, RustcDecodable)]
+pub struct GbaAudioSequencer {
+	/// The cycle that the APU has caught up to.
+	pub cycle: u64,
+
+	/// Cycles until the frame sequencer is clocked again (512Hz).
+	pub sequencer_timer: u32,
+
+	/// The current step of the frame sequencer (0-7).
+	///   Length is clocked on even steps (256Hz),
+	///   sweep on steps 2 and 6 (128Hz)
+	///   and envelopes on step 7 (64Hz).
+	pub sequencer_step: u16,
+
+	/// Cycles until the next sample is output at the rate selected in SOUNDBIAS.
+	pub sample_timer: u32,
+}
+
+#[derive(Default, RustcEncodable, RustcDecodable)]
+pub struct SerialInternalReg {
+	/// Set whenever RCNT, SIOCNT or the send data register is written to.
+	pub dirty: bool,
+
+	/// True while a transfer is in progress (SIOCNT bit 7).
+	pub busy: bool,
+
+	/// The cycle at which the current transfer will complete.
+	pub transfer_end: u64,
+
+	/// Multi-player ID assigned by the last transfer (0=Parent, 1-3=1st-3rd child).
+	pub multiplayer_id: u16,
+
+	/// Set if the last transfer failed.
+	pub error: bool,
+
+	/// JOYCNT bits 0-2, set by JOY Bus commands and cleared by writing 1 to them.
+	pub joycnt_flags: u16,
+
+	/// JOY_STAT bits 1 and 3, bit 1 is cleared when the game reads JOY_RECV.
+	pub joystat_flags: Cell<u16>,
+
+	/// UART send FIFO, only the first byte is used when the FIFO is disabled.
+	pub uart_send: Vec<u8>,
+
+	/// UART receive FIFO, the first byte is visible in SIODATA8.
+	pub uart_receive: Vec<u8>,
+
+	/// The cycle at which the UART finishes sending the first byte in `uart_send`.
+	pub uart_send_end: u64,
+
+	/// Set when the game writes to SIODATA8.
+	pub uart_data_written: bool,
+
+	/// Set when the game reads from SIODATA8.
+	pub uart_data_read: Cell<bool>,
+}
+
 // Internal IO registers.
 #[derive(Default, RustcEncodable, RustcDecodable)]
 pub struct InternalRegisters {
+	/// The reference points used while drawing, these advance by PB/PD after every line.
 	pub bg2x: u32,
 	pub bg2y: u32,
 	pub bg3x: u32,
 	pub bg3y: u32,
 
+	/// BG2X, BG2Y, BG3X and BG3Y as they were last written by the game.
+	/// The internal reference points are reloaded from these at VBlank.
+	pub bg_ref_io: [u32; 4],
+
 	pub halted: bool,
 	pub stopped: bool,
 
@@ -509,12 +579,15 @@ pub struct InternalRegisters {
 	pub dma_registers: [DMAInternalReg; 4],
 	pub timers: [TimerInternalReg; 4],
 
+	pub serial: SerialInternalReg,
+
 	pub audio_channel1: GbaChannel1,
 	pub audio_channel2: GbaChannel2,
 	pub audio_channel3: GbaChannel3,
 	pub audio_channel4: GbaChannel4,
 	pub audio_fifo_a: GbaChannelFIFO,
 	pub audio_fifo_b: GbaChannelFIFO,
+	pub audio_sequencer: GbaAudioSequencer,
 }
 
 impl InternalRegisters {
@@ -543,16 +616,52 @@ impl InternalRegisters {
 		self.on_reg_write((address & 0x3ff) + 2, ((value >> 16) & 0xFFFF) as u16);
 	}
 
-	pub fn on_frame_end(&mut self, iodata: &[u8]) {
-		// refresh BG2X, BG2Y, BG3X, BG3Y
-		self.on_reg_write(0x0000028, iodata.direct_read16(0x0000028));
-		self.on_reg_write(0x000002A, iodata.direct_read16(0x000002A));
-		self.on_reg_write(0x000002C, iodata.direct_read16(0x000002C));
-		self.on_reg_write(0x000002E, iodata.direct_read16(0x000002E));
-		self.on_reg_write(0x0000038, iodata.direct_read16(0x0000038));
-		self.on_reg_write(0x000003A, iodata.direct_read16(0x000003A));
-		self.on_reg_write(0x000003C, iodata.direct_read16(0x000003C));
-		self.on_reg_write(0x000003E, iodata.direct_read16(0x000003E));
+	/// Reloads the internal BG2X, BG2Y, BG3X, BG3Y reference points.
+	pub fn on_vblank(&mut self) {
+		for index in 0..4 {
+			self.latch_bg_ref(index);
+		}
+	}
+
+	/// The internal reference points BG2X, BG2Y, BG3X and BG3Y.
+	pub fn bg_refs(&self) -> [u32; 4] {
+		[self.bg2x, self.bg2y, self.bg3x, self.bg3y]
+	}
+
+	pub fn set_bg_refs(&mut self, refs: [u32; 4]) {
+		self.bg2x = refs[0];
+		self.bg2y = refs[1];
+		self.bg3x = refs[2];
+		self.bg3y = refs[3];
+	}
+
+	/// Moves the internal reference points to the start of the next line.
+	pub fn advance_bg_refs(&mut self, bg2_dmx: u16, bg2_dmy: u16, bg3_dmx: u16, bg3_dmy: u16) {
+		// sign extension from 16bits to 32bits
+		self.bg2x = self.bg2x.wrapping_add((bg2_dmx as i16) as i32 as u32);
+		self.bg2y = self.bg2y.wrapping_add((bg2_dmy as i16) as i32 as u32);
+		self.bg3x = self.bg3x.wrapping_add((bg3_dmx as i16) as i32 as u32);
+		self.bg3y = self.bg3y.wrapping_add((bg3_dmy as i16) as i32 as u32);
+	}
+
+	/// Writing to either half of a reference point register reloads the whole internal register.
+	fn write_bg_ref(&mut self, index: usize, hi: bool, value: u16) {
+		self.bg_ref_io[index] = if hi {
+			put_hi16!(self.bg_ref_io[index], value)
+		} else {
+			put_lo16!(self.bg_ref_io[index], value)
+		};
+		self.latch_bg_ref(index);
+	}
+
+	fn latch_bg_ref(&mut self, index: usize) {
+		let value = (((self.bg_ref_io[index] << 4) as i32) >> 4) as u32; // sign extension from 28bits to 32bits
+		match index {
+			0 => self.bg2x = value,
+			1 => self.bg2y = value,
+			2 => self.bg3x = value,
+			_ => self.bg3y = value,
+		}
 	}
 
 	pub fn on_reg_write8(&mut self, register: u32, value: u8) {
@@ -576,17 +685,17 @@ impl InternalRegisters {
 	pub fn on_reg_write(&mut self, register: u32, value: u16) {
 		match register {
 			// Gfx:
-			0x00000028 => { self.bg2x = (((put_lo16!(self.bg2x, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
-			0x0000002A => { self.bg2x = (((put_hi16!(self.bg2x, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
+			0x00000028 => { self.write_bg_ref(0, false, value) },
+			0x0000002A => { self.write_bg_ref(0, true, value) },
 
-			0x0000002C => { self.bg2y = (((put_lo16!(self.bg2y, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
-			0x0000002E => { self.bg2y = (((put_hi16!(self.bg2y, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
+			0x0000002C => { self.write_bg_ref(1, false, value) },
+			0x0000002E => { self.write_bg_ref(1, true, value) },
 
-			0x00000038 => { self.bg3x = (((put_lo16!(self.bg3x, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
-			0x0000003A => { self.bg3x = (((put_hi16!(self.bg3x, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
+			0x00000038 => { self.write_bg_ref(2, false, value) },
+			0x0000003A => { self.write_bg_ref(2, true, value) },
 
-			0x0000003C => { self.bg3y = (((put_lo16!(self.bg3y, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
-			0x0000003E => { self.bg3y = (((put_hi16!(self.bg3y, value) << 4) as i32) >> 4) as u32 }, // sign extension from 28bits to 32bits
+			0x0000003C => { self.write_bg_ref(3, false, value) },
+			0x0000003E => { self.write_bg_ref(3, true, value) },
 
 			// Timers:
 			0x00000100 => { self.update_timer_lo(0, value) },
@@ -604,6 +713,20 @@ impl InternalRegisters {
 			0x000000D2 => { self.update_dma_hi(2, value); },
 			0x000000DE => { self.update_dma_hi(3, value); },
 
+			// Serial:
+			0x0000012A => { self.serial.uart_data_written = true; self.serial.dirty = true; },
+			0x00000128 | 0x00000134 => { self.serial.dirty = true; },
+			0x00000140 => {
+				self.serial.joycnt_flags &= !(value & 0x7);
+				self.serial.dirty = true;
+			},
+			0x00000154 | 0x00000156 => {
+				let flags = self.serial.joystat_flags.get();
+				self.serial.joystat_flags.set(flags | 0x8);
+				self.serial.dirty = true;
+			},
+			0x00000158 => { self.serial.dirty = true; },
+
 			// Audio Channel 1:
 			0x00000060 => {
 				self.audio_channel1.sweep_shift_number = value & 0x7;
@@ -612,6 +735,7 @@ impl InternalRegisters {
 			},
 			0x00000062 => {
 				self.audio_channel1.sound_length = value & 0x3f;
+				self.audio_channel1.length_counter = 64 - self.audio_channel1.sound_length as u32;
 				self.audio_channel1.wave_pattern_duty = (value >> 6) & 0x3;
 				self.audio_channel1.envelope_step_time = (value >> 8) & 0x7;
 				self.audio_channel1.envelope_inc = (value & 0x800) != 0;
@@ -619,14 +743,14 @@ impl InternalRegisters {
 			},
 			0x00000064 => {
 				self.audio_channel1.frequency = value & 0x7ff;
-				self.audio_channel1.frequency_f = 131072.0 / (2048.0 - self.audio_channel1.frequency as f32);
 				self.audio_channel1.length_flag = (value & 0x4000) != 0;
-				self.audio_channel1.initial = (value & 0x8000) != 0;
+				if (value & 0x8000) != 0 { self.audio_channel1.initial = true; }
 			},
 
 			// Audio Channel 2:
 			0x0000068 => {
 				self.audio_channel2.sound_length = value & 0x3f;
+				self.audio_channel2.length_counter = 64 - self.audio_channel2.sound_length as u32;
 				self.audio_channel2.wave_pattern_duty = (value >> 6) & 0x3;
 				self.audio_channel2.envelope_step_time = (value >> 8) & 0x7;
 				self.audio_channel2.envelope_inc = (value & 0x800) != 0;
@@ -634,9 +758,8 @@ impl InternalRegisters {
 			},
 			0x000006C => {
 				self.audio_channel2.frequency = value & 0x7ff;
-				self.audio_channel2.frequency_f = 131072.0 / (2048.0 - self.audio_channel2.frequency as f32);
 				self.audio_channel2.length_flag = (value & 0x4000) != 0;
-				self.audio_channel2.initial = (value & 0x8000) != 0;
+				if (value & 0x8000) != 0 { self.audio_channel2.initial = true; }
 			},
 
 			// Audio Channel 3:
@@ -647,13 +770,14 @@ impl InternalRegisters {
 			},
 			0x00000072 => {
 				self.audio_channel3.sound_length = value & 0xff;
+				self.audio_channel3.length_counter = 256 - self.audio_channel3.sound_length as u32;
 				self.audio_channel3.sound_volume = (value >> 13) & 0x3;
 				self.audio_channel3.force_volume = (value & 0x8000) != 0;
 			},
 			0x00000074 => {
 				self.audio_channel3.sample_rate = value & 0x7ff;
 				self.audio_channel3.length_flag = (value & 0x4000) != 0;
-				self.audio_channel3.initial = (value & 0x8000) != 0;
+				if (value & 0x8000) != 0 { self.audio_channel3.initial = true; }
 			},
 			0x00000090 ... 0x0000009E => { // Writing to Wave RAM
 				let bank = (self.audio_channel3.wav_ram_bank ^ 1) as usize;
@@ -663,6 +787,7 @@ impl InternalRegisters {
 			// Audio Channel 4:
 			0x00000078 => {
 				self.audio_channel4.sound_length = value & 0x3f;
+				self.audio_channel4.length_counter = 64 - self.audio_channel4.sound_length as u32;
 				self.audio_channel4.envelope_step_time = (value >> 8) & 0x7;
 				self.audio_channel4.envelope_inc = (value & 0x800) != 0;
 				self.audio_channel4.initial_volume = (value >> 12) & 0xf;
@@ -672,7 +797,7 @@ impl InternalRegisters {
 				self.audio_channel4.counter_width_7 = (value & 0x8) != 0;
 				self.audio_channel4.shift_clock_freq = (value >> 4) & 0xf;
 				self.audio_channel4.length_flag = (value & 0x4000) != 0;
-				self.audio_channel4.initial = (value & 0x8000) != 0;
+				if (value & 0x8000) != 0 { self.audio_channel4.initial = true; }
 			},
 
 			// FIFO A:
@@ -694,7 +819,6 @@ impl InternalRegisters {
 					console_log!("Reset FIFO A.");
 					self.audio_fifo_a.reset();
 				}
-				self.update_fifo_a_frequency(((value >> 10) & 1) as usize);
 
 				self.audio_fifo_b.enable_right = (value & 0x1000) != 0;
 				self.audio_fifo_b.enable_left = (value & 0x2000) != 0;
@@ -703,7 +827,6 @@ impl InternalRegisters {
 					console_log!("Reset FIFO B.");
 					self.audio_fifo_b.reset();
 				}
-				self.update_fifo_b_frequency(((value >> 14) & 1) as usize);
 			},
 
 			// #TODO when bit 7 of 4000084h - SOUNDCNT_X (NR52) is cleared,
@@ -757,45 +880,28 @@ impl InternalRegisters {
 	}
 
 	fn update_timer_hi(&mut self, t_idx: usize, hi_data: u16) {
-		{
-			let timer = &mut self.timers[t_idx];
-			timer.prescaler = match hi_data & 0x3 {
-				0 => 0,  // 1
-				1 => 6,  // 64
-				2 => 8,  // 256
-				3 => 10, // 1024
-				_ => unreachable!()
-			};
-
-			timer.count_up = ((hi_data >> 2) & 1) == 1;
-			timer.irq_enabled = ((hi_data >> 6) & 1) == 1;
-			timer.operate = ((hi_data >> 7) & 1) == 1;
-
-			// #FIXME not sure if this is suppose to happen if we enable
-			//        an already enabled timer.
-			timer.counter = timer.reload;
-			timer.unscaled_counter = 0;
-		}
-
-		if t_idx == self.audio_fifo_a.timer as usize {
-			self.update_fifo_a_frequency(t_idx);
-		}
-
-		if t_idx == self.audio_fifo_b.timer as usize {
-			self.update_fifo_b_frequency(t_idx);
-		}
-	}
+		let timer = &mut self.timers[t_idx];
+		timer.prescaler = match hi_data & 0x3 {
+			0 => 0,  // 1
+			1 => 6,  // 64
+			2 => 8,  // 256
+			3 => 10, // 1024
+			_ => unreachable!()
+		};
 
-	fn update_fifo_a_frequency(&mut self, timer: usize) {
-		self.audio_fifo_a.frequency = self.get_timer_frequency(timer);
-	}
+		timer.count_up = ((hi_data >> 2) & 1) == 1;
+		timer.irq_enabled = ((hi_data >> 6) & 1) == 1;
+		timer.operate = ((hi_data >> 7) & 1) == 1;
 
-	fn update_fifo_b_frequency(&mut self, timer: usize) {
-		self.audio_fifo_b.frequency = self.get_timer_frequency(timer);
+		// #FIXME not sure if this is suppose to happen if we enable
+		//        an already enabled timer.
+		timer.counter = timer.reload;
+		timer.unscaled_counter = 0;
 	}
 
-	fn get_timer_frequency(&mut self, timer: usize) -> f32 {
-		((16777216 >> self.timers[timer].prescaler) as f32) / max!(1.0, 65535.0 - self.timers[timer].reload as f32)
+	/// The rate DirectSound samples are played at with the given timer.
+	pub fn get_timer_frequency(&self, timer: usize) -> f32 {
+		((16777216 >> self.timers[timer].prescaler) as f32) / max!(1.0, 65536.0 - self.timers[timer].reload as f32)
 	}
 
 	// pub fn increment_timers(&mut self, amt: u32) -> u16 {
@@ -1,4 +1,4 @@
-mod compat;
+pub mod compat;
 pub mod ioreg;
 
 // #TODO reduce the calls to map.
@@ -9,6 +9,7 @@ use self::ioreg::IORegister8;
 use self::ioreg::IORegister16;
 use self::ioreg::IORegister32;
 use self::ioreg::InternalRegisters;
+use std::cell::Cell;
 
 pub struct MemoryRegion {
 	pub start: u32,
@@ -36,11 +37,33 @@ pub const MEM_ROM1:		MemoryRegion = MemoryRegion { start: 0x0a000000, end: 0x0Bf
 pub const MEM_ROM2:		MemoryRegion = MemoryRegion { start: 0x0c000000, end: 0x0Dffffff, size: 0x2000000, local_addr: 0 };
 pub const MEM_SRAM:		MemoryRegion = MemoryRegion { start: 0x0e000000, end: 0x0E00ffff, size: 0x10000, local_addr: 0 };
 
+/// The end (exclusive) of the IO registers that the LCD reads while drawing a line.
+const DISPLAY_IOREG_END: usize = 0x56;
+
 pub struct GbaMemory {
 	pub internal_data: [u8; INTERNAL_MEM_SIZE],
 	pub internal_regs: InternalRegisters,
-	pub rom: Vec<u8>
+	pub rom: Vec<u8>,
 	// #TODO add SRAM.
+
+	/// Set while the LCD is drawing a line so that writes which
+	/// change what the rest of the line looks like are recorded.
+	pub track_display_writes: bool,
+
+	/// (local address, old value, new value) for every byte written to
+	/// the display registers, palette RAM or the visible parts of VRAM while tracking.
+	pub display_writes: Vec<(usize, u8, u8)>,
+
+	/// The parts of VRAM (offsets from the start of VRAM) that the rest of the
+	/// line is drawn from, see `GbaLcd::visible_vram`.
+	pub display_vram: [(usize, usize); 2],
+
+	/// The internal reference points from before the first recorded display write.
+	/// Writing to BG2X-BG3Y reloads them right away so they're undone along with the bytes.
+	display_refs: [u32; 4],
+
+	/// Set whenever the CPU reads KEYINPUT, movies use this to find lag frames.
+	pub keyinput_read: Cell<bool>,
 }
 
 impl GbaMemory {
@@ -48,10 +71,59 @@ impl GbaMemory {
 		GbaMemory {
 			internal_data: [0u8; INTERNAL_MEM_SIZE],
 			internal_regs: InternalRegisters::new(),
-			rom: vec![]
+			rom: vec![],
+			track_display_writes: false,
+			display_writes: Vec::new(),
+			display_vram: [(0, 0), (0, 0)],
+			display_refs: [0; 4],
+			keyinput_read: Cell::new(false),
 		}
 	}
 
+	/// Puts back the values that were there before the recorded display writes.
+	pub fn undo_display_writes(&mut self) {
+		for &(local_addr, old, _) in self.display_writes.iter().rev() {
+			self.internal_data[local_addr] = old;
+		}
+		self.swap_display_refs();
+	}
+
+	/// Applies the recorded display writes again after `undo_display_writes`.
+	pub fn redo_display_writes(&mut self) {
+		for &(local_addr, _, new) in self.display_writes.iter() {
+			self.internal_data[local_addr] = new;
+		}
+		self.swap_display_refs();
+	}
+
+	fn swap_display_refs(&mut self) {
+		let refs = self.internal_regs.bg_refs();
+		self.internal_regs.set_bg_refs(self.display_refs);
+		self.display_refs = refs;
+	}
+
+	/// Records a write while tracking display writes. Writes to palette RAM and VRAM
+	/// that don't change anything can't be seen, IO writes can have side effects
+	/// (like reloading the reference points) so those are always recorded.
+	#[inline]
+	fn track_display_write(&mut self, local_addr: usize, value: u8) {
+		let old = self.internal_data[local_addr];
+		if !self.is_display_addr(local_addr) || (old == value && local_addr >= MEM_PAL.local_addr) { return }
+		if self.display_writes.is_empty() {
+			self.display_refs = self.internal_regs.bg_refs();
+		}
+		self.display_writes.push((local_addr, old, value));
+	}
+
+	#[inline]
+	fn is_display_addr(&self, local_addr: usize) -> bool {
+		if local_addr >= MEM_IOREG.local_addr && local_addr < (MEM_IOREG.local_addr + DISPLAY_IOREG_END) { return true }
+		if local_addr >= MEM_PAL.local_addr && local_addr < (MEM_PAL.local_addr + MEM_PAL.size) { return true }
+		if local_addr < MEM_VRAM.local_addr || local_addr >= (MEM_VRAM.local_addr + MEM_VRAM.size) { return false }
+		let offset = local_addr - MEM_VRAM.local_addr;
+		self.display_vram.iter().any(|&(start, end)| offset >= start && offset < end)
+	}
+
 	// #TODO handle rom.
 	pub fn get_region_mut(&mut self, region: MemoryRegion) -> &mut [u8] {
 		&mut self.internal_data[region.local_addr..(region.local_addr+region.size)]
@@ -156,6 +228,31 @@ impl GbaMemory {
 		self.__read8__(address)
 	}
 
+	/// Side effects of the CPU loading `size` bytes from `address`. Only the CPU's
+	/// loads come through here so that the debugger and the disassembler can read
+	/// any address without popping the UART receive FIFO, acknowledging JOY_RECV
+	/// or making a lag frame look like it read the buttons.
+	pub fn on_read(&self, address: u32, size: u32) {
+		if address > 0x04000153 || address + size <= 0x0400012A { return }
+		let end = address + size;
+		let serial = &self.internal_regs.serial;
+
+		// SIODATA8
+		if address <= 0x0400012A && end > 0x0400012A {
+			serial.uart_data_read.set(true);
+		}
+
+		// KEYINPUT
+		if address <= 0x04000131 && end > 0x04000130 {
+			self.keyinput_read.set(true);
+		}
+
+		// JOY_RECV, the Receive Status Flag in JOY_STAT goes back to 0.
+		if address <= 0x04000153 && end > 0x04000150 {
+			serial.joystat_flags.set(serial.joystat_flags.get() & !0x2);
+		}
+	}
+
 	pub fn write8(&mut self, address: u32, value: u8) {
 		self.__write8__(address, value);
 		if address >= 0x04000000 && address <= 0x4000803 {
@@ -233,6 +330,9 @@ impl GbaMemory {
 			_ => {
 				let (local_addr, writeable) = self.map(address);
 				if writeable {
+					if self.track_display_writes {
+						self.track_display_write(local_addr, value);
+					}
 					match address {
 						0x4000202 | 0x4000203 => self.internal_data[local_addr] &= !value,
 						_ => self.internal_data[local_addr] = value
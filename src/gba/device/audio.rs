@@ -1,57 +1,446 @@
+//! Sends the emulator's audio somewhere. That's usually the sound card through PortAudio,
+//! but it can also be nowhere, a WAV file, or an application that embeds Pyrite
+//! and pulls the samples out itself (see `AudioPull`).
+
 use portaudio;
 use std::thread;
 use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::mem;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
 use ::util::async_ring_buffer::AsyncRingBuffer;
+use ::util::wav::WavWriter;
+use ::util::io::create_parent_dir;
+use super::resampler::{Resampler, ResamplingQuality};
+use super::audio_dump::{StemFrame, STEM_COUNT};
+use super::audio_filter::{OutputFilter, AudioOutputFilter};
+use super::screenshot::timestamped_path;
+use super::time_stretch::TimeStretcher;
 // use std::f64::consts::PI;
 
 const CHANNELS: i32 = 2;
 const SAMPLE_RATE: f64 = 44_100.0;
 const FRAMES_PER_BUFFER: u32 = 256;
-const AUDIO_DATA_BUFFER_SIZE: usize = FRAMES_PER_BUFFER as usize;
-const AUDIO_DATA_BUFFER_COUNT: usize = 8;
+pub const AUDIO_DATA_BUFFER_SIZE: usize = FRAMES_PER_BUFFER as usize;
+
+/// The most the output rate is allowed to drift from the real one to keep the
+/// ring buffer half full. At 0.5% the pitch change can't be heard.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// How many video frames it takes the measured buffer fill to mostly catch up with
+/// the real one (about half a second), this smooths out the jumps from the output
+/// reading a whole buffer at a time.
+const FILL_SMOOTHING_FRAMES: f32 = 30.0;
+
+pub const DEFAULT_TARGET_LATENCY: u32 = 32;
 
 // Size of audio data buffer in bytes is around:
-// AUDIO_DATA_BUFFER_SIZE * AUDIO_DATA_BUFFER_COUNT * 8
+// AUDIO_DATA_BUFFER_SIZE * buffer count * 4
 
 pub type AudioBufferType = [(i16, i16); AUDIO_DATA_BUFFER_SIZE as usize];
 
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AudioBackend {
+	PortAudio,
+	/// Throws all of the audio away.
+	Null,
+	/// Writes the audio to a new file in the audio dump directory.
+	Wav,
+}
+
+pub const AUDIO_BACKENDS: [AudioBackend; 3] = [
+	AudioBackend::PortAudio,
+	AudioBackend::Null,
+	AudioBackend::Wav,
+];
+
+impl AudioBackend {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			AudioBackend::PortAudio => "PortAudio",
+			AudioBackend::Null => "None",
+			AudioBackend::Wav => "WAV File",
+		}
+	}
+}
+
+/// An output that `AudioDevice::start` can start.
+pub enum AudioOutput {
+	PortAudio,
+	Null,
+	Wav(PathBuf),
+	/// Samples wait in the ring buffer until they are read through an `AudioPull`.
+	Callback,
+}
+
+impl AudioOutput {
+	/// Parses an output description: `portaudio`, `null`, `wav` or `wav:<path>`.
+	pub fn from_description(description: &str) -> Result<AudioOutput, String> {
+		if description == "portaudio" {
+			Ok(AudioOutput::PortAudio)
+		} else if description == "null" {
+			Ok(AudioOutput::Null)
+		} else if description == "wav" {
+			Ok(AudioOutput::from_backend(AudioBackend::Wav))
+		} else if description.starts_with("wav:") {
+			Ok(AudioOutput::Wav(PathBuf::from(&description[4..])))
+		} else {
+			Err(format!("Unknown audio output `{}` (expected portaudio, null, wav or wav:<path>)", description))
+		}
+	}
+
+	pub fn from_backend(backend: AudioBackend) -> AudioOutput {
+		match backend {
+			AudioBackend::PortAudio => AudioOutput::PortAudio,
+			AudioBackend::Null => AudioOutput::Null,
+			AudioBackend::Wav => AudioOutput::Wav(timestamped_path(&::pyrite::get_settings().audio_dump_dir, "wav")),
+		}
+	}
+}
+
+/// The output that is currently running.
+enum RunningOutput {
+	Stopped,
+	PortAudio(thread::JoinHandle<()>),
+	Null,
+	Wav(WavWriter<BufWriter<File>>, PathBuf),
+	Callback,
+}
+
 pub struct AudioDevice {
 	pub ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>,
-	output_thread: Option<thread::JoinHandle<()>>,
+	output: RunningOutput,
 	pub sample_rate: u32,
-	pub sample_rate_f: f32
+	pub sample_rate_f: f32,
+
+	/// The last value of the backend setting, see `follow_backend_setting`.
+	backend_setting: AudioBackend,
+
+	/// Converts samples from the rate the APU runs at to `sample_rate`.
+	pub resampler: Resampler,
+
+	/// Runs on the mix before it gets resampled.
+	pub output_filter: OutputFilter,
+
+	/// The rate the APU is producing samples at, from SOUNDBIAS.
+	apu_rate: u32,
+
+	/// Settings that the APU uses, copied once per frame by `apply_settings`.
+	pub resampling_quality: ResamplingQuality,
+	pub output_filter_kind: AudioOutputFilter,
+	pub sound_enabled: bool,
+	pub channels_enabled: [bool; 6],
+
+	/// Set while the debugger's sound window is open, its plots only get samples then.
+	pub plotting: bool,
+
+	/// What the APU produced for recordings and dumps since its last tick.
+	/// These are kept around so that they don't have to be allocated every tick.
+	pub capture_frames: Vec<(i16, i16)>,
+	pub capture_stems: Vec<StemFrame>,
+
+	/// Samples from the live resampler that are on their way to the output.
+	live_frames: Vec<(i16, i16)>,
+
+	/// Samples at `sample_rate` that still have to be sent to the output.
+	output_queue: Vec<(i16, i16)>,
+
+	/// How fast the emulator is running compared to a GBA, the audio is stretched to match.
+	speed: f64,
+
+	/// Audio is replaced with silence while this is set (e.g. while fast-forwarding).
+	mute: bool,
+
+	stretcher: TimeStretcher,
+
+	/// Set while the stretcher has samples in it.
+	stretching: bool,
+
+	/// How much audio (in milliseconds) is kept in the ring buffer.
+	/// The ring buffer is twice this size so it's half full when things are going well.
+	pub target_latency: u32,
+
+	/// How full the ring buffer is from 0.0 to 1.0, smoothed.
+	pub buffer_fill: f32,
+
+	/// The current adjustment to the output rate, see `StreamResampler::set_rate_adjustment`.
+	pub rate_adjustment: f64,
 }
 
 impl AudioDevice {
 	pub fn new() -> AudioDevice {
-		let generator_fn = || [(0, 0); AUDIO_DATA_BUFFER_SIZE as usize];
 		AudioDevice {
-			ring_buffer: Arc::new(AsyncRingBuffer::new(AUDIO_DATA_BUFFER_COUNT, generator_fn)),
-			output_thread: None,
+			ring_buffer: Arc::new(create_ring_buffer(DEFAULT_TARGET_LATENCY, 44_100)),
+			output: RunningOutput::Stopped,
 			sample_rate: 44_100,
-			sample_rate_f: 44_100.0
+			sample_rate_f: 44_100.0,
+			backend_setting: psetting!(audio_backend),
+			resampler: Resampler::new(ResamplingQuality::Nearest, 32768, 44_100, STEM_COUNT),
+			output_filter: OutputFilter::new(AudioOutputFilter::None, 32768),
+			apu_rate: 32768,
+			resampling_quality: ResamplingQuality::Nearest,
+			output_filter_kind: AudioOutputFilter::None,
+			sound_enabled: true,
+			channels_enabled: [true; 6],
+			plotting: false,
+			capture_frames: Vec::new(),
+			capture_stems: Vec::new(),
+			live_frames: Vec::new(),
+			output_queue: Vec::new(),
+			speed: 1.0,
+			mute: false,
+			stretcher: TimeStretcher::new(),
+			stretching: false,
+			target_latency: DEFAULT_TARGET_LATENCY,
+			buffer_fill: 0.5,
+			rate_adjustment: 0.0,
+		}
+	}
+
+	/// Picks up changes to the audio settings. This is called once per frame instead
+	/// of from the APU because changing the latency or the backend restarts the output.
+	pub fn apply_settings(&mut self) {
+		self.set_target_latency(psetting!(audio_latency));
+		self.follow_backend_setting(psetting!(audio_backend));
+		self.resampling_quality = psetting!(resampling_quality);
+		self.output_filter_kind = psetting!(audio_output_filter);
+		self.sound_enabled = psetting!(sound_enabled);
+		self.channels_enabled = [
+			psetting!(channel1_enabled), psetting!(channel2_enabled),
+			psetting!(channel3_enabled), psetting!(channel4_enabled),
+			psetting!(channela_enabled), psetting!(channelb_enabled),
+		];
+		self.plotting = ::debug::debugger::get_debugger().sound_info_window_opened;
+		self.configure_processing();
+	}
+
+	/// Called by the APU when SOUNDBIAS changes its sample rate.
+	pub fn set_apu_rate(&mut self, apu_rate: u32) {
+		if self.apu_rate != apu_rate {
+			self.apu_rate = apu_rate;
+			self.configure_processing();
+		}
+	}
+
+	/// Rebuilds the resampler and the output filter if their settings or the APU's rate changed.
+	fn configure_processing(&mut self) {
+		self.resampler.configure(self.resampling_quality, self.apu_rate, self.sample_rate);
+		self.output_filter.configure(self.output_filter_kind, self.apu_rate);
+	}
+
+	/// Sends whatever the live resampler has to the output. The output only takes
+	/// whole buffers so samples wait here until there are enough of them.
+	pub fn flush_output(&mut self) {
+		while let Some(frame) = self.resampler.live.pop() {
+			self.live_frames.push(frame);
+		}
+
+		let wav_result = match self.output {
+			RunningOutput::Stopped | RunningOutput::Null => {
+				self.live_frames.clear();
+				return
+			},
+
+			// WAV files are written in emulated time so they don't need
+			// any rate control or stretching.
+			RunningOutput::Wav(ref mut writer, ref path) => {
+				let volume = master_volume();
+				for frame in self.live_frames.iter_mut() {
+					*frame = (apply_volume(frame.0, volume), apply_volume(frame.1, volume));
+				}
+				let result = match writer.write_stereo(&self.live_frames) {
+					Ok(_) => Ok(()),
+					Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+				};
+				self.live_frames.clear();
+				result
+			},
+
+			RunningOutput::PortAudio(_) | RunningOutput::Callback => {
+				if self.speed == 1.0 && !self.mute {
+					if self.stretching {
+						self.stretcher.reset();
+						self.stretching = false;
+					}
+					self.output_queue.extend_from_slice(&self.live_frames);
+				} else {
+					if self.mute {
+						for frame in self.live_frames.iter_mut() { *frame = (0, 0); }
+					}
+					self.stretcher.process(&self.live_frames, self.speed, &mut self.output_queue);
+					self.stretching = true;
+				}
+				self.live_frames.clear();
+				Ok(())
+			},
+		};
+
+		let is_wav = match self.output {
+			RunningOutput::Wav(..) => true,
+			_ => false,
+		};
+		if is_wav {
+			if let Err(e) = wav_result {
+				console_error!("{}", e);
+				self.start(AudioOutput::Null);
+			}
+			return
+		}
+
+		// If the sound card ran dry (e.g. because the emulator was paused) the buffer is
+		// filled back up to the target with silence instead of slowly getting there by
+		// stretching the sound. Applications pulling samples through a callback get
+		// only what was emulated.
+		let is_port_audio = match self.output {
+			RunningOutput::PortAudio(_) => true,
+			_ => false,
+		};
+		if is_port_audio && self.ring_buffer.len() == 0 {
+			let target = self.ring_buffer.capacity() / 2;
+			for _ in 0..target {
+				self.ring_buffer.try_write(|buffer| {
+					*buffer = [(0, 0); AUDIO_DATA_BUFFER_SIZE];
+					return true
+				});
+			}
+			self.buffer_fill = 0.5;
+		}
+
+		while self.output_queue.len() >= AUDIO_DATA_BUFFER_SIZE {
+			{
+				let queue = &self.output_queue;
+				self.ring_buffer.try_write(|buffer| {
+					buffer.copy_from_slice(&queue[..AUDIO_DATA_BUFFER_SIZE]);
+					return true
+				});
+			}
+			self.output_queue.drain(..AUDIO_DATA_BUFFER_SIZE);
+		}
+	}
+
+	/// Stretches the audio that goes to the sound card so it plays `speed` times
+	/// as fast, or replaces it with silence if `mute` is set.
+	pub fn set_speed(&mut self, speed: f64, mute: bool) {
+		self.speed = speed;
+		self.mute = mute;
+	}
+
+	/// Nudges the output rate up while the ring buffer is less than half
+	/// full and down while it's more than half full. Called once per video frame.
+	pub fn update_rate_control(&mut self) {
+		match self.output {
+			RunningOutput::PortAudio(_) | RunningOutput::Callback => {},
+			_ => return,
+		}
+
+		let capacity = (self.ring_buffer.capacity() * AUDIO_DATA_BUFFER_SIZE) as f32;
+		let queued = (self.ring_buffer.len() * AUDIO_DATA_BUFFER_SIZE + self.output_queue.len()) as f32;
+		let fill = min!(1.0, queued / capacity);
+		self.buffer_fill += (fill - self.buffer_fill) / FILL_SMOOTHING_FRAMES;
+
+		let adjustment = (1.0 - 2.0 * self.buffer_fill as f64) * MAX_RATE_ADJUSTMENT;
+		self.rate_adjustment = max!(-MAX_RATE_ADJUSTMENT, min!(MAX_RATE_ADJUSTMENT, adjustment));
+		self.resampler.live.set_rate_adjustment(self.rate_adjustment);
+	}
+
+	/// Resizes the ring buffer for a new target latency, restarting the output if it's running.
+	/// `AudioPull`s taken before this stop getting samples.
+	pub fn set_target_latency(&mut self, milliseconds: u32) {
+		if self.target_latency == milliseconds { return }
+		self.target_latency = milliseconds;
+
+		let restart_port_audio = match self.output {
+			RunningOutput::PortAudio(_) => true,
+			_ => false,
+		};
+		if restart_port_audio { self.stop(); }
+		self.ring_buffer = Arc::new(create_ring_buffer(milliseconds, self.sample_rate));
+		self.output_queue.clear();
+		self.buffer_fill = 0.5;
+		if restart_port_audio { self.start(AudioOutput::PortAudio); }
+	}
+
+	/// Switches to `backend` if the setting changed since the last call.
+	/// Outputs that were picked some other way (e.g. from the command line)
+	/// are left alone until the setting changes.
+	pub fn follow_backend_setting(&mut self, backend: AudioBackend) {
+		if self.backend_setting != backend {
+			self.backend_setting = backend;
+			self.start(AudioOutput::from_backend(backend));
 		}
 	}
 
-	#[inline(always)]
-	pub fn millis_to_frames(&self, milliseconds: u32, millisecond_tenths: u32) -> u32 {
-		// 1 / 44_100 s - sample
-		// 1 / 1000 s - milliseconds
-		return milliseconds * 44 + millisecond_tenths * 4;
+	/// How many times the output needed samples and there weren't any.
+	pub fn underruns(&self) -> usize {
+		use std::sync::atomic::Ordering;
+		self.ring_buffer._stat_read_misses.load(Ordering::Relaxed)
 	}
 
-	pub fn start(&mut self) {
-		let ring_buffer = self.ring_buffer.clone();
-		let thread = thread::Builder::new().name("Audio".to_string()).spawn(move || {
-			start_port_audio(ring_buffer);
-		}).expect("Failed to start audio thread.");
-		self.output_thread = Some(thread);
+	/// How many buffers were thrown away because the output wasn't taking them fast enough.
+	pub fn overruns(&self) -> usize {
+		use std::sync::atomic::Ordering;
+		self.ring_buffer._stat_write_misses.load(Ordering::Relaxed)
+	}
+
+	/// A name for the output that is running, for display.
+	pub fn output_name(&self) -> String {
+		match self.output {
+			RunningOutput::Stopped => "Stopped".to_string(),
+			RunningOutput::PortAudio(_) => "PortAudio".to_string(),
+			RunningOutput::Null => "None".to_string(),
+			RunningOutput::Wav(_, ref path) => format!("WAV File ({})", path.display()),
+			RunningOutput::Callback => "Callback".to_string(),
+		}
+	}
+
+	/// Starts the audio going to `output`, stopping whatever output was running before.
+	/// If the output can't be started the audio goes nowhere instead.
+	pub fn start(&mut self, output: AudioOutput) {
+		self.stop();
+		self.output_queue.clear();
+		self.buffer_fill = 0.5;
+		self.rate_adjustment = 0.0;
+		self.resampler.live.set_rate_adjustment(0.0);
+
+		self.output = match output {
+			AudioOutput::PortAudio => {
+				match spawn_port_audio(self.ring_buffer.clone()) {
+					Ok(thread) => RunningOutput::PortAudio(thread),
+					Err(e) => {
+						console_error!("Failed to start PortAudio, there will be no sound: {}", e);
+						RunningOutput::Null
+					}
+				}
+			},
+			AudioOutput::Null => RunningOutput::Null,
+			AudioOutput::Wav(path) => {
+				match create_wav_output(&path, self.sample_rate) {
+					Ok(writer) => {
+						console_log!("Writing audio to {}", path.display());
+						RunningOutput::Wav(writer, path)
+					},
+					Err(e) => {
+						console_error!("{}", e);
+						RunningOutput::Null
+					}
+				}
+			},
+			AudioOutput::Callback => RunningOutput::Callback,
+		};
+	}
+
+	/// Starts the callback output and returns something to pull the samples out with.
+	pub fn start_callback(&mut self) -> AudioPull {
+		self.start(AudioOutput::Callback);
+		AudioPull::new(self.ring_buffer.clone())
 	}
 
 	pub fn stop(&mut self) {
-		debug_trace!("Waiting for audio output thread to stop...");
-		match self.output_thread.take() {
-			Some(t) => {
+		match mem::replace(&mut self.output, RunningOutput::Stopped) {
+			RunningOutput::PortAudio(t) => {
+				debug_trace!("Waiting for audio output thread to stop...");
 				t.thread().unpark();
 				debug_trace!("Unparked PortAudio thread.");
 				match t.join() {
@@ -62,13 +451,80 @@ impl AudioDevice {
 					}
 				}
 			},
-			None => {
-				debug_warn!("No audio thread to stop.");
-			}
+			RunningOutput::Wav(writer, path) => {
+				match writer.finish() {
+					Ok(_) => console_log!("Wrote audio to {}", path.display()),
+					Err(e) => console_error!("Failed to write {}: {}", path.display(), e),
+				}
+			},
+			_ => {}
+		}
+	}
+}
+
+/// Reads samples out of the ring buffer for outputs that
+/// aren't run by the emulator (PortAudio's callback or an embedding application).
+pub struct AudioPull {
+	ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>,
+
+	/// How far into the current buffer reading has gotten.
+	index: usize,
+}
+
+impl AudioPull {
+	pub fn new(ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>) -> AudioPull {
+		AudioPull {
+			ring_buffer: ring_buffer,
+			index: 0,
+		}
+	}
+
+	/// Fills `out` with as many samples as are ready and returns how many that was.
+	/// The samples don't have the master volume applied.
+	pub fn read(&mut self, out: &mut [(i16, i16)]) -> usize {
+		let mut count = 0;
+		let mut index = self.index;
+		while count < out.len() {
+			let read = self.ring_buffer.try_read(|audio_data| {
+				let available = AUDIO_DATA_BUFFER_SIZE - index;
+				let wanted = min!(available, out.len() - count);
+				out[count..(count + wanted)].copy_from_slice(&audio_data[index..(index + wanted)]);
+				count += wanted;
+				index += wanted;
+				if index >= AUDIO_DATA_BUFFER_SIZE {
+					index = 0;
+					return true; // Finished reading this buffer.
+				}
+				return false;
+			});
+			if !read { break }
 		}
+		self.index = index;
+		count
+	}
+}
+
+fn create_wav_output(path: &PathBuf, sample_rate: u32) -> Result<WavWriter<BufWriter<File>>, String> {
+	if let Err(e) = create_parent_dir(path) {
+		return Err(e)
+	}
+	let file = match File::create(path) {
+		Ok(f) => BufWriter::new(f),
+		Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+	};
+	match WavWriter::new(file, 2, sample_rate) {
+		Ok(writer) => Ok(writer),
+		Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
 	}
 }
 
+/// Creates a ring buffer that holds twice `latency` milliseconds of audio.
+fn create_ring_buffer(latency: u32, sample_rate: u32) -> AsyncRingBuffer<AudioBufferType> {
+	let frames = (latency * sample_rate / 1000) as usize * 2;
+	let count = max!(4, (frames + AUDIO_DATA_BUFFER_SIZE - 1) / AUDIO_DATA_BUFFER_SIZE);
+	AsyncRingBuffer::new(count, || [(0, 0); AUDIO_DATA_BUFFER_SIZE])
+}
+
 /// Hearing is logarithmic or something or other,
 /// so just multiplying our signal by 1/10 won't translate
 /// exactly to 1/10 of perceived volume.
@@ -78,76 +534,102 @@ pub fn volume_to_signal_multiplier(volume: f32) -> f32 {
 	return sound_pressure;
 }
 
+fn master_volume() -> f32 {
+	use std::sync::atomic::Ordering;
+	let unscaled_volume = ::pyrite::settings::ATOMIC_MASTER_VOLUME
+		.load(Ordering::Relaxed)
+		.unwrap_or(1.0);
+	volume_to_signal_multiplier(unscaled_volume)
+}
+
 fn apply_volume(s: i16, v: f32) -> i16 {
 	(s as f32 * v) as i16
 }
 
-fn start_port_audio(ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>) {
+/// Rounds a sample to 16 bits, clipping it if it's out of range.
+#[inline(always)]
+pub fn clip(sample: f32) -> i16 {
+	if sample >= 32767.0 { 32767 }
+	else if sample <= -32768.0 { -32768 }
+	else { sample.round() as i16 }
+}
+
+/// Starts PortAudio on its own thread and waits until it's either playing or has failed.
+fn spawn_port_audio(ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>) -> Result<thread::JoinHandle<()>, String> {
+	let (sender, receiver) = channel();
+	let thread = match thread::Builder::new().name("Audio".to_string()).spawn(move || {
+		run_port_audio(ring_buffer, sender);
+	}) {
+		Ok(t) => t,
+		Err(e) => return Err(format!("Failed to start audio thread: {}", e)),
+	};
+
+	match receiver.recv() {
+		Ok(Ok(_)) => Ok(thread),
+		Ok(Err(e)) => {
+			let _ = thread.join();
+			Err(e)
+		},
+		Err(_) => {
+			let _ = thread.join();
+			Err("The audio thread stopped before PortAudio started.".to_string())
+		},
+	}
+}
+
+fn run_port_audio(ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>, started: Sender<Result<(), String>>) {
 	// SETUP:
-	let pa = portaudio::PortAudio::new().expect("Failed to initialize port audio.");
-	let settings = pa.default_output_stream_settings(CHANNELS, SAMPLE_RATE, FRAMES_PER_BUFFER)
-		.expect("Failed to get PortAudio default output stream settings.");
-	let mut remaining_audio_data_index = 0;
+	let pa = match portaudio::PortAudio::new() {
+		Ok(pa) => pa,
+		Err(e) => {
+			let _ = started.send(Err(format!("Failed to initialize PortAudio: {}", e)));
+			return
+		}
+	};
+	let settings = match pa.default_output_stream_settings(CHANNELS, SAMPLE_RATE, FRAMES_PER_BUFFER) {
+		Ok(settings) => settings,
+		Err(e) => {
+			let _ = started.send(Err(format!("Failed to get PortAudio default output stream settings: {}", e)));
+			return
+		}
+	};
+
+	let mut pull = AudioPull::new(ring_buffer);
+	let mut frames_buffer = vec![(0, 0); FRAMES_PER_BUFFER as usize];
 
 	let mut last_left = 0;
 	let mut last_right = 0;
 
 	let callback = move |portaudio::OutputStreamCallbackArgs { buffer, frames, .. }| {
-		let mut idx = 0;
-		let buffer_len = frames * 2;
+		let volume = master_volume();
 
-		use std::sync::atomic::Ordering;
-		let unscaled_volume = ::pyrite::settings::ATOMIC_MASTER_VOLUME
-			.load(Ordering::Relaxed)
-			.unwrap_or(1.0);
-		let volume = volume_to_signal_multiplier(unscaled_volume);
-
-		let mut continue_reading = true;
-		while continue_reading {
-			let read_status = ring_buffer.try_read(|audio_data| {
-				while remaining_audio_data_index < AUDIO_DATA_BUFFER_SIZE {
-					let (left, right) = audio_data[remaining_audio_data_index];
-
-					last_left = left;
-					last_right = right;
-
-					buffer[idx] = apply_volume(left, volume);
-					buffer[idx + 1] = apply_volume(right, volume);
-
-					idx += 2;
-					remaining_audio_data_index += 1;
-
-					if idx >= buffer_len {
-						continue_reading = false;
-						if remaining_audio_data_index < AUDIO_DATA_BUFFER_SIZE {
-							// Didn't finish reading this buffer but the device
-							// doesn't require anymore frames at the moment.
-							return false;
-						} else {
-							remaining_audio_data_index = 0;
-							// Finished reading this buffer and the device doesn't
-							// require anymore frames.
-							return true;
-						}
-					}
-				}
-				remaining_audio_data_index = 0;
-				return true;
-			});
-			continue_reading &= read_status;
+		if frames_buffer.len() < frames {
+			frames_buffer.resize(frames, (0, 0));
+		}
+		let read = pull.read(&mut frames_buffer[..frames]);
+		if read > 0 {
+			let (left, right) = frames_buffer[read - 1];
+			last_left = left;
+			last_right = right;
 		}
 
-		while idx < buffer_len {
-			buffer[idx] = apply_volume(last_left, volume);
-			buffer[idx + 1] = apply_volume(last_right, volume);
-			idx += 2;
+		for idx in 0..frames {
+			// Repeat the last sample if we ran out.
+			let (left, right) = if idx < read { frames_buffer[idx] } else { (last_left, last_right) };
+			buffer[idx * 2] = apply_volume(left, volume);
+			buffer[idx * 2 + 1] = apply_volume(right, volume);
 		}
 
 		return portaudio::Continue;
 	};
 
-	let mut stream = pa.open_non_blocking_stream(settings, callback)
-		.expect("Failed to create PortAudio output stream.");
+	let mut stream = match pa.open_non_blocking_stream(settings, callback) {
+		Ok(stream) => stream,
+		Err(e) => {
+			let _ = started.send(Err(format!("Failed to create PortAudio output stream: {}", e)));
+			return
+		}
+	};
 	debug_info!("Opened PortAudio stream.");
 
 	match stream.start() {
@@ -155,10 +637,11 @@ fn start_port_audio(ring_buffer: Arc<AsyncRingBuffer<AudioBufferType>>) {
 			debug_info!("Started PortAudio output stream");
 		},
 		Err(e) => {
-			debug_error!("Failed to start PortAudio output stream. Error: {}", e);
+			let _ = started.send(Err(format!("Failed to start PortAudio output stream: {}", e)));
 			return
 		}
 	}
+	let _ = started.send(Ok(()));
 
 	debug_trace!("Parked PortAudio thread.");
 	thread::park(); // And now we wait...
@@ -0,0 +1,136 @@
+//! Dumps the final audio mix, and optionally every sound channel on its own,
+//! to WAV files. Samples are generated in emulated time like they are for
+//! recordings, so dumps line up with recordings made on hardware.
+//!
+//! Each stem is stereo and holds what that channel adds to the left and right
+//! outputs after panning and the master volumes, before the channels are summed.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use ::util::wav::WavWriter;
+use ::util::io::create_parent_dir;
+
+/// Channels 1-4 followed by DirectSound A and B.
+pub const STEM_COUNT: usize = 6;
+pub const STEM_NAMES: [&'static str; STEM_COUNT] = ["ch1", "ch2", "ch3", "ch4", "fifoa", "fifob"];
+
+pub type StemFrame = [(i16, i16); STEM_COUNT];
+
+struct WavFile {
+	writer: WavWriter<BufWriter<File>>,
+	path: PathBuf,
+}
+
+impl WavFile {
+	fn create(path: PathBuf, sample_rate: u32) -> Result<WavFile, String> {
+		let file = match File::create(&path) {
+			Ok(f) => BufWriter::new(f),
+			Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+		};
+		match WavWriter::new(file, 2, sample_rate) {
+			Ok(writer) => Ok(WavFile { writer: writer, path: path }),
+			Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+		}
+	}
+
+	fn write(&mut self, frames: &[(i16, i16)]) -> Result<(), String> {
+		match self.writer.write_stereo(frames) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", self.path.display(), e)),
+		}
+	}
+
+	fn finish(self) -> Result<(), String> {
+		let path = self.path;
+		match self.writer.finish() {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+		}
+	}
+}
+
+pub struct AudioDump {
+	mix: WavFile,
+
+	/// Empty unless stems were asked for.
+	stems: Vec<WavFile>,
+
+	/// Reused while splitting stem frames into one buffer per stem.
+	stem_buffer: Vec<(i16, i16)>,
+
+	pub samples: u64,
+}
+
+impl AudioDump {
+	/// Starts dumping the mix to `path`. Stems go next to it,
+	/// e.g. `dump.wav` gets `dump-ch1.wav` through `dump-fifob.wav`.
+	pub fn new(path: &Path, sample_rate: u32, stems: bool) -> Result<AudioDump, String> {
+		if let Err(e) = create_parent_dir(path) {
+			return Err(e)
+		}
+
+		let mix = match WavFile::create(path.to_path_buf(), sample_rate) {
+			Ok(f) => f,
+			Err(e) => return Err(e),
+		};
+
+		let mut stem_files = Vec::new();
+		if stems {
+			let base = match path.file_stem() {
+				Some(stem) => stem.to_string_lossy().into_owned(),
+				None => "dump".to_string(),
+			};
+			for name in STEM_NAMES.iter() {
+				let stem_path = path.with_file_name(format!("{}-{}.wav", base, name));
+				match WavFile::create(stem_path, sample_rate) {
+					Ok(f) => stem_files.push(f),
+					Err(e) => return Err(e),
+				}
+			}
+		}
+
+		Ok(AudioDump {
+			mix: mix,
+			stems: stem_files,
+			stem_buffer: Vec::new(),
+			samples: 0,
+		})
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.mix.path
+	}
+
+	pub fn wants_stems(&self) -> bool {
+		!self.stems.is_empty()
+	}
+
+	/// `stems` is ignored unless stems were asked for, in which case
+	/// it must have one entry for every frame in `mix`.
+	pub fn write(&mut self, mix: &[(i16, i16)], stems: &[StemFrame]) -> Result<(), String> {
+		self.samples += mix.len() as u64;
+		if let Err(e) = self.mix.write(mix) { return Err(e) }
+
+		for (index, file) in self.stems.iter_mut().enumerate() {
+			self.stem_buffer.clear();
+			for frame in stems.iter() {
+				self.stem_buffer.push(frame[index]);
+			}
+			if let Err(e) = file.write(&self.stem_buffer) { return Err(e) }
+		}
+		Ok(())
+	}
+
+	/// Finishes every file. The WAV files aren't valid until this is called.
+	pub fn finish(self) -> Result<(), String> {
+		let mut result = self.mix.finish();
+		for file in self.stems.into_iter() {
+			if let Err(e) = file.finish() {
+				result = Err(e);
+			}
+		}
+		result
+	}
+}
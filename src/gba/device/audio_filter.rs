@@ -0,0 +1,114 @@
+//! Filters that make the mixed output sound like it's coming out of a GBA.
+//! The real thing only ever plays sound through a tiny speaker or through
+//! the headphone jack, and both cut off more than a plain DAC would.
+
+use std::f32::consts::PI;
+use super::audio::clip;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AudioOutputFilter {
+	/// The mix as it comes out of the DAC.
+	None,
+
+	/// The built in speaker, which barely plays anything low and rolls the highs off.
+	Speaker,
+
+	/// The headphone jack, which only blocks DC and the very top of the range.
+	Headphones,
+}
+
+pub const AUDIO_OUTPUT_FILTERS: [AudioOutputFilter; 3] = [
+	AudioOutputFilter::None,
+	AudioOutputFilter::Speaker,
+	AudioOutputFilter::Headphones,
+];
+
+impl AudioOutputFilter {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			AudioOutputFilter::None => "None",
+			AudioOutputFilter::Speaker => "Speaker",
+			AudioOutputFilter::Headphones => "Headphones",
+		}
+	}
+
+	/// The high pass and low pass cutoffs in Hz.
+	fn cutoffs(&self) -> (f32, f32) {
+		match *self {
+			AudioOutputFilter::None => (0.0, 0.0),
+			AudioOutputFilter::Speaker => (400.0, 5000.0),
+			AudioOutputFilter::Headphones => (20.0, 15000.0),
+		}
+	}
+}
+
+/// A one pole high pass into a one pole low pass for each side.
+pub struct OutputFilter {
+	kind: AudioOutputFilter,
+	sample_rate: u32,
+
+	/// Coefficients for the high pass and the low pass.
+	hp_alpha: f32,
+	lp_alpha: f32,
+
+	/// The last (input, output) of the high pass and the last output of the low pass, left then right.
+	hp_state: [(f32, f32); 2],
+	lp_state: [f32; 2],
+}
+
+impl OutputFilter {
+	pub fn new(kind: AudioOutputFilter, sample_rate: u32) -> OutputFilter {
+		let (hp_cutoff, lp_cutoff) = kind.cutoffs();
+		let dt = 1.0 / sample_rate as f32;
+
+		let hp_alpha = if hp_cutoff > 0.0 {
+			let rc = 1.0 / (2.0 * PI * hp_cutoff);
+			rc / (rc + dt)
+		} else {
+			1.0
+		};
+
+		let lp_alpha = if lp_cutoff > 0.0 {
+			let rc = 1.0 / (2.0 * PI * lp_cutoff);
+			dt / (rc + dt)
+		} else {
+			1.0
+		};
+
+		OutputFilter {
+			kind: kind,
+			sample_rate: sample_rate,
+			hp_alpha: hp_alpha,
+			lp_alpha: lp_alpha,
+			hp_state: [(0.0, 0.0); 2],
+			lp_state: [0.0; 2],
+		}
+	}
+
+	/// Rebuilds the filter if the kind of filter or the sample rate changed.
+	pub fn configure(&mut self, kind: AudioOutputFilter, sample_rate: u32) {
+		if self.kind != kind || self.sample_rate != sample_rate {
+			*self = OutputFilter::new(kind, sample_rate);
+		}
+	}
+
+	pub fn process(&mut self, frame: (i16, i16)) -> (i16, i16) {
+		if self.kind == AudioOutputFilter::None {
+			return frame
+		}
+		let left = self.process_side(0, frame.0 as f32);
+		let right = self.process_side(1, frame.1 as f32);
+		(clip(left), clip(right))
+	}
+
+	#[inline(always)]
+	fn process_side(&mut self, side: usize, input: f32) -> f32 {
+		let (last_input, last_output) = self.hp_state[side];
+		let high_passed = self.hp_alpha * (last_output + input - last_input);
+		self.hp_state[side] = (input, high_passed);
+
+		let low_passed = self.lp_state[side] + self.lp_alpha * (high_passed - self.lp_state[side]);
+		self.lp_state[side] = low_passed;
+		low_passed
+	}
+}
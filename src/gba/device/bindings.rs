@@ -0,0 +1,307 @@
+//! Maps keys on the keyboard to GBA buttons and to things the emulator does
+//! (pausing, save states, debug layers...). The bindings live in the settings
+//! and keys are stored by the name glutin gives them (`Z`, `Return`, `F5`)
+//! so that the settings file stays readable. A key can be bound to more than
+//! one action and an action can have more than one key.
+
+use glutin::VirtualKeyCode;
+use super::super::hw::joypad::*;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Action {
+	// GBA BUTTONS:
+	A,
+	B,
+	Select,
+	Start,
+	Right,
+	Left,
+	Up,
+	Down,
+	R,
+	L,
+
+	// TURBO BUTTONS:
+	TurboA,
+	TurboB,
+	TurboL,
+	TurboR,
+
+	// EMULATOR:
+	Pause,
+	FastForward,
+	Rewind,
+	SlowMotion,
+	SaveState,
+	LoadState,
+	NextSlot,
+	Screenshot,
+	Record,
+	Movie,
+
+	// DEBUGGING:
+	DynamicDebug,
+	DumpRegisters,
+	ToggleBg0,
+	ToggleBg1,
+	ToggleBg2,
+	ToggleBg3,
+	ToggleObj,
+	HideLayers,
+	ShowLayers,
+}
+
+pub const ACTIONS: [Action; 33] = [
+	Action::A,
+	Action::B,
+	Action::Select,
+	Action::Start,
+	Action::Right,
+	Action::Left,
+	Action::Up,
+	Action::Down,
+	Action::R,
+	Action::L,
+	Action::TurboA,
+	Action::TurboB,
+	Action::TurboL,
+	Action::TurboR,
+	Action::Pause,
+	Action::FastForward,
+	Action::Rewind,
+	Action::SlowMotion,
+	Action::SaveState,
+	Action::LoadState,
+	Action::NextSlot,
+	Action::Screenshot,
+	Action::Record,
+	Action::Movie,
+	Action::DynamicDebug,
+	Action::DumpRegisters,
+	Action::ToggleBg0,
+	Action::ToggleBg1,
+	Action::ToggleBg2,
+	Action::ToggleBg3,
+	Action::ToggleObj,
+	Action::HideLayers,
+	Action::ShowLayers,
+];
+
+impl Action {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Action::A => "A",
+			Action::B => "B",
+			Action::Select => "Select",
+			Action::Start => "Start",
+			Action::Right => "Right",
+			Action::Left => "Left",
+			Action::Up => "Up",
+			Action::Down => "Down",
+			Action::R => "R",
+			Action::L => "L",
+			Action::TurboA => "Turbo A",
+			Action::TurboB => "Turbo B",
+			Action::TurboL => "Turbo L",
+			Action::TurboR => "Turbo R",
+			Action::Pause => "Pause",
+			Action::FastForward => "Fast-Forward (Hold)",
+			Action::Rewind => "Rewind (Hold)",
+			Action::SlowMotion => "Slow Motion",
+			Action::SaveState => "Save State",
+			Action::LoadState => "Load State",
+			Action::NextSlot => "Next Save Slot",
+			Action::Screenshot => "Screenshot",
+			Action::Record => "Start/Stop Recording",
+			Action::Movie => "Start/Stop Movie",
+			Action::DynamicDebug => "Debug Output (Hold)",
+			Action::DumpRegisters => "Dump Registers",
+			Action::ToggleBg0 => "Toggle BG 0",
+			Action::ToggleBg1 => "Toggle BG 1",
+			Action::ToggleBg2 => "Toggle BG 2",
+			Action::ToggleBg3 => "Toggle BG 3",
+			Action::ToggleObj => "Toggle OBJ",
+			Action::HideLayers => "Hide All Layers",
+			Action::ShowLayers => "Show All Layers",
+		}
+	}
+
+	/// The KEYINPUT bit of the button this presses, if it's a GBA button.
+	pub fn button_mask(&self) -> Option<u16> {
+		match *self {
+			Action::A => Some(GBA_BTN_A),
+			Action::B => Some(GBA_BTN_B),
+			Action::Select => Some(GBA_BTN_SELECT),
+			Action::Start => Some(GBA_BTN_START),
+			Action::Right => Some(GBA_BTN_RIGHT),
+			Action::Left => Some(GBA_BTN_LEFT),
+			Action::Up => Some(GBA_BTN_UP),
+			Action::Down => Some(GBA_BTN_DOWN),
+			Action::R => Some(GBA_BTN_R),
+			Action::L => Some(GBA_BTN_L),
+			_ => None,
+		}
+	}
+
+	/// The KEYINPUT bit of the button this presses over and over, if it's a turbo button.
+	pub fn turbo_mask(&self) -> Option<u16> {
+		match *self {
+			Action::TurboA => Some(GBA_BTN_A),
+			Action::TurboB => Some(GBA_BTN_B),
+			Action::TurboL => Some(GBA_BTN_L),
+			Action::TurboR => Some(GBA_BTN_R),
+			_ => None,
+		}
+	}
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Debug)]
+pub struct KeyBinding {
+	pub action: Action,
+	pub key: String,
+}
+
+impl KeyBinding {
+	pub fn new(action: Action, key: VirtualKeyCode) -> KeyBinding {
+		KeyBinding {
+			action: action,
+			key: key_name(key),
+		}
+	}
+}
+
+/// The keys Pyrite has always used.
+pub fn default_bindings() -> Vec<KeyBinding> {
+	vec![
+		KeyBinding::new(Action::A, VirtualKeyCode::Z),
+		KeyBinding::new(Action::B, VirtualKeyCode::X),
+		KeyBinding::new(Action::Select, VirtualKeyCode::Back),
+		KeyBinding::new(Action::Start, VirtualKeyCode::Return),
+		KeyBinding::new(Action::Right, VirtualKeyCode::Right),
+		KeyBinding::new(Action::Left, VirtualKeyCode::Left),
+		KeyBinding::new(Action::Up, VirtualKeyCode::Up),
+		KeyBinding::new(Action::Down, VirtualKeyCode::Down),
+		KeyBinding::new(Action::R, VirtualKeyCode::S),
+		KeyBinding::new(Action::L, VirtualKeyCode::A),
+		KeyBinding::new(Action::TurboA, VirtualKeyCode::C),
+		KeyBinding::new(Action::TurboB, VirtualKeyCode::V),
+		KeyBinding::new(Action::Pause, VirtualKeyCode::P),
+		KeyBinding::new(Action::FastForward, VirtualKeyCode::Tab),
+		KeyBinding::new(Action::Rewind, VirtualKeyCode::Grave),
+		KeyBinding::new(Action::SlowMotion, VirtualKeyCode::F8),
+		KeyBinding::new(Action::SaveState, VirtualKeyCode::F5),
+		KeyBinding::new(Action::LoadState, VirtualKeyCode::F7),
+		KeyBinding::new(Action::NextSlot, VirtualKeyCode::F6),
+		KeyBinding::new(Action::Screenshot, VirtualKeyCode::F12),
+		KeyBinding::new(Action::Record, VirtualKeyCode::F10),
+		KeyBinding::new(Action::Movie, VirtualKeyCode::F9),
+		KeyBinding::new(Action::DynamicDebug, VirtualKeyCode::D),
+		KeyBinding::new(Action::DumpRegisters, VirtualKeyCode::R),
+		KeyBinding::new(Action::ToggleBg0, VirtualKeyCode::Key1),
+		KeyBinding::new(Action::ToggleBg1, VirtualKeyCode::Key2),
+		KeyBinding::new(Action::ToggleBg2, VirtualKeyCode::Key3),
+		KeyBinding::new(Action::ToggleBg3, VirtualKeyCode::Key4),
+		KeyBinding::new(Action::ToggleObj, VirtualKeyCode::Key5),
+		KeyBinding::new(Action::HideLayers, VirtualKeyCode::L),
+		KeyBinding::new(Action::ShowLayers, VirtualKeyCode::K),
+	]
+}
+
+pub fn key_name(key: VirtualKeyCode) -> String {
+	format!("{:?}", key)
+}
+
+/// Every action that is bound to `key`.
+pub fn actions_for_key(bindings: &[KeyBinding], key: VirtualKeyCode) -> Vec<Action> {
+	let name = key_name(key);
+	bindings.iter().filter(|b| b.key == name).map(|b| b.action).collect()
+}
+
+/// The keys bound to `action` separated by commas, or "None".
+pub fn keys_for_action(bindings: &[KeyBinding], action: Action) -> String {
+	let keys: Vec<&str> = bindings.iter().filter(|b| b.action == action).map(|b| &b.key[..]).collect();
+	if keys.is_empty() {
+		"None".to_string()
+	} else {
+		keys.join(", ")
+	}
+}
+
+/// Adds a binding unless it's already there.
+pub fn bind(bindings: &mut Vec<KeyBinding>, action: Action, key: VirtualKeyCode) {
+	let binding = KeyBinding::new(action, key);
+	if !bindings.contains(&binding) {
+		bindings.push(binding);
+	}
+}
+
+pub fn unbind_action(bindings: &mut Vec<KeyBinding>, action: Action) {
+	bindings.retain(|b| b.action != action);
+}
+
+/// Actions that share a key with `action`, a key being bound to a game
+/// button and a hotkey at the same time is usually a mistake.
+pub fn conflicts(bindings: &[KeyBinding], action: Action) -> Vec<Action> {
+	let mut ret = Vec::new();
+	for binding in bindings.iter().filter(|b| b.action == action) {
+		for other in bindings.iter() {
+			if other.key == binding.key && other.action != action && !ret.contains(&other.action) {
+				ret.push(other.action);
+			}
+		}
+	}
+	ret
+}
+
+/// The buttons held on the keyboard. This is kept apart from the joypad because
+/// turbo buttons go on and off from frame to frame and movies only take input
+/// at the start of a frame.
+pub struct InputState {
+	/// Buttons that are held (1 = held, unlike KEYINPUT).
+	buttons: u16,
+
+	/// Turbo buttons that are held.
+	turbo: u16,
+
+	/// Counts frames for the turbo buttons.
+	turbo_frame: u32,
+}
+
+impl InputState {
+	pub fn new() -> InputState {
+		InputState {
+			buttons: 0,
+			turbo: 0,
+			turbo_frame: 0,
+		}
+	}
+
+	pub fn button_changed(&mut self, mask: u16, pressed: bool) {
+		if pressed { self.buttons |= mask; } else { self.buttons &= !mask; }
+	}
+
+	pub fn turbo_changed(&mut self, mask: u16, pressed: bool) {
+		if pressed {
+			// Turbo buttons always start out pressed.
+			if self.turbo == 0 { self.turbo_frame = 0; }
+			self.turbo |= mask;
+		} else {
+			self.turbo &= !mask;
+		}
+	}
+
+	pub fn next_frame(&mut self) {
+		self.turbo_frame = self.turbo_frame.wrapping_add(1);
+	}
+
+	/// KEYINPUT for what is held right now. Turbo buttons are pressed
+	/// for `turbo_rate` frames and then released for as many.
+	pub fn keyinput(&self, turbo_rate: u32) -> u16 {
+		let rate = if turbo_rate == 0 { 1 } else { turbo_rate };
+		let mut held = self.buttons;
+		if (self.turbo_frame / rate) % 2 == 0 {
+			held |= self.turbo;
+		}
+		!held & KEY_MASK
+	}
+}
@@ -2,12 +2,49 @@ use glutin::{Event, ElementState, VirtualKeyCode};
 use super::super::Gba;
 use super::super::GbaEventPoll;
 use super::super::INT_KEYPAD;
+use super::super::serialization::SAVE_STATE_SLOTS;
 use super::imgui_support;
+use super::bindings;
+use super::bindings::Action;
 use rust_imgui as imgui;
 
 impl GbaEventPoll for Gba {
 	fn poll_device_events(&mut self) {
-		for event in self.device.video.display.poll_events() {
+		if self.device.video.is_some() {
+			self.poll_window_events();
+		}
+
+		// Movies and rewinding set the keys themselves at the start of every frame.
+		if self.device.movie.is_none() && !self.device.rewind.active {
+			let keys = self.device.input.keyinput(psetting!(turbo_rate));
+			self.joypad.set_keys(keys);
+		}
+
+		if self.joypad.tick(&mut self.cpu) {
+			self.hardware_interrupt(INT_KEYPAD);
+		}
+	}
+}
+
+impl Gba {
+	fn poll_window_events(&mut self) {
+		let events: Vec<Event> = match self.device.video {
+			Some(ref video) => video.display.poll_events().collect(),
+			None => return,
+		};
+
+		for event in events {
+			// The binding editor is waiting for a key, Escape cancels.
+			if let Event::KeyboardInput(ElementState::Pressed, _, Some(keycode)) = event {
+				if let Some(action) = self.extras.rebinding.take() {
+					if keycode != VirtualKeyCode::Escape {
+						bindings::bind(&mut ::pyrite::get_settings().key_bindings, action, keycode);
+						::pyrite::settings_changed();
+					}
+					continue
+				}
+			}
+
 			match event {
 				Event::Closed => self.request_exit = true,
 				Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Escape)) => {
@@ -16,52 +53,72 @@ impl GbaEventPoll for Gba {
 				_ => {}
 			}
 
-			imgui_support::imgui_check_event(&mut self.device.video.im_support, &event);
+			if let Some(ref mut video) = self.device.video {
+				imgui_support::imgui_check_event(&mut video.im_support, &event);
+			}
 			let io = imgui::get_io();
 			let imgui_not_capturing = (io.want_text_input | io.want_capture_keyboard) == 0;
 
 			if imgui_not_capturing {
-				match event {
-				// GENERAL DEBUGGING STUFF:
-					Event::KeyboardInput(state, _, Some(VirtualKeyCode::D)) => {
-						match state {
-							ElementState::Pressed => set_pyrite_dyn_debug!(true),
-							_ => set_pyrite_dyn_debug!(false)
-						}
-					},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::R)) => {
-						self.cpu.reg_dump_pretty();
-					},
-
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::P)) => {
-						self.extras.request_pause = !self.extras.paused;
-					},
-
-				// DEBUGGING LAYERS IN GRAPHICS:
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Key1)) => {debug_toggle_layer!(0);},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Key2)) => {debug_toggle_layer!(1);},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Key3)) => {debug_toggle_layer!(2);},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Key4)) => {debug_toggle_layer!(3);},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Key5)) => {debug_toggle_layer!(4);},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::L)) => {debug_turn_off_all_layers!();},
-					Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::K)) => {debug_turn_on_all_layers!();},
-
-				// ACTUAL GBA SHIT:
-					Event::KeyboardInput(ElementState::Pressed, _, Some(keycode)) => {
-						self.joypad.key_pressed(keycode);
-					},
-					Event::KeyboardInput(ElementState::Released, _, Some(keycode)) => {
-						self.joypad.key_released(keycode);
-					},
-					_ => {}
+				if let Event::KeyboardInput(state, _, Some(keycode)) = event {
+					let pressed = state == ElementState::Pressed;
+					for action in bindings::actions_for_key(&::pyrite::get_settings().key_bindings, keycode) {
+						self.do_action(action, pressed);
+					}
 				}
 			}
 		}
+	}
 
-		if self.joypad.tick(&mut self.cpu) {
-			self.hardware_interrupt(INT_KEYPAD);
+	/// Does whatever `action` is bound to when its key is pressed or released.
+	fn do_action(&mut self, action: Action, pressed: bool) {
+		if let Some(mask) = action.button_mask() {
+			self.device.input.button_changed(mask, pressed);
+			return
 		}
 
+		if let Some(mask) = action.turbo_mask() {
+			self.device.input.turbo_changed(mask, pressed);
+			return
+		}
+
+		// Held actions:
+		match action {
+			Action::FastForward => { self.extras.fast_forward = pressed; return },
+			Action::Rewind => { self.extras.rewinding = pressed; return },
+			Action::DynamicDebug => { set_pyrite_dyn_debug!(pressed); return },
+			_ => {}
+		}
+
+		if !pressed { return }
+
+		match action {
+			Action::Pause => self.extras.request_pause = !self.extras.paused,
+			Action::SlowMotion => {
+				self.extras.cycle_slow_motion();
+				console_log!("Speed: {}%", self.extras.slow_motion * 100.0);
+			},
+			Action::SaveState => self.extras.request_save_state = true,
+			Action::LoadState => self.extras.request_load_state = true,
+			Action::NextSlot => {
+				self.extras.save_state_slot = (self.extras.save_state_slot + 1) % SAVE_STATE_SLOTS;
+				console_log!("Save state slot {}", self.extras.save_state_slot);
+			},
+			Action::Screenshot => self.extras.request_screenshot = true,
+			Action::Record => self.extras.request_recording_toggle = true,
+			Action::Movie => self.extras.request_movie_toggle = true,
+			Action::DumpRegisters => self.cpu.reg_dump_pretty(),
+
+		// DEBUGGING LAYERS IN GRAPHICS:
+			Action::ToggleBg0 => debug_toggle_layer!(0),
+			Action::ToggleBg1 => debug_toggle_layer!(1),
+			Action::ToggleBg2 => debug_toggle_layer!(2),
+			Action::ToggleBg3 => debug_toggle_layer!(3),
+			Action::ToggleObj => debug_toggle_layer!(4),
+			Action::HideLayers => debug_turn_off_all_layers!(),
+			Action::ShowLayers => debug_turn_on_all_layers!(),
+			_ => {}
+		}
 	}
 }
 
@@ -0,0 +1,262 @@
+//! Post-processing for frames that come out of the LCD.
+//! This runs on the CPU so that the window, screenshots and recordings
+//! all get the same picture, even when running headless.
+//!
+//! The stages are applied in order:
+//!   1. Interframe blending (for games that flicker sprites on alternate frames)
+//!   2. Scaling (nearest neighbour or xBR)
+//!   3. LCD grid or scanline overlay
+
+use super::super::hw::lcd::{GbaLcdScreenBuffer, OutputPixel};
+
+const GBA_SCREEN_WIDTH: usize = 240;
+const GBA_SCREEN_HEIGHT: usize = 160;
+
+/// How much the grid and scanlines darken pixels (out of 256).
+const OVERLAY_DARKEN: u32 = 176;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Scaler {
+	/// Integer nearest neighbour scaling.
+	Nearest,
+
+	/// 2xBR, applied once for 2x and twice for 4x.
+	/// 3x is 2xBR followed by nearest neighbour up to 3x.
+	Xbr,
+}
+
+pub const SCALERS: [Scaler; 2] = [Scaler::Nearest, Scaler::Xbr];
+
+impl Scaler {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Scaler::Nearest => "Nearest Neighbour",
+			Scaler::Xbr => "xBR",
+		}
+	}
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LcdOverlay {
+	None,
+	Grid,
+	Scanlines,
+}
+
+pub const LCD_OVERLAYS: [LcdOverlay; 3] = [LcdOverlay::None, LcdOverlay::Grid, LcdOverlay::Scanlines];
+
+impl LcdOverlay {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			LcdOverlay::None => "None",
+			LcdOverlay::Grid => "LCD Grid",
+			LcdOverlay::Scanlines => "Scanlines",
+		}
+	}
+}
+
+/// A frame after it has gone through the filters.
+pub struct FilteredFrame {
+	pub width: usize,
+	pub height: usize,
+
+	/// Row major, `width * height` pixels.
+	pub pixels: Vec<OutputPixel>,
+}
+
+pub struct VideoFilter {
+	/// The last frame that came out of the LCD, for interframe blending.
+	previous: Vec<OutputPixel>,
+
+	/// Used while applying xBR more than once.
+	scratch: FilteredFrame,
+
+	pub frame: FilteredFrame,
+}
+
+impl VideoFilter {
+	pub fn new() -> VideoFilter {
+		VideoFilter {
+			previous: vec![(0, 0, 0); GBA_SCREEN_WIDTH * GBA_SCREEN_HEIGHT],
+			scratch: FilteredFrame { width: 0, height: 0, pixels: Vec::new() },
+			frame: FilteredFrame { width: 0, height: 0, pixels: Vec::new() },
+		}
+	}
+
+	/// Runs a frame through the filters selected in the settings.
+	/// This should be called once for every frame because of interframe blending.
+	pub fn apply(&mut self, buffer: &GbaLcdScreenBuffer) -> &FilteredFrame {
+		let scaler = psetting!(scaler);
+		let scale = max!(1, min!(4, psetting!(scale) as usize));
+		let overlay = psetting!(lcd_overlay);
+		let frame_blending = psetting!(frame_blending);
+
+		self.scratch.width = GBA_SCREEN_WIDTH;
+		self.scratch.height = GBA_SCREEN_HEIGHT;
+		self.scratch.pixels.clear();
+		for y in 0..GBA_SCREEN_HEIGHT {
+			for x in 0..GBA_SCREEN_WIDTH {
+				let pixel = buffer[y][x];
+				let index = y * GBA_SCREEN_WIDTH + x;
+				if frame_blending {
+					self.scratch.pixels.push(average(pixel, self.previous[index]));
+				} else {
+					self.scratch.pixels.push(pixel);
+				}
+				self.previous[index] = pixel;
+			}
+		}
+
+		match scaler {
+			Scaler::Nearest => nearest(&self.scratch, scale, &mut self.frame),
+			Scaler::Xbr => {
+				if scale >= 4 {
+					xbr2x(&self.scratch, &mut self.frame);
+					xbr2x(&self.frame, &mut self.scratch);
+					nearest(&self.scratch, 1, &mut self.frame);
+				} else if scale == 3 {
+					xbr2x(&self.scratch, &mut self.frame);
+					resize_nearest(&self.frame, GBA_SCREEN_WIDTH * 3, GBA_SCREEN_HEIGHT * 3, &mut self.scratch);
+					nearest(&self.scratch, 1, &mut self.frame);
+				} else if scale == 2 {
+					xbr2x(&self.scratch, &mut self.frame);
+				} else {
+					nearest(&self.scratch, 1, &mut self.frame);
+				}
+			}
+		}
+
+		apply_overlay(overlay, &mut self.frame);
+		&self.frame
+	}
+}
+
+/// The size of the frames that `VideoFilter::apply` makes with the current settings.
+pub fn output_size() -> (usize, usize) {
+	let scale = max!(1, min!(4, psetting!(scale) as usize));
+	(GBA_SCREEN_WIDTH * scale, GBA_SCREEN_HEIGHT * scale)
+}
+
+fn nearest(source: &FilteredFrame, scale: usize, dest: &mut FilteredFrame) {
+	resize_nearest(source, source.width * scale, source.height * scale, dest);
+}
+
+/// Nearest neighbour to any size, not just whole multiples of the source.
+fn resize_nearest(source: &FilteredFrame, width: usize, height: usize, dest: &mut FilteredFrame) {
+	dest.width = width;
+	dest.height = height;
+	dest.pixels.clear();
+	for y in 0..height {
+		let row = (y * source.height / height) * source.width;
+		for x in 0..width {
+			dest.pixels.push(source.pixels[row + (x * source.width / width)]);
+		}
+	}
+}
+
+/// Darkens the edges of every GBA pixel so that the frame looks like an LCD.
+/// Only has an effect once the frame has been scaled at least 2x.
+fn apply_overlay(overlay: LcdOverlay, frame: &mut FilteredFrame) {
+	let cell = frame.width / GBA_SCREEN_WIDTH;
+	if overlay == LcdOverlay::None || cell < 2 { return }
+
+	for y in 0..frame.height {
+		let row_edge = (y % cell) == (cell - 1);
+		for x in 0..frame.width {
+			let column_edge = (x % cell) == (cell - 1);
+			let darken = match overlay {
+				LcdOverlay::Grid => row_edge || column_edge,
+				LcdOverlay::Scanlines => row_edge,
+				LcdOverlay::None => false,
+			};
+			if darken {
+				let pixel = &mut frame.pixels[y * frame.width + x];
+				*pixel = (
+					((pixel.0 as u32 * OVERLAY_DARKEN) >> 8) as u8,
+					((pixel.1 as u32 * OVERLAY_DARKEN) >> 8) as u8,
+					((pixel.2 as u32 * OVERLAY_DARKEN) >> 8) as u8,
+				);
+			}
+		}
+	}
+}
+
+#[inline(always)]
+fn average(a: OutputPixel, b: OutputPixel) -> OutputPixel {
+	(
+		((a.0 as u16 + b.0 as u16) >> 1) as u8,
+		((a.1 as u16 + b.1 as u16) >> 1) as u8,
+		((a.2 as u16 + b.2 as u16) >> 1) as u8,
+	)
+}
+
+/// Distance between two colors in YUV space, weighted the way xBR does it.
+#[inline(always)]
+fn color_distance(a: OutputPixel, b: OutputPixel) -> i32 {
+	let yuv = |p: OutputPixel| -> (i32, i32, i32) {
+		let (r, g, b) = (p.0 as i32, p.1 as i32, p.2 as i32);
+		(
+			(299 * r + 587 * g + 114 * b) / 1000,
+			(-169 * r - 331 * g + 500 * b) / 1000,
+			(500 * r - 419 * g - 81 * b) / 1000,
+		)
+	};
+	let (ay, au, av) = yuv(a);
+	let (by, bu, bv) = yuv(b);
+	48 * (ay - by).abs() + 7 * (au - bu).abs() + 6 * (av - bv).abs()
+}
+
+/// 2xBR (level 1) by Hyllian.
+/// Every pixel becomes 2x2 and each of the 4 new pixels is checked for an edge
+/// running through its corner using the 5x5 neighbourhood around the source pixel:
+///
+///          A1 B1 C1
+///       A0 A  B  C  C4
+///       D0 D  E  F  F4
+///       G0 G  H  I  I4
+///          G5 H5 I5
+///
+/// This is written for the bottom right corner; the other corners mirror the neighbourhood.
+fn xbr2x(source: &FilteredFrame, dest: &mut FilteredFrame) {
+	let (sw, sh) = (source.width as i32, source.height as i32);
+	dest.width = source.width * 2;
+	dest.height = source.height * 2;
+	dest.pixels.clear();
+	dest.pixels.resize(dest.width * dest.height, (0, 0, 0));
+
+	let fetch = |x: i32, y: i32| -> OutputPixel {
+		let x = max!(0, min!(sw - 1, x));
+		let y = max!(0, min!(sh - 1, y));
+		source.pixels[(y * sw + x) as usize]
+	};
+
+	for y in 0..sh {
+		for x in 0..sw {
+			for &(sy, sx) in [(1i32, 1i32), (1, -1), (-1, 1), (-1, -1)].iter() {
+				// Neighbour relative to E with the neighbourhood mirrored towards this corner.
+				let p = |dx: i32, dy: i32| -> OutputPixel { fetch(x + dx * sx, y + dy * sy) };
+
+				let e = p(0, 0);
+				let f = p(1, 0);
+				let h = p(0, 1);
+
+				let mut out = e;
+				if e != f && e != h {
+					let wd1 = color_distance(e, p(1, -1)) + color_distance(e, p(-1, 1)) +
+						color_distance(p(1, 1), p(2, 0)) + color_distance(p(1, 1), p(0, 2)) +
+						4 * color_distance(h, f);
+					let wd2 = color_distance(h, p(-1, 0)) + color_distance(h, p(1, 2)) +
+						color_distance(f, p(2, 1)) + color_distance(f, p(0, -1)) +
+						4 * color_distance(e, p(1, 1));
+					if wd1 < wd2 {
+						out = if color_distance(e, f) <= color_distance(e, h) { f } else { h };
+					}
+				}
+
+				let dx = (x * 2 + if sx > 0 { 1 } else { 0 }) as usize;
+				let dy = (y * 2 + if sy > 0 { 1 } else { 0 }) as usize;
+				dest.pixels[dy * dest.width + dx] = out;
+			}
+		}
+	}
+}
@@ -0,0 +1,140 @@
+//! A scripted stand-in for the JOY Bus host (a GameCube) on the other end of the port.
+//!
+//! Scripts are plain text with one step per line, `#` starts a comment:
+//!
+//! ```text
+//! wait 280896       # let one frame pass (in cycles)
+//! reset             # command FFh
+//! status            # command 00h
+//! write 0x12345678  # command 15h, the GBA receives this in JOY_RECV
+//! read              # command 14h, the host receives JOY_TRANS
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JoyBusCommand {
+	/// FFh - Device Reset
+	Reset,
+	/// 00h - Type/Status Data Request
+	Status,
+	/// 14h - Read data from JOY_TRANS
+	Read,
+	/// 15h - Write data to JOY_RECV
+	Write(u32),
+}
+
+impl JoyBusCommand {
+	pub fn code(&self) -> u8 {
+		match *self {
+			JoyBusCommand::Reset => 0xFF,
+			JoyBusCommand::Status => 0x00,
+			JoyBusCommand::Read => 0x14,
+			JoyBusCommand::Write(_) => 0x15,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+enum JoyBusStep {
+	Wait(u64),
+	Command(JoyBusCommand),
+}
+
+pub struct JoyBusHost {
+	steps: VecDeque<JoyBusStep>,
+
+	/// The cycle at which the next step runs.
+	next_step_at: Option<u64>,
+
+	/// Every command that was sent with the bytes that the GBA replied with.
+	pub replies: Vec<(JoyBusCommand, Vec<u8>)>,
+}
+
+impl JoyBusHost {
+	pub fn from_script(script: &str) -> Result<JoyBusHost, String> {
+		let mut steps = VecDeque::new();
+		for (line_idx, line) in script.lines().enumerate() {
+			let line = match line.find('#') {
+				Some(comment) => &line[..comment],
+				None => line,
+			};
+			let mut words = line.split_whitespace();
+			let step = match words.next() {
+				Some("wait") => match words.next().and_then(parse_number) {
+					Some(cycles) => JoyBusStep::Wait(cycles),
+					None => return Err(format!("line {}: expected a number of cycles after `wait`", line_idx + 1)),
+				},
+				Some("reset") => JoyBusStep::Command(JoyBusCommand::Reset),
+				Some("status") => JoyBusStep::Command(JoyBusCommand::Status),
+				Some("read") => JoyBusStep::Command(JoyBusCommand::Read),
+				Some("write") => match words.next().and_then(parse_number) {
+					Some(value) => JoyBusStep::Command(JoyBusCommand::Write(value as u32)),
+					None => return Err(format!("line {}: expected a value after `write`", line_idx + 1)),
+				},
+				Some(other) => return Err(format!("line {}: unknown step `{}`", line_idx + 1, other)),
+				None => continue,
+			};
+			steps.push_back(step);
+		}
+
+		Ok(JoyBusHost {
+			steps: steps,
+			next_step_at: None,
+			replies: Vec::new(),
+		})
+	}
+
+	pub fn load(path: &str) -> Result<JoyBusHost, String> {
+		let mut script = String::new();
+		match File::open(path) {
+			Ok(mut f) => {
+				if let Err(e) = f.read_to_string(&mut script) {
+					return Err(format!("Failed to read JOY Bus script {}: {}", path, e));
+				}
+			},
+			Err(e) => return Err(format!("Failed to open JOY Bus script {}: {}", path, e)),
+		}
+		JoyBusHost::from_script(&script)
+	}
+
+	/// Returns true once every step in the script has run.
+	pub fn finished(&self) -> bool {
+		self.steps.is_empty()
+	}
+
+	/// Returns the next command to send if it is due at `cycles`.
+	pub fn next_command(&mut self, cycles: u64) -> Option<JoyBusCommand> {
+		loop {
+			let next_step_at = match self.next_step_at {
+				Some(at) => at,
+				None => {
+					self.next_step_at = Some(cycles);
+					cycles
+				}
+			};
+			if cycles < next_step_at { return None }
+
+			match self.steps.pop_front() {
+				Some(JoyBusStep::Wait(wait_cycles)) => self.next_step_at = Some(next_step_at + wait_cycles),
+				Some(JoyBusStep::Command(command)) => return Some(command),
+				None => return None,
+			}
+		}
+	}
+
+	pub fn on_reply(&mut self, command: JoyBusCommand, reply: Vec<u8>) {
+		debug_info!("JOY Bus: {:02X}h -> {:?}", command.code(), reply);
+		self.replies.push((command, reply));
+	}
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+	if s.starts_with("0x") {
+		u64::from_str_radix(&s[2..], 16).ok()
+	} else {
+		s.parse().ok()
+	}
+}
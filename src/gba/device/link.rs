@@ -0,0 +1,520 @@
+//! The link cable connects the serial port of this GBA to other instances
+//! of Pyrite. The other end can either be in the same process (see `local_cable`)
+//! or in another process over a TCP or Unix socket (see `host` and `connect`).
+//!
+//! The cable is wired as a star with the parent (multi-player ID 0) in the middle.
+//! Every player reports how many cycles it has emulated and no player is allowed
+//! to run more than `LINK_MAX_LEAD` cycles ahead of the players it is connected to,
+//! so transfers land at (almost) the same emulated time on every GBA.
+
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use bincode::rustc_serialize::{encode_into, decode_from};
+use bincode::SizeLimit;
+
+/// How often (in emulated cycles) a player tells the others how far it has gotten.
+pub const LINK_SYNC_CYCLES: u64 = 1232;
+
+/// The maximum number of cycles that a player can be ahead of the others.
+/// This must be at least twice as large as `LINK_SYNC_CYCLES`.
+pub const LINK_MAX_LEAD: u64 = 1232 * 4;
+
+/// How long we will wait on the other end of the cable before giving up on it.
+const LINK_TIMEOUT_MS: u64 = 5000;
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub enum LinkMessage {
+	/// Sent by the parent to a child when it connects to give it its multi-player ID.
+	Welcome(u8),
+
+	/// The number of cycles that the sender has emulated.
+	Sync(u64),
+
+	/// Sent by the parent when it starts a transfer.
+	/// (start cycle, end cycle, parent's SIOMLT_SEND)
+	Transfer(u64, u64, u16),
+
+	/// Sent by a child once it has latched its SIOMLT_SEND for a transfer.
+	/// (multi-player ID, child's SIOMLT_SEND)
+	Reply(u8, u16),
+
+	/// Sent by the parent when a transfer completes, with the data from every GBA.
+	Complete([u16; 4]),
+
+	/// The sender is going away.
+	Disconnect,
+}
+
+struct LinkEvent {
+	from: u8,
+	message: LinkMessage,
+}
+
+enum LinkOutput {
+	Local(Sender<LinkEvent>),
+	Stream(Box<Write + Send>),
+}
+
+struct LinkPeer {
+	id: u8,
+	output: LinkOutput,
+	cycles: u64,
+	connected: bool,
+}
+
+pub struct LinkDevice {
+	/// Multi-player ID of this GBA (0=Parent, 1-3=1st-3rd child)
+	pub id: u8,
+
+	/// Set when the other end of the cable stopped responding or went away,
+	/// cleared once the game has been told through SIOCNT.
+	error: bool,
+
+	peers: Vec<LinkPeer>,
+	incoming: Receiver<LinkEvent>,
+
+	cycles: u64,
+	last_sync: u64,
+
+	/// The value in this GBA's SIOMLT_SEND register.
+	send_value: u16,
+
+	/// (start cycle, end cycle) of a transfer started by the parent.
+	transfer_request: Option<(u64, u64)>,
+	transfer_replied: bool,
+	replies: [Option<u16>; 4],
+	completed: Option<[u16; 4]>,
+}
+
+impl LinkDevice {
+	fn new(id: u8, incoming: Receiver<LinkEvent>) -> LinkDevice {
+		LinkDevice {
+			id: id,
+			error: false,
+			peers: Vec::new(),
+			incoming: incoming,
+			cycles: 0,
+			last_sync: 0,
+			send_value: 0xFFFF,
+			transfer_request: None,
+			transfer_replied: false,
+			replies: [None; 4],
+			completed: None,
+		}
+	}
+
+	/// Returns true if at least one other GBA is on the other end of the cable.
+	pub fn connected(&self) -> bool {
+		self.peers.iter().any(|p| p.connected)
+	}
+
+	/// Returns the number of GBAs on the cable, including this one.
+	pub fn players(&self) -> usize {
+		1 + self.peers.iter().filter(|p| p.connected).count()
+	}
+
+	pub fn is_parent(&self) -> bool {
+		self.id == 0
+	}
+
+	/// Returns true if the cable failed since the last time this was called.
+	pub fn take_error(&mut self) -> bool {
+		let error = self.error;
+		self.error = false;
+		error
+	}
+
+	pub fn set_send_value(&mut self, value: u16) {
+		self.send_value = value;
+	}
+
+	/// Tells the other players how far we have gotten and waits for them
+	/// if we have gotten too far ahead.
+	pub fn sync(&mut self, cycles: u64) {
+		self.cycles = cycles;
+		if cycles >= self.last_sync + LINK_SYNC_CYCLES {
+			self.last_sync = cycles;
+			self.broadcast(LinkMessage::Sync(cycles));
+		}
+		self.poll();
+
+		while self.connected() && cycles > self.slowest_peer() + LINK_MAX_LEAD {
+			if !self.wait_event() { break }
+		}
+	}
+
+	/// Handles all of the messages that have arrived without blocking.
+	pub fn poll(&mut self) {
+		loop {
+			match self.incoming.try_recv() {
+				Ok(event) => self.handle_event(event),
+				Err(TryRecvError::Empty) => break,
+				Err(TryRecvError::Disconnected) => {
+					self.disconnect_all();
+					break
+				}
+			}
+		}
+		self.try_reply();
+	}
+
+	/// PARENT: Starts a transfer that will complete at `end`.
+	pub fn start_transfer(&mut self, start: u64, end: u64, data: u16) {
+		self.replies = [None; 4];
+		self.replies[0] = Some(data);
+		self.broadcast(LinkMessage::Transfer(start, end, data));
+	}
+
+	/// PARENT: Waits for every child to send its data and then sends
+	/// the result of the transfer to everyone.
+	pub fn finish_transfer(&mut self) -> [u16; 4] {
+		self.poll();
+		while self.connected() && !self.peers.iter().all(|p| !p.connected || self.replies[(p.id & 3) as usize].is_some()) {
+			if !self.wait_event() { break }
+		}
+
+		let mut data = [0xFFFFu16; 4];
+		for idx in 0..4 {
+			if let Some(value) = self.replies[idx] {
+				data[idx] = value;
+			}
+		}
+		self.broadcast(LinkMessage::Complete(data));
+		data
+	}
+
+	/// CHILD: Returns the (start, end) cycles of a transfer that has been started by the parent
+	/// once this GBA has reached the start of it.
+	pub fn take_transfer(&mut self) -> Option<(u64, u64)> {
+		if self.transfer_replied {
+			if let Some(request) = self.transfer_request.take() {
+				return Some(request)
+			}
+		}
+		None
+	}
+
+	/// CHILD: Waits for the result of the transfer from the parent.
+	/// Returns None if the parent went away.
+	pub fn wait_complete(&mut self) -> Option<[u16; 4]> {
+		self.poll();
+		while self.completed.is_none() && self.connected() {
+			if !self.wait_event() { break }
+		}
+		self.completed.take()
+	}
+
+	/// Lets the other end of the cable know that we are going away.
+	pub fn close(&mut self) {
+		self.broadcast(LinkMessage::Disconnect);
+		for peer in self.peers.iter_mut() {
+			peer.connected = false;
+		}
+	}
+
+	fn slowest_peer(&self) -> u64 {
+		self.peers.iter().filter(|p| p.connected).map(|p| p.cycles).min().unwrap_or(self.cycles)
+	}
+
+	/// Blocks until a message arrives. Returns false if nothing arrived in time.
+	fn wait_event(&mut self) -> bool {
+		match self.incoming.recv_timeout(Duration::from_millis(LINK_TIMEOUT_MS)) {
+			Ok(event) => {
+				self.handle_event(event);
+				self.try_reply();
+				true
+			},
+			Err(RecvTimeoutError::Timeout) => {
+				console_error!("Link cable timed out.");
+				debug_warn!("Link cable timed out.");
+				self.disconnect_all();
+				false
+			},
+			Err(RecvTimeoutError::Disconnected) => {
+				self.disconnect_all();
+				false
+			}
+		}
+	}
+
+	/// CHILD: Replies to a transfer started by the parent once we have reached it.
+	fn try_reply(&mut self) {
+		if self.transfer_replied { return }
+		if let Some((start, _)) = self.transfer_request {
+			if self.cycles >= start {
+				let (id, value) = (self.id, self.send_value);
+				self.broadcast(LinkMessage::Reply(id, value));
+				self.transfer_replied = true;
+			}
+		}
+	}
+
+	fn handle_event(&mut self, event: LinkEvent) {
+		match event.message {
+			LinkMessage::Welcome(id) => self.id = id,
+			LinkMessage::Sync(cycles) => {
+				if let Some(peer) = self.peers.iter_mut().find(|p| p.id == event.from) {
+					peer.cycles = cycles;
+				}
+			},
+			LinkMessage::Transfer(start, end, _) => {
+				// The parent has at least reached the start of the transfer.
+				if let Some(peer) = self.peers.iter_mut().find(|p| p.id == event.from) {
+					peer.cycles = max!(peer.cycles, start);
+				}
+				self.transfer_request = Some((start, end));
+				self.transfer_replied = false;
+				self.completed = None;
+			},
+			LinkMessage::Reply(id, value) => self.replies[(id & 3) as usize] = Some(value),
+			LinkMessage::Complete(data) => self.completed = Some(data),
+			LinkMessage::Disconnect => {
+				if let Some(peer) = self.peers.iter_mut().find(|p| p.id == event.from) {
+					if peer.connected {
+						console_warn!("Link cable: player {} disconnected.", peer.id);
+						peer.connected = false;
+					}
+				}
+				self.error = true;
+			}
+		}
+	}
+
+	fn disconnect_all(&mut self) {
+		for peer in self.peers.iter_mut() {
+			peer.connected = false;
+		}
+		self.error = true;
+	}
+
+	fn broadcast(&mut self, message: LinkMessage) {
+		let from = self.id;
+		for peer in self.peers.iter_mut() {
+			if !peer.connected { continue }
+			let sent = match peer.output {
+				LinkOutput::Local(ref sender) => {
+					sender.send(LinkEvent { from: from, message: message.clone() }).is_ok()
+				},
+				LinkOutput::Stream(ref mut writer) => {
+					encode_into(&message, writer, SizeLimit::Infinite).is_ok()
+				}
+			};
+			if !sent {
+				peer.connected = false;
+				self.error = true;
+			}
+		}
+	}
+}
+
+impl Drop for LinkDevice {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
+
+/// Creates a link cable connecting `players` GBAs in the same process.
+/// The first device returned is the parent.
+pub fn local_cable(players: usize) -> Vec<LinkDevice> {
+	let players = max!(2, min!(4, players));
+	let mut senders = Vec::with_capacity(players);
+	let mut devices = Vec::with_capacity(players);
+
+	for id in 0..players {
+		let (sender, receiver) = channel();
+		senders.push(sender);
+		devices.push(LinkDevice::new(id as u8, receiver));
+	}
+
+	for id in 1..players {
+		devices[0].peers.push(LinkPeer {
+			id: id as u8,
+			output: LinkOutput::Local(senders[id].clone()),
+			cycles: 0,
+			connected: true,
+		});
+
+		devices[id].peers.push(LinkPeer {
+			id: 0,
+			output: LinkOutput::Local(senders[0].clone()),
+			cycles: 0,
+			connected: true,
+		});
+	}
+
+	devices
+}
+
+/// Opens a socket at `address` and waits for `children` other GBAs to connect to it.
+/// This GBA will be the parent.
+///
+/// Addresses are either `tcp:<host>:<port>`, `unix:<path>` or just `<host>:<port>`.
+pub fn host(address: &str, children: usize) -> Result<LinkDevice, String> {
+	let children = max!(1, min!(3, children));
+	let (sender, receiver) = channel();
+	let mut device = LinkDevice::new(0, receiver);
+	let mut listener = match try_listen(address) {
+		Ok(listener) => listener,
+		Err(e) => return Err(e),
+	};
+
+	debug_info!("Waiting for {} player(s) on {}...", children, address);
+	for id in 1..(children + 1) {
+		let (reader, mut writer) = match listener.accept() {
+			Ok(streams) => streams,
+			Err(e) => return Err(e),
+		};
+		if encode_into(&LinkMessage::Welcome(id as u8), &mut writer, SizeLimit::Infinite).is_err() {
+			return Err(format!("Failed to welcome player {}.", id));
+		}
+		spawn_reader(id as u8, reader, sender.clone());
+		device.peers.push(LinkPeer {
+			id: id as u8,
+			output: LinkOutput::Stream(writer),
+			cycles: 0,
+			connected: true,
+		});
+		debug_info!("Player {} connected.", id);
+	}
+
+	Ok(device)
+}
+
+/// Connects to a parent that is waiting at `address`.
+pub fn connect(address: &str) -> Result<LinkDevice, String> {
+	let (sender, receiver) = channel();
+	let (mut reader, writer) = match try_connect(address) {
+		Ok(streams) => streams,
+		Err(e) => return Err(e),
+	};
+
+	let id = match decode_from(&mut reader, SizeLimit::Infinite) {
+		Ok(LinkMessage::Welcome(id)) => id,
+		Ok(message) => return Err(format!("Expected a welcome from the parent, got {:?}.", message)),
+		Err(e) => return Err(format!("Failed to read welcome from the parent: {}", e)),
+	};
+
+	let mut device = LinkDevice::new(id, receiver);
+	spawn_reader(0, reader, sender);
+	device.peers.push(LinkPeer {
+		id: 0,
+		output: LinkOutput::Stream(writer),
+		cycles: 0,
+		connected: true,
+	});
+	debug_info!("Connected to {} as player {}.", address, id);
+
+	Ok(device)
+}
+
+fn spawn_reader(from: u8, mut reader: Box<Read + Send>, sender: Sender<LinkEvent>) {
+	thread::Builder::new().name(format!("Link {}", from)).spawn(move || {
+		loop {
+			let message = match decode_from(&mut reader, SizeLimit::Infinite) {
+				Ok(message) => message,
+				Err(_) => LinkMessage::Disconnect,
+			};
+			let disconnect = match message { LinkMessage::Disconnect => true, _ => false };
+			if sender.send(LinkEvent { from: from, message: message }).is_err() || disconnect {
+				break
+			}
+		}
+	}).expect("Failed to start link cable thread.");
+}
+
+type LinkStreams = (Box<Read + Send>, Box<Write + Send>);
+
+enum LinkListener {
+	Tcp(TcpListener),
+	#[cfg(unix)]
+	Unix(UnixListener),
+}
+
+impl LinkListener {
+	fn accept(&mut self) -> Result<LinkStreams, String> {
+		match *self {
+			LinkListener::Tcp(ref listener) => match listener.accept() {
+				Ok((stream, _)) => tcp_streams(stream),
+				Err(e) => Err(format!("Failed to accept link cable connection: {}", e)),
+			},
+			#[cfg(unix)]
+			LinkListener::Unix(ref listener) => match listener.accept() {
+				Ok((stream, _)) => unix_streams(stream),
+				Err(e) => Err(format!("Failed to accept link cable connection: {}", e)),
+			},
+		}
+	}
+}
+
+fn try_listen(address: &str) -> Result<LinkListener, String> {
+	if address.starts_with("unix:") {
+		return listen_unix(&address[5..]);
+	}
+	let address = if address.starts_with("tcp:") { &address[4..] } else { address };
+	match TcpListener::bind(address) {
+		Ok(listener) => Ok(LinkListener::Tcp(listener)),
+		Err(e) => Err(format!("Failed to listen on {}: {}", address, e)),
+	}
+}
+
+fn try_connect(address: &str) -> Result<LinkStreams, String> {
+	if address.starts_with("unix:") {
+		return connect_unix(&address[5..]);
+	}
+	let address = if address.starts_with("tcp:") { &address[4..] } else { address };
+	match TcpStream::connect(address) {
+		Ok(stream) => tcp_streams(stream),
+		Err(e) => Err(format!("Failed to connect to {}: {}", address, e)),
+	}
+}
+
+fn tcp_streams(stream: TcpStream) -> Result<LinkStreams, String> {
+	// Messages are tiny and we're usually waiting on them.
+	let _ = stream.set_nodelay(true);
+	match stream.try_clone() {
+		Ok(reader) => Ok((Box::new(reader), Box::new(stream))),
+		Err(e) => Err(format!("Failed to clone link cable stream: {}", e)),
+	}
+}
+
+#[cfg(unix)]
+fn unix_streams(stream: UnixStream) -> Result<LinkStreams, String> {
+	match stream.try_clone() {
+		Ok(reader) => Ok((Box::new(reader), Box::new(stream))),
+		Err(e) => Err(format!("Failed to clone link cable stream: {}", e)),
+	}
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> Result<LinkListener, String> {
+	let _ = ::std::fs::remove_file(path); // Left over from the last time.
+	match UnixListener::bind(path) {
+		Ok(listener) => Ok(LinkListener::Unix(listener)),
+		Err(e) => Err(format!("Failed to listen on {}: {}", path, e)),
+	}
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> Result<LinkStreams, String> {
+	match UnixStream::connect(path) {
+		Ok(stream) => unix_streams(stream),
+		Err(e) => Err(format!("Failed to connect to {}: {}", path, e)),
+	}
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_: &str) -> Result<LinkListener, String> {
+	Err("Unix sockets are not supported on this platform.".to_string())
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_: &str) -> Result<LinkStreams, String> {
+	Err("Unix sockets are not supported on this platform.".to_string())
+}
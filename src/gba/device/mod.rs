@@ -5,27 +5,155 @@ pub mod video;
 pub mod audio;
 pub mod events;
 pub mod imgui_support;
+pub mod link;
+pub mod multiboot;
+pub mod joybus;
+pub mod uart;
+pub mod filter;
+pub mod screenshot;
+pub mod recorder;
+pub mod audio_dump;
+pub mod resampler;
+pub mod audio_filter;
+pub mod pacer;
+pub mod time_stretch;
+pub mod rewind;
+pub mod movie;
+pub mod bindings;
 
 use self::video::*;
 use self::audio::*;
+use self::link::LinkDevice;
+use self::joybus::JoyBusHost;
+use self::uart::UartDevice;
+use self::filter::VideoFilter;
+use self::recorder::Recorder;
+use self::audio_dump::AudioDump;
+use self::pacer::FramePacer;
+use self::rewind::RewindBuffer;
+use self::movie::{Movie, MovieMode};
+use self::bindings::InputState;
 pub use self::events::*;
 
 pub struct GbaDevice {
-	pub video: VideoDevice,
-	pub audio: AudioDevice
+	/// None when running headless.
+	pub video: Option<VideoDevice>,
+	pub filter: VideoFilter,
+	pub audio: AudioDevice,
+	pub link: Option<LinkDevice>,
+	pub joybus: Option<JoyBusHost>,
+	pub uart: Option<UartDevice>,
+
+	/// Some while gameplay is being recorded to disk.
+	pub recorder: Option<Recorder>,
+
+	/// Some while audio is being dumped to disk.
+	pub audio_dump: Option<AudioDump>,
+
+	pub pacer: FramePacer,
+	pub rewind: RewindBuffer,
+
+	/// What is held on the keyboard.
+	pub input: InputState,
+
+	/// Some while an input movie is recording or playing.
+	pub movie: Option<Movie>,
+
+	/// Some while the APU's output is being kept in memory, before it's filtered or resampled.
+	/// This is what save state checks compare.
+	pub audio_capture: Option<Vec<(i16, i16)>>,
 }
 
 impl GbaDevice {
+	/// A device with the audio output picked in the settings.
 	pub fn new() -> GbaDevice {
+		GbaDevice::with_audio(AudioOutput::from_backend(psetting!(audio_backend)))
+	}
+
+	pub fn with_audio(output: AudioOutput) -> GbaDevice {
 		let mut ret = GbaDevice {
-			video: VideoDevice::new(),
-			audio: AudioDevice::new()
+			video: Some(VideoDevice::new()),
+			filter: VideoFilter::new(),
+			audio: AudioDevice::new(),
+			link: None,
+			joybus: None,
+			uart: None,
+			recorder: None,
+			audio_dump: None,
+			pacer: FramePacer::new(),
+			rewind: RewindBuffer::new(),
+			input: InputState::new(),
+			movie: None,
+			audio_capture: None,
 		};
-		ret.audio.start();
+		ret.audio.start(output);
 		return ret;
 	}
 
+	/// A device without a window or audio output.
+	pub fn headless() -> GbaDevice {
+		GbaDevice {
+			video: None,
+			filter: VideoFilter::new(),
+			audio: AudioDevice::new(),
+			link: None,
+			joybus: None,
+			uart: None,
+			recorder: None,
+			audio_dump: None,
+			pacer: FramePacer::new(),
+			rewind: RewindBuffer::new(),
+			input: InputState::new(),
+			movie: None,
+			audio_capture: None,
+		}
+	}
+
 	pub fn close(&mut self) {
+		self.stop_movie();
+		self.stop_recording();
+		self.stop_audio_dump();
 		self.audio.stop();
+		if let Some(ref mut link) = self.link {
+			link.close();
+		}
+	}
+
+	/// Finishes the current recording, if there is one.
+	pub fn stop_recording(&mut self) {
+		if let Some(recorder) = self.recorder.take() {
+			let path = recorder.video_path().to_path_buf();
+			let (frames, samples) = (recorder.frames, recorder.samples);
+			match recorder.finish() {
+				Ok(_) => console_log!("Recorded {} frames and {} samples to {}", frames, samples, path.display()),
+				Err(e) => console_error!("{}", e),
+			}
+		}
+	}
+
+	/// Stops the current movie, saving it if anything was recorded.
+	pub fn stop_movie(&mut self) {
+		if let Some(movie) = self.movie.take() {
+			if movie.mode == MovieMode::Recording {
+				match movie.save() {
+					Ok(_) => console_log!("Saved a movie with {} frames to {}", movie.frame_count(), movie.path().display()),
+					Err(e) => console_error!("{}", e),
+				}
+			} else {
+				console_log!("Stopped the movie at frame {} of {}", movie.frame, movie.frame_count());
+			}
+		}
+	}
+
+	/// Finishes the current audio dump, if there is one.
+	pub fn stop_audio_dump(&mut self) {
+		if let Some(dump) = self.audio_dump.take() {
+			let path = dump.path().to_path_buf();
+			let samples = dump.samples;
+			match dump.finish() {
+				Ok(_) => console_log!("Dumped {} samples to {}", samples, path.display()),
+				Err(e) => console_error!("{}", e),
+			}
+		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+//! Input movies: the keys that were held on every frame, played back into the
+//! joypad so that a run can be reproduced exactly. Input is only changed at the
+//! start of a frame while a movie is recording or playing so that one value
+//! per frame is enough.
+//!
+//! Movies either start at power-on or from a save state that is embedded in
+//! the file. The file is little endian and laid out like this:
+//!
+//!     Offset  Size  Contents
+//!     0       8     "PYRMOVIE"
+//!     8       2     Format version (1)
+//!     10      4     CRC-32 of the ROM
+//!     14      4     Game code
+//!     18      12    Game title, padded with zeros
+//!     30      1     Start (0 = power-on, 1 = save state)
+//!     31      1     Boot flags (bit 0 = booted through the BIOS, bit 1 = SWIs are emulated)
+//!     32      4     Rerecord count
+//!     36      4     Number of frames (N)
+//!     40      4     Size of the save state (S), 0 when starting at power-on
+//!     44      S     Save state, the same as a save state file
+//!     44 + S  N * 2 One entry per frame: bits 0-9 are KEYINPUT (0 = pressed),
+//!                   bit 15 is set if the game never read KEYINPUT during that frame (a lag frame)
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use ::util::io::create_parent_dir;
+use super::super::cartridge::CartridgeInfo;
+
+const MOVIE_MAGIC: [u8; 8] = [b'P', b'Y', b'R', b'M', b'O', b'V', b'I', b'E'];
+const MOVIE_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 44;
+
+const KEY_MASK: u16 = 0x3ff;
+const LAG_FLAG: u16 = 0x8000;
+
+pub const BOOT_FLAG_BIOS: u8 = 0x1;
+pub const BOOT_FLAG_EMULATE_SWI: u8 = 0x2;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MovieMode {
+	Recording,
+	Playing,
+}
+
+pub struct Movie {
+	path: PathBuf,
+
+	rom_crc: u32,
+	game_code: String,
+	title: String,
+	boot_flags: u8,
+	pub rerecords: u32,
+
+	/// The save state the movie starts from, None if it starts at power-on.
+	start_state: Option<Vec<u8>>,
+
+	/// Set until the start state has been loaded.
+	start_pending: bool,
+
+	/// One entry per frame, see the format above.
+	frames: Vec<u16>,
+
+	/// The frame that is about to run.
+	pub frame: usize,
+
+	pub mode: MovieMode,
+
+	/// In read-only playback the keyboard is ignored, otherwise pressing a key
+	/// cuts the movie off at the current frame and continues recording from there.
+	pub read_only: bool,
+
+	/// What the player was holding at the start of the frame. This only
+	/// reaches the joypad while recording.
+	live_keys: u16,
+
+	/// Lag frames so far and frames where lag didn't match the movie.
+	pub lag_frames: u32,
+	pub desyncs: u32,
+}
+
+impl Movie {
+	/// Starts recording a new movie. `start_state` is the save state the movie
+	/// starts from, or None if the GBA hasn't run yet.
+	pub fn record(path: &Path, cartridge: &CartridgeInfo, boot_flags: u8, start_state: Option<Vec<u8>>) -> Movie {
+		Movie {
+			path: path.to_path_buf(),
+			rom_crc: cartridge.crc,
+			game_code: cartridge.game_code.clone(),
+			title: cartridge.title.clone(),
+			boot_flags: boot_flags,
+			rerecords: 0,
+			start_state: start_state,
+			start_pending: false,
+			frames: Vec::new(),
+			frame: 0,
+			mode: MovieMode::Recording,
+			read_only: false,
+			live_keys: KEY_MASK,
+			lag_frames: 0,
+			desyncs: 0,
+		}
+	}
+
+	/// Loads a movie for playback.
+	pub fn load(path: &Path, read_only: bool) -> Result<Movie, String> {
+		let mut data = Vec::new();
+		let result = match File::open(path) {
+			Ok(mut f) => f.read_to_end(&mut data),
+			Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+		};
+		if let Err(e) = result {
+			return Err(format!("Failed to read {}: {}", path.display(), e))
+		}
+
+		if data.len() < HEADER_SIZE || &data[0..8] != &MOVIE_MAGIC {
+			return Err(format!("{} isn't a Pyrite movie.", path.display()))
+		}
+		let version = read_u16(&data, 8);
+		if version != MOVIE_VERSION {
+			return Err(format!("{} is movie version {} but only version {} is supported.", path.display(), version, MOVIE_VERSION))
+		}
+
+		let frame_count = read_u32(&data, 36) as usize;
+		let state_size = read_u32(&data, 40) as usize;
+		if data.len() < HEADER_SIZE + state_size + frame_count * 2 {
+			return Err(format!("{} is cut off.", path.display()))
+		}
+
+		let start_state = match data[30] {
+			0 => None,
+			1 => Some(data[HEADER_SIZE..(HEADER_SIZE + state_size)].to_vec()),
+			_ => return Err(format!("{} has an unknown start.", path.display())),
+		};
+
+		let frames_start = HEADER_SIZE + state_size;
+		let frames = (0..frame_count).map(|i| read_u16(&data, frames_start + i * 2)).collect();
+
+		Ok(Movie {
+			path: path.to_path_buf(),
+			rom_crc: read_u32(&data, 10),
+			game_code: header_string(&data[14..18]),
+			title: header_string(&data[18..30]),
+			boot_flags: data[31],
+			rerecords: read_u32(&data, 32),
+			start_pending: start_state.is_some(),
+			start_state: start_state,
+			frames: frames,
+			frame: 0,
+			mode: MovieMode::Playing,
+			read_only: read_only,
+			live_keys: KEY_MASK,
+			lag_frames: 0,
+			desyncs: 0,
+		})
+	}
+
+	/// Makes sure the movie was made with the same ROM and boot settings.
+	pub fn check(&self, cartridge: &CartridgeInfo, boot_flags: u8) -> Result<(), String> {
+		if self.rom_crc != cartridge.crc {
+			return Err(format!("The movie was made with a different ROM ({} {} {:08X}) than the one that is loaded ({} {} {:08X}).",
+				self.title, self.game_code, self.rom_crc, cartridge.title, cartridge.game_code, cartridge.crc))
+		}
+		if self.start_state.is_none() && self.boot_flags != boot_flags {
+			return Err(format!("The movie was made with different boot settings ({:02X} instead of {:02X}).", self.boot_flags, boot_flags))
+		}
+		Ok(())
+	}
+
+	pub fn starts_from_state(&self) -> bool {
+		self.start_state.is_some()
+	}
+
+	/// The save state to load before the first frame, returned only once.
+	pub fn take_start_state(&mut self) -> Option<Vec<u8>> {
+		if self.start_pending {
+			self.start_pending = false;
+			self.start_state.clone()
+		} else {
+			None
+		}
+	}
+
+	/// Called with what the keyboard is holding before every frame instead of changing the joypad.
+	pub fn set_live_keys(&mut self, keys: u16) {
+		self.live_keys = keys & KEY_MASK;
+	}
+
+	/// Returns KEYINPUT for the frame that is about to run,
+	/// or None once playback has reached the end of the movie.
+	pub fn begin_frame(&mut self) -> Option<u16> {
+		if self.mode == MovieMode::Playing && !self.read_only && self.live_keys != KEY_MASK {
+			self.frames.truncate(self.frame);
+			self.mode = MovieMode::Recording;
+			self.rerecords += 1;
+			console_log!("Recording from frame {} of the movie.", self.frame);
+		}
+
+		match self.mode {
+			MovieMode::Recording => Some(self.live_keys),
+			MovieMode::Playing => {
+				if self.frame < self.frames.len() {
+					Some(self.frames[self.frame] & KEY_MASK)
+				} else {
+					None
+				}
+			},
+		}
+	}
+
+	/// Called after the frame with the keys it ran with and whether the game read KEYINPUT.
+	pub fn end_frame(&mut self, keys: u16, lagged: bool) {
+		if lagged { self.lag_frames += 1; }
+		let entry = (keys & KEY_MASK) | if lagged { LAG_FLAG } else { 0 };
+		match self.mode {
+			MovieMode::Recording => self.frames.push(entry),
+			MovieMode::Playing => {
+				if (self.frames[self.frame] & LAG_FLAG) != (entry & LAG_FLAG) {
+					if self.desyncs == 0 {
+						console_warn!("The movie may have desynced at frame {}.", self.frame);
+					}
+					self.desyncs += 1;
+				}
+			},
+		}
+		self.frame += 1;
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Writes the movie to its file, this only needs to happen if it was recorded to.
+	pub fn save(&self) -> Result<(), String> {
+		if let Err(e) = create_parent_dir(&self.path) {
+			return Err(e)
+		}
+
+		let mut data = Vec::with_capacity(HEADER_SIZE + self.frames.len() * 2);
+		data.extend_from_slice(&MOVIE_MAGIC);
+		push_u16(&mut data, MOVIE_VERSION);
+		push_u32(&mut data, self.rom_crc);
+		push_padded(&mut data, &self.game_code, 4);
+		push_padded(&mut data, &self.title, 12);
+		data.push(if self.start_state.is_some() { 1 } else { 0 });
+		data.push(self.boot_flags);
+		push_u32(&mut data, self.rerecords);
+		push_u32(&mut data, self.frames.len() as u32);
+		match self.start_state {
+			Some(ref state) => {
+				push_u32(&mut data, state.len() as u32);
+				data.extend_from_slice(state);
+			},
+			None => push_u32(&mut data, 0),
+		}
+		for &entry in self.frames.iter() {
+			push_u16(&mut data, entry);
+		}
+
+		let file = match File::create(&self.path) {
+			Ok(f) => f,
+			Err(e) => return Err(format!("Failed to create {}: {}", self.path.display(), e)),
+		};
+		let mut writer = BufWriter::new(file);
+		match writer.write_all(&data).and_then(|_| writer.flush()) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", self.path.display(), e)),
+		}
+	}
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+	(data[offset] as u16) | ((data[offset + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+	(data[offset] as u32) | ((data[offset + 1] as u32) << 8) | ((data[offset + 2] as u32) << 16) | ((data[offset + 3] as u32) << 24)
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+	data.push(value as u8);
+	data.push((value >> 8) as u8);
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+	data.push(value as u8);
+	data.push((value >> 8) as u8);
+	data.push((value >> 16) as u8);
+	data.push((value >> 24) as u8);
+}
+
+fn push_padded(data: &mut Vec<u8>, s: &str, len: usize) {
+	let bytes = s.as_bytes();
+	for i in 0..len {
+		data.push(if i < bytes.len() { bytes[i] } else { 0 });
+	}
+}
+
+fn header_string(bytes: &[u8]) -> String {
+	bytes.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect()
+}
@@ -0,0 +1,224 @@
+//! A host-side stand-in for a parent GBA that uploads a multiboot (`.mb`) image
+//! to a cartless GBA through the BIOS multiboot protocol in Multi-Player mode.
+//!
+//! The host runs on its own thread with its own emulated clock and talks to
+//! the child through one end of a `local_cable`, so it stays in lockstep with
+//! the child the same way another GBA would.
+
+use std::thread;
+use super::link::{self, LinkDevice, LINK_SYNC_CYCLES};
+use super::super::CLOCK_RATE;
+
+/// One 115200 baud transfer between two GBAs (start bit, 16 data bits, stop bit each).
+const TRANSFER_CYCLES: u64 = (18 * 2 * CLOCK_RATE) / 115200;
+
+/// How long the host waits after a transfer before starting the next one,
+/// this gives the child's BIOS time to handle the serial interrupt.
+const TRANSFER_GAP_CYCLES: u64 = 1232 * 2;
+
+/// 1/16 of a second.
+const DELAY_CYCLES: u64 = CLOCK_RATE / 16;
+
+/// The host gives up if the child doesn't answer within this many seconds.
+const HANDSHAKE_TIMEOUT_SECONDS: u64 = 10;
+
+/// The size of the header that is sent unencrypted before the main data block.
+const HEADER_SIZE: usize = 0xC0;
+
+/// The largest image that fits into EWRAM.
+const MAX_IMAGE_SIZE: usize = 0x40000;
+
+/// palette_data sent with 0x63PP. Bits 0-6 select the logo's color and speed.
+const PALETTE_DATA: u16 = 0xD1;
+
+/// Constants used to encrypt the main data block and to calculate its CRC
+/// in Multi-Player mode, see GBATEK's "BIOS Multi Boot" section.
+const KEY_MULTIPLIER: u32 = 0x6F646573; // "sedo"
+const KEY_XOR: u32 = 0x6465646F; // "oded"
+const CRC_INITIAL: u32 = 0xFFF8;
+const CRC_POLYNOMIAL: u32 = 0xA517;
+
+struct MultibootHost {
+	link: LinkDevice,
+	cycles: u64,
+
+	/// The multi-player ID bit of the child (2, 4 or 8).
+	child_bit: u16,
+}
+
+/// Starts uploading `image` on another thread and returns the end of
+/// the link cable that should be plugged into the child GBA.
+pub fn start(image: Vec<u8>) -> Result<LinkDevice, String> {
+	let image = match prepare_image(image) {
+		Ok(image) => image,
+		Err(e) => return Err(e),
+	};
+
+	let mut cable = link::local_cable(2);
+	let child = cable.pop().expect("local cable has no child");
+	let parent = cable.pop().expect("local cable has no parent");
+
+	let spawned = thread::Builder::new().name("Multiboot Host".to_string()).spawn(move || {
+		let mut host = MultibootHost {
+			link: parent,
+			cycles: 0,
+			child_bit: 2,
+		};
+		match host.upload(&image) {
+			Ok(_) => debug_info!("Multiboot upload complete ({} bytes).", image.len()),
+			Err(e) => {
+				debug_error!("Multiboot upload failed: {}", e);
+				console_error!("Multiboot upload failed: {}", e);
+			}
+		}
+		host.link.close();
+	});
+
+	match spawned {
+		Ok(_) => Ok(child),
+		Err(e) => Err(format!("Failed to start multiboot host thread: {}", e)),
+	}
+}
+
+/// Pads the image to a multiple of 16 bytes and checks that the BIOS will accept its size.
+fn prepare_image(mut image: Vec<u8>) -> Result<Vec<u8>, String> {
+	while (image.len() & 0xF) != 0 || image.len() < (HEADER_SIZE + 0x100) {
+		image.push(0);
+	}
+
+	if image.len() > MAX_IMAGE_SIZE {
+		return Err(format!("Multiboot image is too large ({} bytes, max is {}).", image.len(), MAX_IMAGE_SIZE));
+	}
+	Ok(image)
+}
+
+impl MultibootHost {
+	fn upload(&mut self, image: &[u8]) -> Result<(), String> {
+		match self.recognize_child() {
+			Ok(_) => {},
+			Err(e) => return Err(e),
+		}
+
+		// Header, unencrypted.
+		for idx in 0..(HEADER_SIZE / 2) {
+			let value = (image[idx * 2] as u16) | ((image[idx * 2 + 1] as u16) << 8);
+			// The child replies with NN0x where NN counts the remaining halfwords.
+			let reply = self.transfer(value);
+			if (reply & 0xFF) != self.child_bit {
+				return Err(format!("Unexpected reply to header transfer {}: 0x{:04X}", idx, reply));
+			}
+		}
+		self.transfer(0x6200);
+		self.transfer(0x6200 | self.child_bit);
+
+		// Palette exchange, the child replies with 0x73CC once it is ready.
+		let client_data = match self.transfer_until(0x6300 | PALETTE_DATA, |r| (r & 0xFF00) == 0x7300) {
+			Some(reply) => reply & 0xFF,
+			None => return Err("Child never sent its client data.".to_string()),
+		};
+
+		// Absent children count as 0xFF.
+		let handshake_data = (0x11 + client_data + 0xFF + 0xFF) & 0xFF;
+		self.transfer(0x6400 | handshake_data);
+		self.wait(DELAY_CYCLES);
+
+		let length = ((image.len() - HEADER_SIZE) / 4 - 0x34) as u16;
+		let random_data = self.transfer(length) & 0xFF;
+
+		// Main data block, encrypted.
+		let mut key = 0xFFFF0000 | ((client_data as u32) << 8) | (PALETTE_DATA as u32);
+		let mut crc = CRC_INITIAL;
+		let mut pos = HEADER_SIZE;
+		while pos < image.len() {
+			let value = (image[pos] as u32) | ((image[pos + 1] as u32) << 8);
+			crc = crc_update(crc, value, 16);
+			key = key.wrapping_mul(KEY_MULTIPLIER).wrapping_add(1);
+			let encrypted = value ^ key ^ KEY_XOR ^ 0xFE000000u32.wrapping_sub(pos as u32);
+			self.transfer(encrypted as u16);
+			pos += 2;
+		}
+
+		// Wait for the child to be ready for the CRC.
+		if self.transfer_until(0x0065, |r| r == 0x0075).is_none() {
+			return Err("Child never got ready for the CRC.".to_string());
+		}
+		self.transfer(0x0066);
+
+		let final_value = 0xFFFF0000 | ((random_data as u32) << 8) | (handshake_data as u32);
+		crc = crc_update(crc, final_value, 32);
+		let child_crc = self.transfer((crc & 0xFFFF) as u16);
+		if child_crc as u32 != (crc & 0xFFFF) {
+			return Err(format!("CRC mismatch: host 0x{:04X}, child 0x{:04X}", crc & 0xFFFF, child_crc));
+		}
+
+		Ok(())
+	}
+
+	/// Waits for the child to enter Multi-Player mode and for its BIOS to answer with 0x720x.
+	fn recognize_child(&mut self) -> Result<(), String> {
+		let give_up_at = self.cycles + HANDSHAKE_TIMEOUT_SECONDS * CLOCK_RATE;
+		let mut recognized = 0;
+		while recognized < 15 {
+			if self.cycles > give_up_at || !self.link.connected() {
+				return Err("Child did not respond to the multiboot handshake.".to_string());
+			}
+
+			let reply = self.transfer(0x6200);
+			if (reply & 0xFFF0) == 0x7200 && (reply & 0xE) != 0 {
+				self.child_bit = reply & 0xE;
+				recognized += 1;
+			} else {
+				recognized = 0;
+				self.wait(DELAY_CYCLES);
+			}
+		}
+
+		let reply = self.transfer(0x6100 | self.child_bit);
+		if reply != (0x7200 | self.child_bit) {
+			return Err(format!("Unexpected reply to recognition: 0x{:04X}", reply));
+		}
+		Ok(())
+	}
+
+	/// Sends `value` until the child's reply satisfies `done`.
+	fn transfer_until<F: Fn(u16) -> bool>(&mut self, value: u16, done: F) -> Option<u16> {
+		let give_up_at = self.cycles + HANDSHAKE_TIMEOUT_SECONDS * CLOCK_RATE;
+		while self.cycles < give_up_at && self.link.connected() {
+			let reply = self.transfer(value);
+			if done(reply) { return Some(reply) }
+		}
+		None
+	}
+
+	/// Sends `value` to the child and returns what it sent back.
+	fn transfer(&mut self, value: u16) -> u16 {
+		let start = self.cycles;
+		let end = start + TRANSFER_CYCLES;
+		self.link.start_transfer(start, end, value);
+		self.wait(TRANSFER_CYCLES);
+		let data = self.link.finish_transfer();
+		self.wait(TRANSFER_GAP_CYCLES);
+		data[1]
+	}
+
+	/// Lets `cycles` pass on the host's clock while staying in sync with the child.
+	fn wait(&mut self, cycles: u64) {
+		let target = self.cycles + cycles;
+		while self.cycles < target {
+			self.cycles = min!(target, self.cycles + LINK_SYNC_CYCLES);
+			self.link.sync(self.cycles);
+			if !self.link.connected() { break }
+		}
+	}
+}
+
+/// Feeds the lower `bits` bits of `value` into the CRC.
+fn crc_update(mut crc: u32, mut value: u32, bits: u32) -> u32 {
+	for _ in 0..bits {
+		let bit = (crc ^ value) & 1;
+		crc >>= 1;
+		if bit != 0 { crc ^= CRC_POLYNOMIAL; }
+		value >>= 1;
+	}
+	crc
+}
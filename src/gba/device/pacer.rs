@@ -0,0 +1,156 @@
+//! Keeps the emulator running at the speed of a real GBA (or some multiple of it)
+//! by sleeping between frames. This doesn't depend on vsync, a GBA frame is
+//! 280896 cycles at 16.78MHz which is about 59.7275Hz and no monitor runs at that.
+
+use std::thread;
+use std::time::Duration;
+use time;
+use super::super::{CLOCK_RATE, CYCLES_PER_FRAME};
+
+/// How long a GBA frame lasts in nanoseconds.
+pub const GBA_FRAME_NS: f64 = CYCLES_PER_FRAME as f64 * 1_000_000_000.0 / CLOCK_RATE as f64;
+
+/// When running faster than this, frames stop being presented
+/// every time and are shown at about 60Hz instead.
+const PRESENT_INTERVAL_NS: u64 = 16_000_000;
+
+/// Sleeping is only precise to a millisecond or so, the rest of the wait is spent yielding.
+const SPIN_NS: u64 = 2_000_000;
+
+/// How many frames the pacer can fall behind before it gives up on catching up.
+const MAX_FRAMES_BEHIND: u64 = 4;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FastForwardSpeed {
+	Double,
+	Quadruple,
+	Octuple,
+	/// As fast as the host can go.
+	Unlimited,
+}
+
+pub const FAST_FORWARD_SPEEDS: [FastForwardSpeed; 4] = [
+	FastForwardSpeed::Double,
+	FastForwardSpeed::Quadruple,
+	FastForwardSpeed::Octuple,
+	FastForwardSpeed::Unlimited,
+];
+
+impl FastForwardSpeed {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			FastForwardSpeed::Double => "2x",
+			FastForwardSpeed::Quadruple => "4x",
+			FastForwardSpeed::Octuple => "8x",
+			FastForwardSpeed::Unlimited => "Unlimited",
+		}
+	}
+
+	/// None for unlimited.
+	pub fn multiplier(&self) -> Option<f64> {
+		match *self {
+			FastForwardSpeed::Double => Some(2.0),
+			FastForwardSpeed::Quadruple => Some(4.0),
+			FastForwardSpeed::Octuple => Some(8.0),
+			FastForwardSpeed::Unlimited => None,
+		}
+	}
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FastForwardAudio {
+	/// Plays the audio at the normal pitch by skipping parts of it.
+	Stretch,
+	Mute,
+}
+
+pub const FAST_FORWARD_AUDIO_MODES: [FastForwardAudio; 2] = [
+	FastForwardAudio::Stretch,
+	FastForwardAudio::Mute,
+];
+
+impl FastForwardAudio {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			FastForwardAudio::Stretch => "Time Stretch",
+			FastForwardAudio::Mute => "Mute",
+		}
+	}
+}
+
+pub struct FramePacer {
+	/// When the next frame should start (time::precise_time_ns).
+	next_frame: u64,
+
+	/// When the last frame started.
+	last_frame: u64,
+
+	/// When a frame was last shown.
+	last_present: u64,
+
+	/// How fast the emulator is actually running compared to a GBA, smoothed.
+	pub measured_speed: f64,
+}
+
+impl FramePacer {
+	pub fn new() -> FramePacer {
+		let now = time::precise_time_ns();
+		FramePacer {
+			next_frame: now,
+			last_frame: now,
+			last_present: 0,
+			measured_speed: 1.0,
+		}
+	}
+
+	/// Waits until it's time to start the next frame at `speed` times the speed
+	/// of a GBA, or doesn't wait at all if `speed` is None.
+	pub fn wait(&mut self, speed: Option<f64>) {
+		let now = time::precise_time_ns();
+		match speed {
+			Some(speed) => {
+				let duration = (GBA_FRAME_NS / speed) as u64;
+				self.next_frame += duration;
+				if now > self.next_frame + duration * MAX_FRAMES_BEHIND || self.next_frame > now + duration * 2 {
+					// Too far behind (or the speed just went down), start over from now.
+					self.next_frame = now + duration;
+				}
+
+				loop {
+					let now = time::precise_time_ns();
+					if now >= self.next_frame { break }
+					let remaining = self.next_frame - now;
+					if remaining > SPIN_NS {
+						thread::sleep(Duration::new(0, (remaining - SPIN_NS / 2) as u32));
+					} else {
+						thread::yield_now();
+					}
+				}
+			},
+			None => {
+				self.next_frame = now;
+			}
+		}
+
+		let frame_start = time::precise_time_ns();
+		let elapsed = max!(1, frame_start - self.last_frame);
+		self.last_frame = frame_start;
+		let speed = GBA_FRAME_NS / elapsed as f64;
+		self.measured_speed += (speed - self.measured_speed) * 0.1;
+	}
+
+	/// Whether the frame that was just emulated should be shown. Frames are always shown
+	/// at normal speed or slower, but faster than that there would be no point in
+	/// showing more frames than the screen can.
+	pub fn should_present(&mut self, speed: Option<f64>) -> bool {
+		let now = time::precise_time_ns();
+		let present = match speed {
+			Some(speed) if speed <= 1.0 => true,
+			_ => now - self.last_present >= PRESENT_INTERVAL_NS,
+		};
+		if present {
+			self.last_present = now;
+		}
+		present
+	}
+}
@@ -0,0 +1,163 @@
+//! Records gameplay to disk as a YUV4MPEG2 video and a WAV file.
+//! Everything is captured in emulated time, one video frame for every
+//! frame the GBA draws and as many audio samples as the emulated time
+//! that passed, so recordings don't depend on how fast the host is.
+//!
+//! Video is written as 4:4:4 so no color information is thrown away
+//! except for rounding. Frames are recorded at 240x160 unless filtered
+//! recordings are turned on in the settings, then they go through the same
+//! filters as the screen at the size the filters had when the recording started.
+//! Something like this will turn it into a regular video:
+//!
+//!     ffmpeg -i rec.y4m -i rec.wav -c:v libx264 -crf 0 -c:a flac rec.mkv
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use ::util::wav::WavWriter;
+use ::util::io::create_parent_dir;
+use super::super::hw::lcd::{GbaLcdScreenBuffer, OutputPixel};
+use super::filter::{self, FilteredFrame};
+use super::super::{CLOCK_RATE, CYCLES_PER_FRAME};
+
+const GBA_SCREEN_WIDTH: usize = 240;
+const GBA_SCREEN_HEIGHT: usize = 160;
+
+pub struct Recorder {
+	video: BufWriter<File>,
+	audio: WavWriter<BufWriter<File>>,
+	video_path: PathBuf,
+	audio_path: PathBuf,
+
+	/// True if frames go through the filters before they're written.
+	pub filtered: bool,
+	width: usize,
+	height: usize,
+
+	/// Reused while converting frames.
+	plane_buffer: Vec<u8>,
+
+	pub frames: u64,
+	pub samples: u64,
+}
+
+impl Recorder {
+	/// Starts a recording. `path` is the video file,
+	/// the audio goes next to it with a .wav extension.
+	pub fn new(path: &Path, sample_rate: u32, filtered: bool) -> Result<Recorder, String> {
+		let (width, height) = if filtered {
+			filter::output_size()
+		} else {
+			(GBA_SCREEN_WIDTH, GBA_SCREEN_HEIGHT)
+		};
+
+		if let Err(e) = create_parent_dir(path) {
+			return Err(e)
+		}
+
+		let video_path = path.to_path_buf();
+		let audio_path = path.with_extension("wav");
+
+		let mut video = match File::create(&video_path) {
+			Ok(f) => BufWriter::new(f),
+			Err(e) => return Err(format!("Failed to create {}: {}", video_path.display(), e)),
+		};
+
+		// 59.7275 Hz is 16777216 / 280896.
+		let header = format!("YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+			width, height, CLOCK_RATE, CYCLES_PER_FRAME);
+		if let Err(e) = video.write_all(header.as_bytes()) {
+			return Err(format!("Failed to write {}: {}", video_path.display(), e))
+		}
+
+		let audio_file = match File::create(&audio_path) {
+			Ok(f) => BufWriter::new(f),
+			Err(e) => return Err(format!("Failed to create {}: {}", audio_path.display(), e)),
+		};
+		let audio = match WavWriter::new(audio_file, 2, sample_rate) {
+			Ok(w) => w,
+			Err(e) => return Err(format!("Failed to write {}: {}", audio_path.display(), e)),
+		};
+
+		Ok(Recorder {
+			video: video,
+			audio: audio,
+			video_path: video_path,
+			audio_path: audio_path,
+			filtered: filtered,
+			width: width,
+			height: height,
+			plane_buffer: Vec::with_capacity(width * height * 3),
+			frames: 0,
+			samples: 0,
+		})
+	}
+
+	pub fn video_path(&self) -> &Path {
+		&self.video_path
+	}
+
+	pub fn write_audio(&mut self, frames: &[(i16, i16)]) -> Result<(), String> {
+		self.samples += frames.len() as u64;
+		match self.audio.write_stereo(frames) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", self.audio_path.display(), e)),
+		}
+	}
+
+	/// Writes a frame exactly as it came out of the LCD.
+	pub fn write_native(&mut self, buffer: &GbaLcdScreenBuffer) -> Result<(), String> {
+		self.write_frame(GBA_SCREEN_WIDTH, GBA_SCREEN_HEIGHT, |x, y| buffer[y][x])
+	}
+
+	/// Writes a frame that has gone through the filters.
+	pub fn write_filtered(&mut self, frame: &FilteredFrame) -> Result<(), String> {
+		self.write_frame(frame.width, frame.height, |x, y| frame.pixels[y * frame.width + x])
+	}
+
+	/// Converts the frame to BT.601 (limited range) Y'CbCr and writes it.
+	fn write_frame<F: Fn(usize, usize) -> OutputPixel>(&mut self, width: usize, height: usize, pixel: F) -> Result<(), String> {
+		if width != self.width || height != self.height {
+			return Err(format!("Stopped recording to {}, the frames changed size from {}x{} to {}x{}.",
+				self.video_path.display(), self.width, self.height, width, height))
+		}
+
+		let plane = width * height;
+		self.plane_buffer.clear();
+		self.plane_buffer.resize(plane * 3, 0);
+
+		for y in 0..height {
+			for x in 0..width {
+				let (r, g, b) = pixel(x, y);
+				let (r, g, b) = (r as i32, g as i32, b as i32);
+				let index = y * width + x;
+				self.plane_buffer[index] = ((( 66 * r + 129 * g +  25 * b + 128) >> 8) + 16) as u8;
+				self.plane_buffer[plane + index] = (((-38 * r -  74 * g + 112 * b + 128) >> 8) + 128) as u8;
+				self.plane_buffer[plane * 2 + index] = (((112 * r -  94 * g -  18 * b + 128) >> 8) + 128) as u8;
+			}
+		}
+
+		self.frames += 1;
+		let result = match self.video.write_all(b"FRAME\n") {
+			Ok(_) => self.video.write_all(&self.plane_buffer),
+			Err(e) => Err(e),
+		};
+		match result {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", self.video_path.display(), e)),
+		}
+	}
+
+	/// Finishes both files. The WAV file isn't valid until this is called.
+	pub fn finish(mut self) -> Result<(), String> {
+		if let Err(e) = self.video.flush() {
+			return Err(format!("Failed to write {}: {}", self.video_path.display(), e))
+		}
+		match self.audio.finish() {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to write {}: {}", self.audio_path.display(), e)),
+		}
+	}
+}
@@ -0,0 +1,204 @@
+//! Band-limited resampling from the rate the APU outputs at (selected by SOUNDBIAS,
+//! 32768Hz to 262144Hz) to the rate of the audio output.
+//! Every output sample is a windowed sinc interpolation of the samples around it,
+//! with the cutoff below whichever of the two Nyquist frequencies is lower so that
+//! nothing above it folds back down into the audible range.
+
+use std::f64::consts::PI;
+use super::audio::clip;
+
+/// Points in the kernel table for every input sample.
+const KERNEL_RESOLUTION: usize = 64;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ResamplingQuality {
+	/// Picks the closest sample without any filtering. This aliases.
+	Nearest,
+	Low,
+	Medium,
+	High,
+}
+
+pub const RESAMPLING_QUALITIES: [ResamplingQuality; 4] = [
+	ResamplingQuality::Nearest,
+	ResamplingQuality::Low,
+	ResamplingQuality::Medium,
+	ResamplingQuality::High,
+];
+
+impl ResamplingQuality {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			ResamplingQuality::Nearest => "Nearest (Aliased)",
+			ResamplingQuality::Low => "Low",
+			ResamplingQuality::Medium => "Medium",
+			ResamplingQuality::High => "High",
+		}
+	}
+
+	/// Zero crossings on each side of the sinc.
+	fn zero_crossings(&self) -> usize {
+		match *self {
+			ResamplingQuality::Nearest => 0,
+			ResamplingQuality::Low => 4,
+			ResamplingQuality::Medium => 8,
+			ResamplingQuality::High => 16,
+		}
+	}
+}
+
+/// Resamples one stereo stream.
+pub struct StreamResampler {
+	/// One side of the kernel, `KERNEL_RESOLUTION` points for every input sample.
+	/// Empty for nearest sampling.
+	kernel: Vec<f32>,
+
+	/// Input samples on each side of an output sample that the kernel reaches.
+	half_width: usize,
+
+	/// Input samples for every output sample.
+	step: f64,
+
+	/// `step` before any rate adjustment.
+	base_step: f64,
+
+	input: Vec<(f32, f32)>,
+
+	/// Where the next output sample is in `input`.
+	position: f64,
+}
+
+impl StreamResampler {
+	pub fn new(quality: ResamplingQuality, input_rate: u32, output_rate: u32) -> StreamResampler {
+		let zero_crossings = quality.zero_crossings();
+		let mut kernel = Vec::new();
+		let mut half_width = 0;
+
+		if zero_crossings > 0 {
+			// in cycles per input sample, 90% of the lower Nyquist frequency.
+			let cutoff = 0.45 * min!(1.0, output_rate as f64 / input_rate as f64);
+			let width = zero_crossings as f64 / (2.0 * cutoff);
+			half_width = width.ceil() as usize;
+
+			for i in 0..(half_width * KERNEL_RESOLUTION + 1) {
+				let x = i as f64 / KERNEL_RESOLUTION as f64;
+				if x >= width {
+					kernel.push(0.0);
+					continue
+				}
+				let sinc = if i == 0 { 1.0 } else { (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x) };
+				let w = PI * x / width;
+				let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+				kernel.push((sinc * window) as f32);
+			}
+		}
+
+		StreamResampler {
+			kernel: kernel,
+			half_width: half_width,
+			step: input_rate as f64 / output_rate as f64,
+			base_step: input_rate as f64 / output_rate as f64,
+			input: vec![(0.0, 0.0); half_width],
+			position: half_width as f64,
+		}
+	}
+
+	/// Speeds the output up or slows it down by a tiny bit so it can keep up with a
+	/// sound card that runs a bit slower or faster than it says it does.
+	/// An adjustment of 0.001 makes 0.1% more output samples.
+	pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+		self.step = self.base_step / (1.0 + adjustment);
+	}
+
+	/// Adds a sample at the input rate.
+	#[inline]
+	pub fn push(&mut self, frame: (i16, i16)) {
+		self.input.push((frame.0 as f32, frame.1 as f32));
+	}
+
+	/// Returns the next sample at the output rate once there is enough input for it.
+	pub fn pop(&mut self) -> Option<(i16, i16)> {
+		let center = self.position.floor() as usize;
+		if center + self.half_width + 1 >= self.input.len() {
+			return None
+		}
+
+		let frame = if self.kernel.is_empty() {
+			let (l, r) = self.input[center];
+			(l as i16, r as i16)
+		} else {
+			let mut left = 0.0f32;
+			let mut right = 0.0f32;
+			let mut total = 0.0f32;
+			for index in (center + 1 - self.half_width)..(center + self.half_width + 1) {
+				let weight = self.weight((index as f64 - self.position).abs());
+				let (l, r) = self.input[index];
+				left += l * weight;
+				right += r * weight;
+				total += weight;
+			}
+			// Dividing by the sum of the weights keeps DC from wobbling between phases.
+			if total != 0.0 {
+				left /= total;
+				right /= total;
+			}
+			(clip(left), clip(right))
+		};
+
+		self.position += self.step;
+
+		// Throw away input that no output sample can reach anymore.
+		let used = self.position.floor() as usize;
+		if used > self.half_width + 1024 {
+			let drop = used - self.half_width;
+			self.input.drain(..drop);
+			self.position -= drop as f64;
+		}
+
+		Some(frame)
+	}
+
+	/// The kernel at `x` input samples from the center, linearly interpolated.
+	#[inline(always)]
+	fn weight(&self, x: f64) -> f32 {
+		let t = x * KERNEL_RESOLUTION as f64;
+		let i = t as usize;
+		if i + 1 >= self.kernel.len() { return 0.0 }
+		let frac = (t - i as f64) as f32;
+		self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * frac
+	}
+}
+
+/// Resamplers for the final mix and for the channel stems.
+/// The mix is resampled twice: `live` goes to the audio output and has its rate
+/// nudged around to keep the output buffer filled, `mix` goes to recordings and
+/// dumps and always runs at exactly the output rate.
+pub struct Resampler {
+	pub quality: ResamplingQuality,
+	pub input_rate: u32,
+	pub output_rate: u32,
+	pub live: StreamResampler,
+	pub mix: StreamResampler,
+	pub stems: Vec<StreamResampler>,
+}
+
+impl Resampler {
+	pub fn new(quality: ResamplingQuality, input_rate: u32, output_rate: u32, stem_count: usize) -> Resampler {
+		Resampler {
+			quality: quality,
+			input_rate: input_rate,
+			output_rate: output_rate,
+			live: StreamResampler::new(quality, input_rate, output_rate),
+			mix: StreamResampler::new(quality, input_rate, output_rate),
+			stems: (0..stem_count).map(|_| StreamResampler::new(quality, input_rate, output_rate)).collect(),
+		}
+	}
+
+	/// Rebuilds the resamplers if the quality or the rates changed.
+	pub fn configure(&mut self, quality: ResamplingQuality, input_rate: u32, output_rate: u32) {
+		if self.quality != quality || self.input_rate != input_rate || self.output_rate != output_rate {
+			let stem_count = self.stems.len();
+			*self = Resampler::new(quality, input_rate, output_rate, stem_count);
+		}
+	}
+}
@@ -0,0 +1,190 @@
+//! Keeps snapshots of the last few seconds of gameplay in memory so that they
+//! can be played back in reverse. Only the newest snapshot is kept whole,
+//! every older one is stored as the XOR of itself and the one after it.
+//! Most of the state doesn't change from one snapshot to the next so those
+//! deltas are mostly zeros, and the runs of zeros are left out.
+//!
+//! Snapshots are only taken every few frames, rewinding goes back one frame at
+//! a time by loading the snapshot before the frame it wants and running up to it
+//! with the keys that were held when those frames were first played.
+
+use std::collections::VecDeque;
+use std::mem;
+
+pub struct RewindBuffer {
+	/// The newest snapshot.
+	latest: Vec<u8>,
+
+	/// Encoded deltas, oldest first, and the keys of every frame between each
+	/// snapshot and the next. Applying the last one to `latest` gives the snapshot before it.
+	deltas: VecDeque<(Vec<u8>, Vec<u16>)>,
+
+	/// Bytes used by `latest` and all of the deltas.
+	memory: usize,
+
+	/// The keys of every frame since the newest snapshot.
+	keys: Vec<u16>,
+
+	/// Set while a rewound frame is being run, so that it doesn't end up in recordings.
+	pub active: bool,
+}
+
+impl RewindBuffer {
+	pub fn new() -> RewindBuffer {
+		RewindBuffer {
+			latest: Vec::new(),
+			deltas: VecDeque::new(),
+			memory: 0,
+			keys: Vec::new(),
+			active: false,
+		}
+	}
+
+	/// Records the keys that were held during a frame. Returns true once
+	/// every `interval` frames, when a snapshot should be taken.
+	pub fn frame(&mut self, interval: u32, keys: u16) -> bool {
+		self.keys.push(keys);
+		self.keys.len() >= interval as usize
+	}
+
+	/// Adds a snapshot, throwing away the oldest ones while there are
+	/// more than `max_snapshots` or they take more than `max_memory` bytes.
+	pub fn push(&mut self, snapshot: Vec<u8>, max_snapshots: usize, max_memory: usize) {
+		if !self.latest.is_empty() {
+			let delta = encode_delta(&self.latest, &snapshot);
+			self.memory += delta.len();
+			let keys = mem::replace(&mut self.keys, Vec::new());
+			self.deltas.push_back((delta, keys));
+		}
+		self.memory -= self.latest.len();
+		self.memory += snapshot.len();
+		self.latest = snapshot;
+		self.keys.clear();
+
+		while self.deltas.len() + 1 > max_snapshots || (self.memory > max_memory && !self.deltas.is_empty()) {
+			match self.deltas.pop_front() {
+				Some((delta, _)) => self.memory -= delta.len(),
+				None => break,
+			}
+		}
+	}
+
+	/// Goes back one frame. Returns the snapshot to load and the keys of the frames to
+	/// run from it to get to the frame before the current one, there's always at least one
+	/// so that there is something to show. Once there is nothing older than the oldest
+	/// snapshot this keeps returning it and the frame after it.
+	pub fn step_back(&mut self) -> Option<(Vec<u8>, Vec<u16>)> {
+		if self.latest.is_empty() {
+			return None
+		}
+
+		// The frame to go back to, counted from the newest snapshot.
+		let mut target = self.keys.len() as i32 - 1;
+		while target < 1 {
+			match self.deltas.pop_back() {
+				Some((delta, keys)) => {
+					let previous = decode_delta(&self.latest, &delta);
+					self.memory -= delta.len();
+					self.memory -= self.latest.len();
+					self.memory += previous.len();
+					self.latest = previous;
+					target += keys.len() as i32;
+					self.keys = keys;
+				},
+				None => {
+					// Nothing was played after the only snapshot left.
+					if self.keys.is_empty() { return None }
+					target = 1;
+				},
+			}
+		}
+
+		self.keys.truncate(target as usize);
+		Some((self.latest.clone(), self.keys.clone()))
+	}
+
+	pub fn clear(&mut self) {
+		self.latest = Vec::new();
+		self.deltas.clear();
+		self.memory = 0;
+		self.keys.clear();
+	}
+
+	/// The number of snapshots in the buffer.
+	pub fn len(&self) -> usize {
+		if self.latest.is_empty() { 0 } else { self.deltas.len() + 1 }
+	}
+
+	pub fn memory(&self) -> usize {
+		self.memory
+	}
+}
+
+/// Encodes `a ^ b` as the length of `a` followed by a list of (zero run length,
+/// literal length, literal bytes) with the lengths as little endian u32s.
+pub fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+	let len = max!(a.len(), b.len());
+	let byte = |index: usize| -> u8 {
+		let x = if index < a.len() { a[index] } else { 0 };
+		let y = if index < b.len() { b[index] } else { 0 };
+		x ^ y
+	};
+
+	let mut out = Vec::new();
+	push_u32(&mut out, a.len() as u32);
+
+	let mut index = 0;
+	while index < len {
+		let zeros_start = index;
+		while index < len && byte(index) == 0 { index += 1; }
+		let zeros = index - zeros_start;
+
+		let literal_start = index;
+		// A couple of zeros in the middle of a literal are cheaper than starting a new run.
+		while index < len && (byte(index) != 0 || (index + 8 < len && (1..9).any(|i| byte(index + i) != 0))) {
+			index += 1;
+		}
+
+		push_u32(&mut out, zeros as u32);
+		push_u32(&mut out, (index - literal_start) as u32);
+		for i in literal_start..index {
+			out.push(byte(i));
+		}
+	}
+	out
+}
+
+/// Applies a delta from `encode_delta` to `b` and returns `a`.
+pub fn decode_delta(b: &[u8], delta: &[u8]) -> Vec<u8> {
+	let mut cursor = 0;
+	let len = read_u32(delta, &mut cursor) as usize;
+	let mut out = b.to_vec();
+	out.resize(len, 0);
+
+	let mut index = 0;
+	while cursor < delta.len() {
+		index += read_u32(delta, &mut cursor) as usize;
+		let literal = read_u32(delta, &mut cursor) as usize;
+		for i in 0..literal {
+			if index + i < out.len() {
+				out[index + i] ^= delta[cursor + i];
+			}
+		}
+		cursor += literal;
+		index += literal;
+	}
+	out
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+	out.push(value as u8);
+	out.push((value >> 8) as u8);
+	out.push((value >> 16) as u8);
+	out.push((value >> 24) as u8);
+}
+
+fn read_u32(data: &[u8], cursor: &mut usize) -> u32 {
+	let c = *cursor;
+	*cursor += 4;
+	(data[c] as u32) | ((data[c + 1] as u32) << 8) | ((data[c + 2] as u32) << 16) | ((data[c + 3] as u32) << 24)
+}
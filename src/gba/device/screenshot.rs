@@ -0,0 +1,54 @@
+//! Saving frames as PNG files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use time;
+
+use ::util::png;
+use ::util::io::create_parent_dir;
+use super::super::hw::lcd::GbaLcdScreenBuffer;
+use super::filter::FilteredFrame;
+
+/// Saves a frame exactly as it came out of the LCD.
+pub fn save_native(buffer: &GbaLcdScreenBuffer, path: &Path) -> Result<(), String> {
+	let mut pixels = Vec::with_capacity(240 * 160);
+	for line in buffer.iter() {
+		pixels.extend_from_slice(&line[..]);
+	}
+	save_pixels(240, 160, &pixels, path)
+}
+
+/// Saves a frame that has gone through the filters.
+pub fn save_filtered(frame: &FilteredFrame, path: &Path) -> Result<(), String> {
+	save_pixels(frame.width, frame.height, &frame.pixels, path)
+}
+
+fn save_pixels(width: usize, height: usize, pixels: &[(u8, u8, u8)], path: &Path) -> Result<(), String> {
+	if let Err(e) = create_parent_dir(path) {
+		return Err(e)
+	}
+
+	let file = match File::create(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+	};
+	let mut writer = BufWriter::new(file);
+	match png::write_png(&mut writer, width, height, pixels) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+	}
+}
+
+/// Returns a path in `directory` named after the current time,
+/// e.g. `pyrite-20161019-153012.png`. A number is added if the file already exists.
+pub fn timestamped_path(directory: &str, extension: &str) -> PathBuf {
+	let stamp = time::strftime("%Y%m%d-%H%M%S", &time::now()).unwrap_or_else(|_| "screenshot".to_string());
+	let mut path = Path::new(directory).join(format!("pyrite-{}.{}", stamp, extension));
+	let mut n = 1;
+	while path.exists() {
+		path = Path::new(directory).join(format!("pyrite-{}-{}.{}", stamp, n, extension));
+		n += 1;
+	}
+	path
+}
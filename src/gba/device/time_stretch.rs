@@ -0,0 +1,80 @@
+//! Changes how long audio lasts without changing its pitch, for fast-forward and
+//! slow motion. Short overlapping grains of the input are windowed and added back
+//! together further apart (slow motion) or closer together (fast-forward).
+//! This isn't as clean as a proper time stretch but it's cheap and good enough
+//! to tell what a game sounds like while it's running at the wrong speed.
+
+use std::f32::consts::PI;
+use super::audio::clip;
+
+/// Samples in a grain, about 23ms at 44.1KHz.
+const GRAIN_SIZE: usize = 1024;
+
+/// Grains overlap by half so the Hann windows add up to 1.
+const HOP_SIZE: usize = GRAIN_SIZE / 2;
+
+pub struct TimeStretcher {
+	window: Vec<f32>,
+
+	input: Vec<(f32, f32)>,
+
+	/// Where the next grain starts in `input`.
+	position: f64,
+
+	/// The second half of the last grain, which the next grain gets added to.
+	overlap: Vec<(f32, f32)>,
+}
+
+impl TimeStretcher {
+	pub fn new() -> TimeStretcher {
+		let window = (0..GRAIN_SIZE).map(|i| {
+			0.5 - 0.5 * (2.0 * PI * i as f32 / GRAIN_SIZE as f32).cos()
+		}).collect();
+
+		TimeStretcher {
+			window: window,
+			input: Vec::new(),
+			position: 0.0,
+			overlap: vec![(0.0, 0.0); HOP_SIZE],
+		}
+	}
+
+	/// Throws away everything that's waiting to be stretched.
+	pub fn reset(&mut self) {
+		self.input.clear();
+		self.position = 0.0;
+		for s in self.overlap.iter_mut() { *s = (0.0, 0.0); }
+	}
+
+	/// Stretches `input` so that it plays `speed` times as fast and adds the result
+	/// to `output`. Input that isn't enough for a whole grain waits for the next call.
+	pub fn process(&mut self, input: &[(i16, i16)], speed: f64, output: &mut Vec<(i16, i16)>) {
+		for &(l, r) in input.iter() {
+			self.input.push((l as f32, r as f32));
+		}
+
+		let step = HOP_SIZE as f64 * speed;
+		while (self.position as usize) + GRAIN_SIZE <= self.input.len() {
+			let start = self.position as usize;
+			for i in 0..HOP_SIZE {
+				let (l, r) = self.input[start + i];
+				let w = self.window[i];
+				let (ol, or) = self.overlap[i];
+				output.push((clip(ol + l * w), clip(or + r * w)));
+			}
+			for i in 0..HOP_SIZE {
+				let (l, r) = self.input[start + HOP_SIZE + i];
+				let w = self.window[HOP_SIZE + i];
+				self.overlap[i] = (l * w, r * w);
+			}
+			self.position += step;
+		}
+
+		// Throw away input that no grain will use anymore.
+		let used = min!(self.position as usize, self.input.len());
+		if used > 0 {
+			self.input.drain(..used);
+			self.position -= used as f64;
+		}
+	}
+}
@@ -0,0 +1,113 @@
+//! The host end of the serial port in UART mode.
+//! Bytes sent by the GBA go to a sink (stdout, a file or the debugger console)
+//! and bytes injected by the host are received by the GBA.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+pub enum UartSink {
+	Stdout,
+	File(File),
+	/// Lines are logged to the debugger's console.
+	Console,
+}
+
+pub struct UartDevice {
+	sink: UartSink,
+
+	/// Bytes that have not been written to the console yet because the line isn't finished.
+	console_line: Vec<u8>,
+
+	/// Bytes waiting to be received by the GBA.
+	input: VecDeque<u8>,
+
+	/// Bytes read from stdin on another thread.
+	stdin: Option<Receiver<u8>>,
+}
+
+impl UartDevice {
+	pub fn new(sink: UartSink) -> UartDevice {
+		UartDevice {
+			sink: sink,
+			console_line: Vec::new(),
+			input: VecDeque::new(),
+			stdin: None,
+		}
+	}
+
+	/// Parses a sink description: `stdout`, `console` or `file:<path>`.
+	pub fn from_description(description: &str) -> Result<UartDevice, String> {
+		let sink = if description == "stdout" {
+			UartSink::Stdout
+		} else if description == "console" {
+			UartSink::Console
+		} else if description.starts_with("file:") {
+			let path = &description[5..];
+			match File::create(path) {
+				Ok(f) => UartSink::File(f),
+				Err(e) => return Err(format!("Failed to create UART output file {}: {}", path, e)),
+			}
+		} else {
+			return Err(format!("Unknown UART sink `{}` (expected stdout, console or file:<path>)", description));
+		};
+		Ok(UartDevice::new(sink))
+	}
+
+	/// Called when the GBA has finished sending a byte.
+	pub fn send(&mut self, byte: u8) {
+		match self.sink {
+			UartSink::Stdout => {
+				let stdout = io::stdout();
+				let mut handle = stdout.lock();
+				let _ = handle.write_all(&[byte]);
+				if byte == b'\n' { let _ = handle.flush(); }
+			},
+			UartSink::File(ref mut f) => {
+				let _ = f.write_all(&[byte]);
+			},
+			UartSink::Console => {
+				if byte == b'\n' {
+					let line = String::from_utf8_lossy(&self.console_line).into_owned();
+					console_log!("[UART] {}", line);
+					self.console_line.clear();
+				} else if byte != b'\r' {
+					self.console_line.push(byte);
+				}
+			}
+		}
+	}
+
+	/// Queues bytes for the GBA to receive.
+	pub fn inject(&mut self, bytes: &[u8]) {
+		self.input.extend(bytes.iter().cloned());
+	}
+
+	/// Forwards everything typed into stdin to the GBA.
+	pub fn inject_stdin(&mut self) {
+		let (sender, receiver) = channel();
+		thread::Builder::new().name("UART stdin".to_string()).spawn(move || {
+			let stdin = io::stdin();
+			for byte in stdin.lock().bytes() {
+				match byte {
+					Ok(byte) => if sender.send(byte).is_err() { break },
+					Err(_) => break,
+				}
+			}
+		}).expect("Failed to start UART stdin thread.");
+		self.stdin = Some(receiver);
+	}
+
+	/// Returns the next byte for the GBA to receive if there is one.
+	pub fn receive(&mut self) -> Option<u8> {
+		if let Some(ref stdin) = self.stdin {
+			while let Ok(byte) = stdin.try_recv() {
+				self.input.push_back(byte);
+			}
+		}
+		self.input.pop_front()
+	}
+}
@@ -4,10 +4,12 @@ use std::mem;
 use std::ptr;
 use super::imgui_support;
 use super::imgui_support::ImGuiSupport;
-use super::super::hw::lcd::GbaLcdScreenBuffer;
+use super::filter::FilteredFrame;
 // use ::util::frame_counter::FrameCounter;
 
-const ENABLE_VSYNC: bool = true;
+/// Off because the frame pacer already runs frames at the GBA's 59.7275Hz,
+/// waiting for vsync as well would make the two beat against each other.
+const ENABLE_VSYNC: bool = false;
 const GBA_SCREEN_WIDTH: u32 = 240;
 const GBA_SCREEN_HEIGHT: u32 = 160;
 
@@ -28,6 +30,9 @@ pub struct VideoDevice {
 	pub gl_shader_program_handle: u32,
 	pub gl_texture_handle: u32,
 	pub gl_attrib_location_tex: u32,
+
+	/// The size of the screen texture, it changes with the filters' scale.
+	pub texture_size: (usize, usize),
 }
 
 impl VideoDevice {
@@ -56,7 +61,8 @@ impl VideoDevice {
 			gl_frag_shader_handle: 0,
 			gl_shader_program_handle: 0,
 			gl_texture_handle: 0,
-			gl_attrib_location_tex: 0
+			gl_attrib_location_tex: 0,
+			texture_size: (GBA_SCREEN_WIDTH as usize, GBA_SCREEN_HEIGHT as usize),
 		};
 		ret.init();
 		return ret;
@@ -178,7 +184,7 @@ impl VideoDevice {
 	}
 
 	/// Renders the screen texture.
-	pub fn render(&mut self, buffer: &GbaLcdScreenBuffer) {
+	pub fn render(&mut self, frame: &FilteredFrame) {
 		profiler_begin!("Render GBA Frame");
 		unsafe {
 			gl::ClearColor(1.0, 0.0, 1.0, 1.0);
@@ -194,17 +200,28 @@ impl VideoDevice {
 			gl::ActiveTexture(gl::TEXTURE0);
 			gl::Uniform1i(self.gl_attrib_location_tex as i32, 0);
 
-			gl::TexSubImage2D(
-				gl::TEXTURE_2D, 0,
-				0, 0, 240, 160,
-				gl::RGB, gl::UNSIGNED_BYTE,
-				buffer.as_ptr() as *const _
-			);
+			gl::BindTexture(gl::TEXTURE_2D, self.gl_texture_handle);
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			if self.texture_size != (frame.width, frame.height) {
+				self.texture_size = (frame.width, frame.height);
+				gl::TexImage2D(
+					gl::TEXTURE_2D, 0, gl::RGB as i32,
+					frame.width as i32, frame.height as i32, 0,
+					gl::RGB, gl::UNSIGNED_BYTE,
+					frame.pixels.as_ptr() as *const _
+				);
+			} else {
+				gl::TexSubImage2D(
+					gl::TEXTURE_2D, 0,
+					0, 0, frame.width as i32, frame.height as i32,
+					gl::RGB, gl::UNSIGNED_BYTE,
+					frame.pixels.as_ptr() as *const _
+				);
+			}
 
 			gl::BindVertexArray(self.gl_vao_handle);
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_vbo_handle);
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.gl_ebo_handle);
-			gl::BindTexture(gl::TEXTURE_2D, self.gl_texture_handle);
 
 			gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, ptr::null());
 		}
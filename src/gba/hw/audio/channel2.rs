@@ -1,68 +1,51 @@
-use super::super::super::core::cpu::ArmCpu;
-use super::super::super::device::audio::AudioDevice;
-use super::super::super::core::memory::*;
 use super::super::super::core::memory::ioreg::GbaChannel2;
-use super::get_freq_len_duty;
-use std;
+use super::{clock_timer, clock_envelope, clock_length, SQUARE_DUTY_PATTERNS};
 
-pub fn init(cpu: &mut ArmCpu, device: &AudioDevice) {
-	let channel: &mut GbaChannel2 = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel2 as *mut GbaChannel2) };
+/// Cycles between steps through the 8 step duty pattern, 131072/(2048-n)Hz for the whole pattern.
+fn step_period(frequency: u16) -> u32 {
+	16 * (2048 - frequency as u32)
+}
 
+/// Handles the initial (restart) flag.
+pub fn init(channel: &mut GbaChannel2) {
 	if channel.initial {
+		channel.initial = false;
 		channel.envelope_time_acc = 0;
 		channel.current_volume = channel.initial_volume;
-		channel.sound_length_time_acc = device.millis_to_frames(3, 9) * (64 - channel.sound_length as u32);
-		channel.initial = false;
+		if channel.length_counter == 0 {
+			channel.length_counter = 64;
+		}
+		channel.frequency_timer = step_period(channel.frequency);
+		channel.duty_step = 0;
+
+		// With an initial volume of 0 that only goes down the channel is off.
+		channel.playing = channel.initial_volume != 0 || channel.envelope_inc;
 	}
+}
 
-	channel.freq_len = device.sample_rate_f / max!(channel.frequency_f, 1.0);
-	channel.freq_len_duty = get_freq_len_duty(channel.freq_len, channel.wave_pattern_duty);
+/// Runs the frequency timer for `cycles` cycles.
+pub fn tick(channel: &mut GbaChannel2, cycles: u32) {
+	if channel.playing {
+		let steps = clock_timer(&mut channel.frequency_timer, step_period(channel.frequency), cycles);
+		channel.duty_step = ((channel.duty_step as u32 + steps) & 7) as u16;
+	}
+}
 
-	channel.playing = !channel.length_flag || channel.sound_length_time_acc > 0;
-	if !channel.playing {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !2);
+/// The 4 bit output of the channel centered on 0, -15 to 15.
+pub fn output(channel: &GbaChannel2) -> i16 {
+	let pattern = SQUARE_DUTY_PATTERNS[channel.wave_pattern_duty as usize];
+	if ((pattern >> channel.duty_step) & 1) != 0 {
+		channel.current_volume as i16
 	} else {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x | 2);
+		-(channel.current_volume as i16)
 	}
 }
 
-pub fn tick(cpu: &mut ArmCpu, device: &AudioDevice) -> usize {
-	let channel: &mut GbaChannel2 = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel2 as *mut GbaChannel2) };
-	if !channel.length_flag || channel.sound_length_time_acc > 0 {
-		// Envelope Function:
-		if channel.envelope_step_time > 0 {
-			channel.envelope_time_acc += 1;
-			let envelope_time_frames = device.millis_to_frames(15, 6) * (channel.envelope_step_time as u32);
-			if channel.envelope_time_acc >= envelope_time_frames {
-				if channel.envelope_inc && channel.current_volume < 15 {
-					channel.current_volume += 1;
-				} else if (!channel.envelope_inc) && channel.current_volume > 0 {
-					channel.current_volume -= 1;
-				}
-				channel.envelope_time_acc = 0;
-			}
-		}
-
-		channel.frequency_step += 1.0; // 0.74303854875, 1.34582519531
-		if channel.frequency_step > channel.freq_len {
-			channel.frequency_step = 0.0;
-		}
-
-		if channel.length_flag {
-			channel.sound_length_time_acc -= 1;
-			if channel.sound_length_time_acc == 0 {
-				let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-				cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !2); // turn the sound off.
-			}
-		}
+pub fn clock_length_counter(channel: &mut GbaChannel2) {
+	clock_length(&mut channel.length_counter, channel.length_flag, &mut channel.playing);
+}
 
-		return if channel.frequency_step < channel.freq_len_duty {
-			channel.current_volume as usize
-		} else {
-			channel.current_volume as usize + 16
-		}
-	}
-	return channel.current_volume as usize + 16
-}
\ No newline at end of file
+pub fn clock_volume_envelope(channel: &mut GbaChannel2) {
+	clock_envelope(&mut channel.current_volume, &mut channel.envelope_time_acc,
+		channel.envelope_step_time, channel.envelope_inc);
+}
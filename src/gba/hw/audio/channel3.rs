@@ -1,96 +1,81 @@
-use super::super::super::core::cpu::ArmCpu;
-use super::super::super::device::audio::AudioDevice;
-use super::super::super::core::memory::*;
 use super::super::super::core::memory::ioreg::GbaChannel3;
-use super::apply_volume;
-use std;
+use super::{clock_timer, clock_length};
 
+/// Centers a 4 bit sample on 0 so that it has the same range as the other PSG channels.
 pub fn convert_sample(sample4: u16) -> i16 {
-	// return (sample4 << 12) as i16;
-	// return ((sample4 << 12) | 0xfff) as i16;
-	const SAMPLES: [i16; 16] = [-32768, -28672, -24576, -20480, -16384, -12288, -8192, -4096, 0, 4096, 8192, 12288, 16384, 20480, 24576, 32767];
-	return SAMPLES[(sample4 as usize) & 0xf];
+	((sample4 & 0xf) as i16) * 2 - 15
 }
 
-pub fn init(cpu: &mut ArmCpu, device: &AudioDevice) {
-	let channel: &mut GbaChannel3 = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel3 as *mut GbaChannel3) };
-
-	let reset_sample_rate = channel.initial || (!channel.length_flag);
+/// Cycles between wave RAM digits, 2097152/(2048-n)Hz.
+fn digit_period(sample_rate: u16) -> u32 {
+	8 * (2048 - sample_rate as u32)
+}
 
+/// Handles the initial (restart) flag.
+pub fn init(channel: &mut GbaChannel3) {
 	if channel.initial {
-		channel.sound_length_time_acc = device.millis_to_frames(3, 9) * (256 - channel.sound_length as u32);
-		channel.current_wav_index = 0;
-		channel.freq_acc = 0.0;
 		channel.initial = false;
+		if channel.length_counter == 0 {
+			channel.length_counter = 256;
+		}
+		channel.current_wav_index = 0;
+		channel.frequency_timer = digit_period(channel.sample_rate);
+		channel.playing = true;
 	}
 
-	if reset_sample_rate {
-		let sample_rate = 2097152.0 / (2048.0 - channel.sample_rate as f32);
-		channel.freq_inc = sample_rate / device.sample_rate_f;
+	if !channel.channel_on {
+		channel.playing = false;
 	}
+}
 
-	channel.playing = channel.channel_on && (!channel.length_flag || channel.sound_length_time_acc > 0);
-	if !channel.playing {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !4); // turn the sound off.
-	} else {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x | 4);
+/// Runs the frequency timer for `cycles` cycles.
+pub fn tick(channel: &mut GbaChannel3, cycles: u32) {
+	if channel.playing {
+		let steps = clock_timer(&mut channel.frequency_timer, digit_period(channel.sample_rate), cycles);
+		channel.current_wav_index = (channel.current_wav_index + steps as usize) & 0x3f;
 	}
+}
 
-	if channel.force_volume {
-		channel.c3_volume_multiplier = 0.75;
-	} else {
-		channel.c3_volume_multiplier =  match channel.sound_volume {
-			0 => 0.0,
-			1 => 1.0,
-			2 => 0.5,
-			3 => 0.25,
-			_ => unreachable!()
-		};
+/// The 4 bit output of the channel centered on 0, -15 to 15.
+pub fn output(channel: &GbaChannel3) -> i16 {
+	if !channel.playing {
+		return 0;
 	}
-}
 
-pub fn tick(cpu: &mut ArmCpu, _: &AudioDevice) -> i16 {
-	let channel: &mut GbaChannel3 = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel3 as *mut GbaChannel3) };
-	if channel.channel_on && (!channel.length_flag || channel.sound_length_time_acc > 0) {
-		let wav_idx = channel.current_wav_index & 0x1f;
-		let bank = if channel.wav_ram_banked {
-			if (channel.current_wav_index & 0x3f) > 0x1f {
-				(channel.wav_ram_bank ^ 1) as usize
-			} else {
-				channel.wav_ram_bank as usize
-			}
+	let wav_idx = channel.current_wav_index & 0x1f;
+	let bank = if channel.wav_ram_banked {
+		if (channel.current_wav_index & 0x3f) > 0x1f {
+			(channel.wav_ram_bank ^ 1) as usize
 		} else {
 			channel.wav_ram_bank as usize
-		};
-		
-		// playing order is 4-7, 0-3, 12-15, 8-11
-
-		// byte select
-		let b_sel = ((wav_idx >> 1) & 1) << 3;
+		}
+	} else {
+		channel.wav_ram_bank as usize
+	};
 
-		// nibble select
-		let n_sel = (1 - ((wav_idx) & 1)) << 2;
+	// playing order is 4-7, 0-3, 12-15, 8-11
 
-		let b = (channel.wav_ram[bank][wav_idx >> 2]) >> b_sel;
-		let sample = (b >> n_sel) & 0xf;
+	// byte select
+	let b_sel = ((wav_idx >> 1) & 1) << 3;
 
-		channel.freq_acc += channel.freq_inc;
-		while channel.freq_acc >= 1.0 {
-			channel.current_wav_index += 1;
-			channel.freq_acc -= 1.0;
-		}
+	// nibble select
+	let n_sel = (1 - ((wav_idx) & 1)) << 2;
 
-		if channel.length_flag {
-			channel.sound_length_time_acc -= 1;
-			if channel.sound_length_time_acc == 0 {
-				let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-				cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !4); // turn the sound off.
-			}
-		}
+	let b = (channel.wav_ram[bank][wav_idx >> 2]) >> b_sel;
+	let sample = (b >> n_sel) & 0xf;
 
-		return apply_volume(convert_sample(sample), channel.c3_volume_multiplier);
+	let sample = convert_sample(sample);
+	if channel.force_volume {
+		return (sample * 3) >> 2;
+	}
+	match channel.sound_volume {
+		0 => 0,
+		1 => sample,
+		2 => sample >> 1,
+		_ => sample >> 2,
 	}
-	return apply_volume(std::i16::MIN, channel.c3_volume_multiplier);
-}
\ No newline at end of file
+}
+
+pub fn clock_length_counter(channel: &mut GbaChannel3) {
+	clock_length(&mut channel.length_counter, channel.length_flag, &mut channel.playing);
+}
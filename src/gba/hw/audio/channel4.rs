@@ -1,22 +1,23 @@
-use super::super::super::core::cpu::ArmCpu;
-use super::super::super::device::audio::AudioDevice;
-use super::super::super::core::memory::*;
 use super::super::super::core::memory::ioreg::GbaChannel4;
-use std;
+use super::{clock_timer, clock_envelope, clock_length};
 
-pub fn init(cpu: &mut ArmCpu, device: &AudioDevice) {
-	let channel: &mut GbaChannel4  = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel4  as *mut GbaChannel4 ) };
-	
+/// Cycles between shifts of the noise generator, 524288/r/2^(s+1)Hz with r=0 counting as 0.5.
+fn shift_period(dividing_ratio: u16, shift_clock_freq: u16) -> u32 {
+	let r = if dividing_ratio == 0 { 16 } else { 32 * dividing_ratio as u32 };
+	r << (shift_clock_freq + 1)
+}
+
+/// Handles the initial (restart) flag.
+pub fn init(channel: &mut GbaChannel4) {
 	if channel.initial {
+		channel.initial = false;
 		channel.envelope_time_acc = 0;
 		channel.current_volume = channel.initial_volume;
+		if channel.length_counter == 0 {
+			channel.length_counter = 64;
+		}
+		channel.frequency_timer = shift_period(channel.dividing_ratio, channel.shift_clock_freq);
 
-		let r = if channel.dividing_ratio == 0 { 0.5 } else { channel.dividing_ratio as f32 };
-		channel.intermediate_freq = 524_288.0 / r;
-
-		channel.sound_length_time_acc = device.millis_to_frames(3, 9) * (64 - channel.sound_length as u32);
-
-		channel.freq_acc = 0.0;
 		if channel.counter_width_7 {
 			channel.lfsr = 0x40;
 			channel.lfsr_mask = 0x7F;
@@ -26,65 +27,41 @@ pub fn init(cpu: &mut ArmCpu, device: &AudioDevice) {
 			channel.lfsr_mask = 0x7FFF;
 			channel.lfsr_xor = 0x6000;
 		}
-		channel.initial = false;
-	}
-
-	let freq = channel.intermediate_freq / ((1 << (channel.shift_clock_freq + 1)) as f32);
-	channel.freq_inc = freq / device.sample_rate_f;
 
-	cpu.memory.internal_regs.audio_channel4.playing = !channel.length_flag || channel.sound_length_time_acc > 0;
-	if !channel.playing {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !8);
-	} else {
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-		cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x | 8);
+		// With an initial volume of 0 that only goes down the channel is off.
+		channel.playing = channel.initial_volume != 0 || channel.envelope_inc;
 	}
 }
 
-pub fn tick(cpu: &mut ArmCpu, device: &AudioDevice) -> usize {
-	let channel: &mut GbaChannel4  = unsafe { std::mem::transmute(&mut cpu.memory.internal_regs.audio_channel4  as *mut GbaChannel4 ) };
-
-	if !channel.length_flag || channel.sound_length_time_acc > 0 {
-		channel.freq_acc += channel.freq_inc;
-		let out = channel.lfsr & 1;
-		while channel.freq_acc >= 1.0 {
+/// Runs the noise generator for `cycles` cycles.
+pub fn tick(channel: &mut GbaChannel4, cycles: u32) {
+	if channel.playing {
+		let period = shift_period(channel.dividing_ratio, channel.shift_clock_freq);
+		let steps = clock_timer(&mut channel.frequency_timer, period, cycles);
+		for _ in 0..steps {
 			if (channel.lfsr & 1) != 0 {
 				channel.lfsr = ((channel.lfsr >> 1) ^ channel.lfsr_xor) & channel.lfsr_mask;
 			} else {
 				channel.lfsr = (channel.lfsr >> 1) & channel.lfsr_mask;
 			}
-			channel.freq_acc -= 1.0;
-		}
-
-		// Envelope Function:
-		if channel.envelope_step_time > 0 {
-			channel.envelope_time_acc += 1;
-			let envelope_time_frames = device.millis_to_frames(15, 6) * (channel.envelope_step_time as u32);
-			if channel.envelope_time_acc >= envelope_time_frames {
-				if channel.envelope_inc && channel.current_volume < 15 {
-					channel.current_volume += 1;
-				} else if (!channel.envelope_inc) && channel.current_volume > 0 {
-					channel.current_volume -= 1;
-				}
-				channel.envelope_time_acc = 0;
-			}
-		}
-
-		if channel.length_flag {
-			channel.sound_length_time_acc -= 1;
-			if channel.sound_length_time_acc == 0 {
-				let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
-				cpu.memory.set_reg(ioreg::SOUNDCNT_X, soundcnt_x & !8); // turn the sound off.
-			}
 		}
+	}
+}
 
-		if out != 0 {
-			channel.current_volume as usize
-		} else {
-			channel.current_volume as usize + 16
-		}
+/// The 4 bit output of the channel centered on 0, -15 to 15.
+pub fn output(channel: &GbaChannel4) -> i16 {
+	if (channel.lfsr & 1) != 0 {
+		channel.current_volume as i16
 	} else {
-		return channel.current_volume as usize + 16
+		-(channel.current_volume as i16)
 	}
-}
\ No newline at end of file
+}
+
+pub fn clock_length_counter(channel: &mut GbaChannel4) {
+	clock_length(&mut channel.length_counter, channel.length_flag, &mut channel.playing);
+}
+
+pub fn clock_volume_envelope(channel: &mut GbaChannel4) {
+	clock_envelope(&mut channel.current_volume, &mut channel.envelope_time_acc,
+		channel.envelope_step_time, channel.envelope_inc);
+}
@@ -6,133 +6,324 @@ pub mod channel_ab;
 
 use super::super::core::cpu::ArmCpu;
 use super::super::core::memory::*;
-use super::super::device::audio::AudioDevice;
+use super::super::core::memory::ioreg::InternalRegisters;
+use super::super::device::GbaDevice;
+use super::super::device::audio_dump::{StemFrame, STEM_COUNT};
 use ::util::measure::*;
 
 
-const AMPLITUDE_OUTPUTS: [i16; 32] = [
-// HIGH:
-0, 2184, 4369, 6553, 8738, 10922, 13107, 15291,
-17476, 19660, 21845, 24029, 26214, 28398, 30583, 32767,
+/// Cycles between clocks of the frame sequencer (512Hz).
+const SEQUENCER_PERIOD: u32 = 32768;
 
-// LOW:
-0, -2185, -4369, -6554, -8738, -10923, -13107, -15292,
--17476, -19661, -21845, -24030, -26214, -28399, -30583, -32768
-];
+/// The debugger's sound plots get one of every this many samples.
+const SOUND_PLOT_DECIMATION: u64 = 16;
 
-const PSG_VOLUME_MULS: [f32; 8] = [
-	0.0, 0.14285714285714285,  0.2857142857142857, 0.42857142857142855,
-	0.5714285714285714, 0.7142857142857143, 0.8571428571428571, 1.0
-];
+/// Square wave duty patterns, one bit for each of the 8 steps (12.5%, 25%, 50%, 75%).
+const SQUARE_DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b00000011, 0b00001111, 0b00111111];
 
-pub fn tick(cpu: &mut ArmCpu, device: &AudioDevice) {
+/// Catches the APU up to the CPU's clock.
+/// Everything in here is counted in CPU cycles: the channels' frequency timers,
+/// the 512Hz frame sequencer and the sample rate selected in SOUNDBIAS.
+/// The samples only get converted to the rate of the audio output at the very end.
+pub fn tick(cpu: &mut ArmCpu, device: &mut GbaDevice) {
 	measure_start(MEASURE_AUDIO_TICK_TIME);
 	measure_iteration(MEASURE_AUDIO_TICK_TIME);
 
-	// console_warn!("{} OUT REMINAING", cpu.memory.internal_regs.audio_fifo_a.out_remaining());
-
-	device.ring_buffer.try_write(|frames| {
-		let soundcnt_l = cpu.memory.get_reg(ioreg::SOUNDCNT_L);
-		let soundcnt_h = cpu.memory.get_reg(ioreg::SOUNDCNT_H);
-		let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
+	let soundcnt_l = cpu.memory.get_reg(ioreg::SOUNDCNT_L);
+	let soundcnt_h = cpu.memory.get_reg(ioreg::SOUNDCNT_H);
+	let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
+	let soundbias = cpu.memory.get_reg(ioreg::SOUNDBIAS);
+
+	// 14-15 Amplitude Resolution/Sampling Cycle (0-3=9bit/32KHz, 8bit/65KHz, 7bit/131KHz, 6bit/262KHz)
+	let resolution = ((soundbias >> 14) & 0x3) as u32;
+	let sample_period = 512 >> resolution;
+	let apu_rate = 32768 << resolution;
+	// Only the APU's rate can change here, the settings are applied once per frame.
+	device.audio.set_apu_rate(apu_rate);
+
+	let sound_enabled = device.audio.sound_enabled;
+	let plotting = device.audio.plotting;
+	let want_stems = match device.audio_dump {
+		Some(ref dump) => dump.wants_stems(),
+		None => false,
+	};
+	// Rewound frames don't go into recordings or dumps.
+	let capturing = (device.recorder.is_some() || device.audio_dump.is_some()) && !device.rewind.active;
+
+	let mut mixer = GbaAudioMixer::new(soundcnt_l, soundcnt_h, soundcnt_x, soundbias, device.audio.channels_enabled);
+
+	channel1::init(&mut cpu.memory.internal_regs.audio_channel1);
+	channel2::init(&mut cpu.memory.internal_regs.audio_channel2);
+	channel3::init(&mut cpu.memory.internal_regs.audio_channel3);
+	channel4::init(&mut cpu.memory.internal_regs.audio_channel4);
+
+	let now = cpu.clock.cycles;
+	if cpu.memory.internal_regs.audio_sequencer.cycle > now {
+		cpu.memory.internal_regs.audio_sequencer.cycle = now;
+	}
 
-		let mut mixer = GbaAudioMixer::new(soundcnt_l, soundcnt_h, soundcnt_x);
-		mixer.init();
+	while cpu.memory.internal_regs.audio_sequencer.cycle < now {
+		let cycles = {
+			let sequencer = &mut cpu.memory.internal_regs.audio_sequencer;
+			if sequencer.sequencer_timer == 0 { sequencer.sequencer_timer = SEQUENCER_PERIOD; }
+			if sequencer.sample_timer == 0 || sequencer.sample_timer > sample_period {
+				sequencer.sample_timer = sample_period;
+			}
+			min!((now - sequencer.cycle) as u32, min!(sequencer.sequencer_timer, sequencer.sample_timer))
+		};
 
-		channel1::init(cpu, device);
-		channel2::init(cpu, device);
-		channel3::init(cpu, device);
-		channel4::init(cpu, device);
-		channel_ab::init(cpu, device);
+		{
+			let regs = &mut cpu.memory.internal_regs;
+			channel1::tick(&mut regs.audio_channel1, cycles);
+			channel2::tick(&mut regs.audio_channel2, cycles);
+			channel3::tick(&mut regs.audio_channel3, cycles);
+			channel4::tick(&mut regs.audio_channel4, cycles);
+
+			regs.audio_sequencer.cycle += cycles as u64;
+			regs.audio_sequencer.sequencer_timer -= cycles;
+			regs.audio_sequencer.sample_timer -= cycles;
+
+			if regs.audio_sequencer.sequencer_timer == 0 {
+				clock_sequencer(regs);
+			}
+		}
 
-		for idx in 0..frames.len() {
-			mixer.psg_count = 0;
+		if cpu.memory.internal_regs.audio_sequencer.sample_timer == 0 {
+			let cycle = cpu.memory.internal_regs.audio_sequencer.cycle;
+			sample_channels(cpu, &mut mixer, soundcnt_x, cycle);
 
-			// Sound 1:
-			if cpu.memory.internal_regs.audio_channel1.playing {
-				mixer.c1 = AMPLITUDE_OUTPUTS[channel1::tick(cpu, device)];
-				mixer.psg_count += 1;
-			} else {
-				mixer.c1 = 0;
+			let mix = mixer.mix();
+			if plotting && (cycle / sample_period as u64) % SOUND_PLOT_DECIMATION == 0 {
+				mixer.plot(mix);
 			}
-
-			// Sound 2:
-			if cpu.memory.internal_regs.audio_channel2.playing {
-				mixer.c2 = AMPLITUDE_OUTPUTS[channel2::tick(cpu, device)];
-				mixer.psg_count += 1;
-			} else {
-				mixer.c2 = 0;
+			if let Some(ref mut capture) = device.audio_capture {
+				if !device.rewind.active { capture.push(mix); }
 			}
-
-			// Sound 3:
-			if cpu.memory.internal_regs.audio_channel3.playing {
-				mixer.c3 = channel3::tick(cpu, device);
-				mixer.psg_count += 1;
-			} else {
-				mixer.c3 = 0;
+			let frame = if sound_enabled { device.audio.output_filter.process(mix) } else { (0, 0) };
+			device.audio.resampler.live.push(frame);
+			device.audio.resampler.mix.push(frame);
+			if want_stems {
+				let outputs = mixer.channel_outputs();
+				for (resampler, &output) in device.audio.resampler.stems.iter_mut().zip(outputs.iter()) {
+					resampler.push(output);
+				}
 			}
+		}
+	}
 
-			// Sound 4:
-			if cpu.memory.internal_regs.audio_channel4.playing {
-				mixer.c4 = AMPLITUDE_OUTPUTS[channel4::tick(cpu, device)];
-				mixer.psg_count += 1;
-			} else {
-				mixer.c4 = 0;
-			}
+	update_channel_status(cpu);
 
-			// DMA Sound:
-			if (soundcnt_x & 0x80) != 0 {
-				mixer.ca = channel_ab::tick_a(cpu);
-				mixer.cb = channel_ab::tick_b(cpu);
-			} else {
-				mixer.ca = 0;
-				mixer.cb = 0;
-			}
+	device.audio.flush_output();
 
-			if psetting!(sound_enabled) {
-				frames[idx] = mixer.mix();
-			} else {
-				frames[idx] = (0, 0);
+	device.audio.capture_frames.clear();
+	device.audio.capture_stems.clear();
+	while let Some(frame) = device.audio.resampler.mix.pop() {
+		// The stems are drained along with the mix even when they're thrown away
+		// so that they still line up with it once capturing starts again.
+		let mut stem_frame = [(0, 0); STEM_COUNT];
+		if want_stems {
+			for (stem, resampler) in stem_frame.iter_mut().zip(device.audio.resampler.stems.iter_mut()) {
+				*stem = resampler.pop().unwrap_or((0, 0));
 			}
 		}
+		if !capturing { continue }
+		device.audio.capture_frames.push(frame);
+		if want_stems { device.audio.capture_stems.push(stem_frame); }
+	}
+
+	let recorder_result = match device.recorder {
+		Some(ref mut recorder) => recorder.write_audio(&device.audio.capture_frames),
+		None => Ok(()),
+	};
+	if let Err(e) = recorder_result {
+		console_error!("{}", e);
+		device.stop_recording();
+	}
 
-		return true
-	});
+	let dump_result = match device.audio_dump {
+		Some(ref mut dump) => dump.write(&device.audio.capture_frames, &device.audio.capture_stems),
+		None => Ok(()),
+	};
+	if let Err(e) = dump_result {
+		console_error!("{}", e);
+		device.stop_audio_dump();
+	}
 
 	measure_end(MEASURE_AUDIO_TICK_TIME);
 }
 
+/// Clocks length (256Hz), sweep (128Hz) and envelopes (64Hz).
+fn clock_sequencer(regs: &mut InternalRegisters) {
+	let step = regs.audio_sequencer.sequencer_step;
+
+	if (step & 1) == 0 {
+		channel1::clock_length_counter(&mut regs.audio_channel1);
+		channel2::clock_length_counter(&mut regs.audio_channel2);
+		channel3::clock_length_counter(&mut regs.audio_channel3);
+		channel4::clock_length_counter(&mut regs.audio_channel4);
+	}
+
+	if step == 2 || step == 6 {
+		channel1::clock_sweep(&mut regs.audio_channel1);
+	}
+
+	if step == 7 {
+		channel1::clock_volume_envelope(&mut regs.audio_channel1);
+		channel2::clock_volume_envelope(&mut regs.audio_channel2);
+		channel4::clock_volume_envelope(&mut regs.audio_channel4);
+	}
+
+	regs.audio_sequencer.sequencer_step = (step + 1) & 7;
+	regs.audio_sequencer.sequencer_timer = SEQUENCER_PERIOD;
+}
+
+/// Loads what every channel is outputting at `cycle` into the mixer.
+fn sample_channels(cpu: &mut ArmCpu, mixer: &mut GbaAudioMixer, soundcnt_x: u16, cycle: u64) {
+	{
+		let regs = &cpu.memory.internal_regs;
+
+		// Sound 1:
+		if regs.audio_channel1.playing {
+			mixer.c1 = channel1::output(&regs.audio_channel1);
+		} else {
+			mixer.c1 = 0;
+		}
+
+		// Sound 2:
+		if regs.audio_channel2.playing {
+			mixer.c2 = channel2::output(&regs.audio_channel2);
+		} else {
+			mixer.c2 = 0;
+		}
+
+		// Sound 3:
+		if regs.audio_channel3.playing {
+			mixer.c3 = channel3::output(&regs.audio_channel3);
+		} else {
+			mixer.c3 = 0;
+		}
+
+		// Sound 4:
+		if regs.audio_channel4.playing {
+			mixer.c4 = channel4::output(&regs.audio_channel4);
+		} else {
+			mixer.c4 = 0;
+		}
+	}
+
+	// DMA Sound:
+	if (soundcnt_x & 0x80) != 0 {
+		mixer.ca = channel_ab::output_a(cpu, cycle);
+		mixer.cb = channel_ab::output_b(cpu, cycle);
+	} else {
+		mixer.ca = 0;
+		mixer.cb = 0;
+	}
+}
+
+/// Bits 0-3 of SOUNDCNT_X show which of the PSG channels are playing.
+fn update_channel_status(cpu: &mut ArmCpu) {
+	let mut status = 0;
+	if cpu.memory.internal_regs.audio_channel1.playing { status |= 1; }
+	if cpu.memory.internal_regs.audio_channel2.playing { status |= 2; }
+	if cpu.memory.internal_regs.audio_channel3.playing { status |= 4; }
+	if cpu.memory.internal_regs.audio_channel4.playing { status |= 8; }
+	let soundcnt_x = cpu.memory.get_reg(ioreg::SOUNDCNT_X);
+	cpu.memory.set_reg(ioreg::SOUNDCNT_X, (soundcnt_x & !0xf) | status);
+}
+
+/// Runs a frequency timer that reloads with `period` for `cycles` cycles.
+/// Returns how many times it ran out.
+fn clock_timer(timer: &mut u32, period: u32, cycles: u32) -> u32 {
+	let mut remaining = cycles;
+	let mut steps = 0;
+	while remaining >= *timer {
+		remaining -= *timer;
+		*timer = period;
+		steps += 1;
+	}
+	*timer -= remaining;
+	steps
+}
+
+/// Clocked at 256Hz.
+fn clock_length(counter: &mut u32, length_flag: bool, playing: &mut bool) {
+	if length_flag && *counter > 0 {
+		*counter -= 1;
+		if *counter == 0 {
+			*playing = false; // turn the sound off.
+		}
+	}
+}
+
+/// Clocked at 64Hz, the volume changes every `step_time` clocks.
+fn clock_envelope(volume: &mut u16, acc: &mut u32, step_time: u16, inc: bool) {
+	if step_time == 0 { return }
+	*acc += 1;
+	if *acc >= step_time as u32 {
+		if inc && *volume < 15 {
+			*volume += 1;
+		} else if !inc && *volume > 0 {
+			*volume -= 1;
+		}
+		*acc = 0;
+	}
+}
+
+/// Mixes the channels the way the hardware does:
+/// The PSG channels are 4 bit and each one can reach a quarter of the output range
+/// with the master volumes all the way up, the DMA channels are 8 bit and can each reach
+/// all of it. Everything is added together with the bias in SOUNDBIAS, clipped to 10 bits
+/// and then cut down to the amplitude resolution that goes with the sampling rate.
 #[derive(Default)]
 pub struct GbaAudioMixer {
+	/// -15 to 15.
 	c1: i16, c2: i16, c3: i16, c4: i16,
-	ca: i16, cb: i16,
 
-	psg_count: u32,
+	/// -128 to 127.
+	ca: i16, cb: i16,
 
-	psg_right_vol: f32,
-	psg_left_vol: f32,
+	/// 1 to 8.
+	psg_right_vol: i16,
+	psg_left_vol: i16,
 
 	soundcnt_l: u16,
 	soundcnt_h: u16,
 	soundcnt_x: u16,
+	soundbias: u16,
+
+	/// The channel toggles from the settings: 1 to 4, A and B.
+	enabled: [bool; 6],
 }
 
 impl GbaAudioMixer {
-	pub fn new(soundcnt_l: u16, soundcnt_h: u16, soundcnt_x: u16) -> GbaAudioMixer {
+	pub fn new(soundcnt_l: u16, soundcnt_h: u16, soundcnt_x: u16, soundbias: u16, enabled: [bool; 6]) -> GbaAudioMixer {
 		GbaAudioMixer {
+			// 0-2   Sound 1-4 Master Volume RIGHT (0-7)
+			psg_right_vol: ((soundcnt_l & 0x7) + 1) as i16,
+
+			// 4-6   Sound 1-4 Master Volume LEFT (0-7)
+			psg_left_vol: (((soundcnt_l >> 4) & 0x7) + 1) as i16,
+
 			soundcnt_l: soundcnt_l,
 			soundcnt_h: soundcnt_h,
 			soundcnt_x: soundcnt_x,
+			soundbias: soundbias,
+			enabled: enabled,
 			..Default::default()
 		}
 	}
 
-	pub fn init(&mut self) {
-		// 0-2   Sound 1-4 Master Volume RIGHT (0-7)
-		self.psg_right_vol = PSG_VOLUME_MULS[(self.soundcnt_l & 0x7) as usize];
+	/// 0-1   Sound # 1-4 Volume   (0=25%, 1=50%, 2=100%, 3=Prohibited)
+	#[inline(always)]
+	fn psg_volume_shift(&self) -> u16 {
+		2 - min!(2, self.soundcnt_h & 0x3)
+	}
 
-		// 4-6   Sound 1-4 Master Volume LEFT (0-7)
-		self.psg_left_vol = PSG_VOLUME_MULS[((self.soundcnt_l >> 4) & 0x7) as usize];
+	/// A DMA channel at 50% or 100% volume (SOUNDCNT_H bits 2 and 3).
+	#[inline(always)]
+	fn dma_volume(&self, sample: i16, volume_bit: u16) -> i16 {
+		(sample << 2) >> (1 - ((self.soundcnt_h >> volume_bit) & 1))
 	}
 
 	pub fn mix(&self) -> (i16, i16) {
@@ -141,89 +332,107 @@ impl GbaAudioMixer {
 		let mut dma_right = 0;
 		let mut dma_left = 0;
 
-		let psg_balance_div = self.psg_count as f32;
-		let psg_right_vol_b = self.psg_right_vol / psg_balance_div;
-		let psg_left_vol_b = self.psg_left_vol / psg_balance_div;
-
-		if psetting!(channel1_enabled) {
-			if (self.soundcnt_l & 0x100) != 0 { psg_right += apply_volume(self.c1, psg_right_vol_b) }
-			if (self.soundcnt_l & 0x1000) != 0 { psg_left += apply_volume(self.c1, psg_left_vol_b) }
+		if self.enabled[0] {
+			if (self.soundcnt_l & 0x100) != 0 { psg_right += self.c1 }
+			if (self.soundcnt_l & 0x1000) != 0 { psg_left += self.c1 }
 		}
 
-		if psetting!(channel2_enabled) {
-			if (self.soundcnt_l & 0x200) != 0 { psg_right += apply_volume(self.c2, psg_right_vol_b) }
-			if (self.soundcnt_l & 0x2000) != 0 { psg_left += apply_volume(self.c2, psg_left_vol_b) }
+		if self.enabled[1] {
+			if (self.soundcnt_l & 0x200) != 0 { psg_right += self.c2 }
+			if (self.soundcnt_l & 0x2000) != 0 { psg_left += self.c2 }
 		}
 
-		if psetting!(channel3_enabled) {
-			if (self.soundcnt_l & 0x400) != 0 { psg_right += apply_volume(self.c3, psg_right_vol_b) }
-			if (self.soundcnt_l & 0x4000) != 0 { psg_left += apply_volume(self.c3, psg_left_vol_b) }
+		if self.enabled[2] {
+			if (self.soundcnt_l & 0x400) != 0 { psg_right += self.c3 }
+			if (self.soundcnt_l & 0x4000) != 0 { psg_left += self.c3 }
 		}
 
-		if psetting!(channel4_enabled) {
-			if (self.soundcnt_l & 0x800) != 0 { psg_right += apply_volume(self.c4, psg_right_vol_b) }
-			if (self.soundcnt_l & 0x8000) != 0 { psg_left += apply_volume(self.c4, psg_left_vol_b) }
+		if self.enabled[3] {
+			if (self.soundcnt_l & 0x800) != 0 { psg_right += self.c4 }
+			if (self.soundcnt_l & 0x8000) != 0 { psg_left += self.c4 }
 		}
 
-
-		// 0-1   Sound # 1-4 Volume   (0=25%, 1=50%, 2=100%, 3=Prohibited)
-		let psg_volume_shift = 2 - min!(2, self.soundcnt_h & 0x3);
-
-		psg_left >>= psg_volume_shift;
-		psg_right >>= psg_volume_shift;
+		let psg_volume_shift = self.psg_volume_shift();
+		psg_left = (psg_left * self.psg_left_vol) >> psg_volume_shift;
+		psg_right = (psg_right * self.psg_right_vol) >> psg_volume_shift;
 
 		if (self.soundcnt_x & 0x80) != 0 {
-			if psetting!(channela_enabled) {
-				let dma_a_volume_shift = 1 - ((self.soundcnt_h >> 2) & 1);
-				let dma_a = self.ca >> dma_a_volume_shift;
+			if self.enabled[4] {
+				let dma_a = self.dma_volume(self.ca, 2);
 				if (self.soundcnt_h & 0x100) != 0 { dma_right += dma_a; }
 				if (self.soundcnt_h & 0x200) != 0 { dma_left += dma_a; }
 			}
 
-			if psetting!(channelb_enabled) {
-				let dma_b_v_volume_shift = 1 - ((self.soundcnt_h >> 3) & 1);
-				let dma_b = self.cb >> dma_b_v_volume_shift;
+			if self.enabled[5] {
+				let dma_b = self.dma_volume(self.cb, 3);
 				if (self.soundcnt_h & 0x1000) != 0 { dma_right += dma_b; }
 				if (self.soundcnt_h & 0x2000) != 0 { dma_left += dma_b; }
 			}
 		}
 
-		let left = psg_left + dma_left;
-		let right = psg_right + dma_right;
+		let left = self.output(psg_left + dma_left);
+		let right = self.output(psg_right + dma_right);
+		(left, right)
+	}
 
-		// DEBUGGER:
-		{
-			let debugger = ::debug::debugger::get_debugger();
-			debugger.sound_channel_1_plot.plot(self.c1 as f32);
-			debugger.sound_channel_2_plot.plot(self.c2 as f32);
-			debugger.sound_channel_3_plot.plot(self.c3 as f32);
-			debugger.sound_channel_4_plot.plot(self.c4 as f32);
-			debugger.sound_channel_a_plot.plot(self.ca as f32);
-			debugger.sound_channel_b_plot.plot(self.cb as f32);
-			debugger.sound_plot.plot(
-				if left == 0 { right } else { left } as f32
-			);
-		}
+	/// Adds the current sample of every channel and the mix to the debugger's plots.
+	fn plot(&self, mix: (i16, i16)) {
+		let (left, right) = mix;
+		let debugger = ::debug::debugger::get_debugger();
+		debugger.sound_channel_1_plot.plot((self.c1 * 2184) as f32);
+		debugger.sound_channel_2_plot.plot((self.c2 * 2184) as f32);
+		debugger.sound_channel_3_plot.plot((self.c3 * 2184) as f32);
+		debugger.sound_channel_4_plot.plot((self.c4 * 2184) as f32);
+		debugger.sound_channel_a_plot.plot((self.ca << 8) as f32);
+		debugger.sound_channel_b_plot.plot((self.cb << 8) as f32);
+		debugger.sound_plot.plot(
+			if left == 0 { right } else { left } as f32
+		);
+	}
 
-		(left, right)
+	/// Takes one side of the mix through the bias, the 10 bit clip and
+	/// the amplitude resolution and scales it up to 16 bits.
+	fn output(&self, sample: i16) -> i16 {
+		// 1-9   Bias Level (Default=100h, converting signed samples into unsigned)
+		let bias = (self.soundbias & 0x3fe) as i32;
+
+		// 14-15 Amplitude Resolution/Sampling Cycle (0-3=9bit/32KHz, 8bit/65KHz, 7bit/131KHz, 6bit/262KHz)
+		let resolution_mask = !((2 << ((self.soundbias >> 14) & 0x3)) - 1) as i32;
+
+		let unsigned = max!(0, min!(0x3ff, sample as i32 + bias)) & resolution_mask;
+		let scaled = (unsigned - bias) << 6;
+		max!(-32768, min!(32767, scaled)) as i16
 	}
-}
 
-fn apply_volume(sample: i16, volume: f32) -> i16 {
-	((sample as f32) * volume) as i16
-}
+	/// What each channel adds to the left and right outputs, in the same order as
+	/// `audio_dump::STEM_NAMES`. This ignores the channel toggles in the settings,
+	/// the bias and the clipping.
+	pub fn channel_outputs(&self) -> StemFrame {
+		let mut outputs = [(0, 0); STEM_COUNT];
+
+		let psg_volume_shift = self.psg_volume_shift();
+		let psg = [self.c1, self.c2, self.c3, self.c4];
+		for channel in 0..4 {
+			let sample = psg[channel];
+			let right = if (self.soundcnt_l & (0x100 << channel)) != 0 { sample * self.psg_right_vol } else { 0 };
+			let left = if (self.soundcnt_l & (0x1000 << channel)) != 0 { sample * self.psg_left_vol } else { 0 };
+			outputs[channel] = ((left >> psg_volume_shift) << 6, (right >> psg_volume_shift) << 6);
+		}
 
-// fn apply_volume_stereo(sample: i16, volume: f32) -> (i16, i16) {
-// 	let r = ((sample as f32) * volume) as i16;
-// 	return (r, r)
-// }
+		if (self.soundcnt_x & 0x80) != 0 {
+			let dma_a = self.dma_volume(self.ca, 2) << 6;
+			outputs[4] = (
+				if (self.soundcnt_h & 0x200) != 0 { dma_a } else { 0 },
+				if (self.soundcnt_h & 0x100) != 0 { dma_a } else { 0 },
+			);
 
-fn get_freq_len_duty(flen: f32, duty: u16) -> f32 {
-	match duty {
-		0 => flen / 8.0,
-		1 => flen / 4.0,
-		2 => flen / 2.0,
-		3 => flen / (4.0 / 3.0),
-		_ => flen / 2.0
+			let dma_b = self.dma_volume(self.cb, 3) << 6;
+			outputs[5] = (
+				if (self.soundcnt_h & 0x2000) != 0 { dma_b } else { 0 },
+				if (self.soundcnt_h & 0x1000) != 0 { dma_b } else { 0 },
+			);
+		}
+
+		outputs
 	}
-}
\ No newline at end of file
+}
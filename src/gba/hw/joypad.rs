@@ -1,6 +1,5 @@
 use super::super::core::cpu::ArmCpu;
 use super::super::core::memory::*;
-use glutin::VirtualKeyCode;
 
 #[derive(RustcEncodable, RustcDecodable)]
 pub struct GbaJoypad {
@@ -41,18 +40,18 @@ pub struct GbaJoypad {
 // In logical OR mode, an interrupt is requested when at least one of the selected buttons is pressed.
 // In logical AND mode, an interrupt is requested when ALL of the selected buttons are pressed.
 
-const GBA_BTN_A: u16 = 0x1;
-const GBA_BTN_B: u16 = 0x2;
-const GBA_BTN_SELECT: u16 = 0x4;
-const GBA_BTN_START: u16 = 0x8;
-const GBA_BTN_RIGHT: u16 = 0x10;
-const GBA_BTN_LEFT: u16 = 0x20; 
-const GBA_BTN_UP: u16 = 0x40;
-const GBA_BTN_DOWN: u16 = 0x80;
-const GBA_BTN_R: u16 = 0x100;
-const GBA_BTN_L: u16 = 0x200;
+pub const GBA_BTN_A: u16 = 0x1;
+pub const GBA_BTN_B: u16 = 0x2;
+pub const GBA_BTN_SELECT: u16 = 0x4;
+pub const GBA_BTN_START: u16 = 0x8;
+pub const GBA_BTN_RIGHT: u16 = 0x10;
+pub const GBA_BTN_LEFT: u16 = 0x20; 
+pub const GBA_BTN_UP: u16 = 0x40;
+pub const GBA_BTN_DOWN: u16 = 0x80;
+pub const GBA_BTN_R: u16 = 0x100;
+pub const GBA_BTN_L: u16 = 0x200;
 
-const KEY_MASK: u16 = 0x3ff; // Mask that takes only the keys.
+pub const KEY_MASK: u16 = 0x3ff; // Mask that takes only the keys.
 
 impl GbaJoypad {
 	pub fn new() -> GbaJoypad {
@@ -83,41 +82,16 @@ impl GbaJoypad {
 		return false
 	}
 
-	pub fn key_pressed(&mut self, keycode: VirtualKeyCode) {
-		if let Some(mask) = self.map_keycode_to_mask(keycode) {
-			self.set_button_pressed(mask);
-		}
-	}
-
-	pub fn key_released(&mut self, keycode: VirtualKeyCode) {
-		if let Some(mask) = self.map_keycode_to_mask(keycode) {
-			self.set_button_released(mask);
-		}
+	/// The current value of KEYINPUT (0 = pressed).
+	pub fn keys(&self) -> u16 {
+		self.key_input
 	}
 
-	pub fn map_keycode_to_mask(&self, keycode: VirtualKeyCode) -> Option<u16> {
-		match keycode {
-			VirtualKeyCode::Z => Some(GBA_BTN_A),
-			VirtualKeyCode::X => Some(GBA_BTN_B),
-			VirtualKeyCode::Back => Some(GBA_BTN_SELECT),
-			VirtualKeyCode::Return => Some(GBA_BTN_START),
-			VirtualKeyCode::Right => Some(GBA_BTN_RIGHT),
-			VirtualKeyCode::Left => Some(GBA_BTN_LEFT),
-			VirtualKeyCode::Up => Some(GBA_BTN_UP),
-			VirtualKeyCode::Down => Some(GBA_BTN_DOWN),
-			VirtualKeyCode::A => Some(GBA_BTN_L),
-			VirtualKeyCode::S => Some(GBA_BTN_R),
-			_ => None
+	/// Replaces every key at once, the keyboard and movies both feed input in through this.
+	pub fn set_keys(&mut self, keys: u16) {
+		if self.key_input != (keys & KEY_MASK) {
+			self.key_input = keys & KEY_MASK;
+			self.dirty = true;
 		}
 	}
-
-	fn set_button_pressed(&mut self, mask: u16) {
-		self.key_input &= !mask; // 0 = button pressed
-		self.dirty = true;
-	}
-
-	fn set_button_released(&mut self, mask: u16) {
-		self.key_input |= mask; // 1 = button released
-		self.dirty = true;
-	}
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+//! Color correction.
+//! The GBA's colors are made for its screen, which looks nothing like a modern monitor.
+//! Each profile approximates how colors looked on one kind of screen and is
+//! precomputed into a lookup table with an entry for every 15-bit color.
+
+use super::{OutputPixel, expand_color};
+
+pub type ColorLut = Vec<OutputPixel>;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ColorProfile {
+	/// Plain 5-bit to 8-bit expansion.
+	Raw,
+
+	/// The original GBA's unlit screen. Dark and desaturated.
+	Gba,
+
+	/// The backlit GBA SP (AGS-101).
+	GbaSp,
+
+	/// A Nintendo DS (Lite) in GBA mode.
+	Nds,
+}
+
+pub const COLOR_PROFILES: [ColorProfile; 4] = [ColorProfile::Raw, ColorProfile::Gba, ColorProfile::GbaSp, ColorProfile::Nds];
+
+impl ColorProfile {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			ColorProfile::Raw => "Raw",
+			ColorProfile::Gba => "GBA",
+			ColorProfile::GbaSp => "GBA SP",
+			ColorProfile::Nds => "Nintendo DS",
+		}
+	}
+}
+
+/// Describes a screen. Colors are converted to linear light with the screen's gamma,
+/// the channels bleed into each other through the matrix, and the result is
+/// converted to the output's gamma (2.2) and scaled by the luminance.
+struct ScreenModel {
+	gamma: f32,
+
+	/// Rows are output red, green and blue; columns are input red, green and blue.
+	matrix: [[f32; 3]; 3],
+
+	luminance: f32,
+}
+
+const OUTPUT_GAMMA: f32 = 2.2;
+
+const GBA_SCREEN: ScreenModel = ScreenModel {
+	gamma: 4.0,
+	matrix: [
+		[255.0 / 255.0,  50.0 / 255.0,   0.0 / 255.0],
+		[ 10.0 / 255.0, 230.0 / 255.0,  30.0 / 255.0],
+		[ 50.0 / 255.0,  10.0 / 255.0, 220.0 / 255.0],
+	],
+	luminance: 255.0 / 280.0,
+};
+
+const GBA_SP_SCREEN: ScreenModel = ScreenModel {
+	gamma: 2.2,
+	matrix: [
+		[0.960, 0.110, -0.070],
+		[0.030, 0.910,  0.060],
+		[0.015, 0.040,  0.945],
+	],
+	luminance: 0.97,
+};
+
+const NDS_SCREEN: ScreenModel = ScreenModel {
+	gamma: 2.2,
+	matrix: [
+		[0.860, 0.100, 0.040],
+		[0.030, 0.745, 0.068],
+		[0.003, 0.140, 0.858],
+	],
+	luminance: 0.93,
+};
+
+pub fn build_lut(profile: ColorProfile) -> ColorLut {
+	let mut lut = Vec::with_capacity(32768);
+	for rgb5 in 0..32768u32 {
+		lut.push(correct_color(profile, rgb5 as u16));
+	}
+	lut
+}
+
+pub fn correct_color(profile: ColorProfile, rgb5: u16) -> OutputPixel {
+	match profile {
+		ColorProfile::Raw => expand_color(rgb5),
+		ColorProfile::Gba => screen_color(&GBA_SCREEN, rgb5),
+		ColorProfile::GbaSp => screen_color(&GBA_SP_SCREEN, rgb5),
+		ColorProfile::Nds => screen_color(&NDS_SCREEN, rgb5),
+	}
+}
+
+fn screen_color(screen: &ScreenModel, rgb5: u16) -> OutputPixel {
+	let r = ((rgb5 & 0x1f) as f32 / 31.0).powf(screen.gamma);
+	let g = (((rgb5 >> 5) & 0x1f) as f32 / 31.0).powf(screen.gamma);
+	let b = (((rgb5 >> 10) & 0x1f) as f32 / 31.0).powf(screen.gamma);
+
+	let channel = |row: usize| -> u8 {
+		let m = &screen.matrix[row];
+		let linear = (m[0] * r) + (m[1] * g) + (m[2] * b);
+		let linear = if linear < 0.0 { 0.0 } else if linear > 1.0 { 1.0 } else { linear };
+		let value = linear.powf(1.0 / OUTPUT_GAMMA) * screen.luminance * 255.0;
+		if value >= 255.0 { 255 } else { (value + 0.5) as u8 }
+	};
+
+	(channel(0), channel(1), channel(2))
+}
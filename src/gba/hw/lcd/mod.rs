@@ -1,7 +1,9 @@
 use super::super::core::memory::*;
+use self::color::{ColorProfile, ColorLut};
 
 pub mod tiles;
 pub mod obj;
+pub mod color;
 
 // #TODO remove these allows
 pub mod mode0;
@@ -74,6 +76,35 @@ impl ObjLineInfo {
 	}
 }
 
+/// Vertical mosaic counters. These count the lines since the start of the current
+/// mosaic block, they are reset at the start of a frame and whenever they pass
+/// the mosaic size that MOSAIC has at that point.
+#[derive(Default, Copy, Clone, RustcEncodable, RustcDecodable)]
+pub struct MosaicCounters {
+	pub bg: u16,
+	pub obj: u16,
+}
+
+impl MosaicCounters {
+	/// Moves the counters to the next line.
+	pub fn next_line(&mut self, mosaic: u16) {
+		let bg_size = (mosaic >> 4) & 0xf;
+		let obj_size = (mosaic >> 12) & 0xf;
+		self.bg = if self.bg >= bg_size { 0 } else { self.bg + 1 };
+		self.obj = if self.obj >= obj_size { 0 } else { self.obj + 1 };
+	}
+}
+
+/// The vertical part of WIN0 and WIN1. Each window has a flag that is turned on when
+/// VCOUNT reaches the window's top and off when it reaches the window's bottom,
+/// checked once at the start of every line (including the lines in VBlank).
+/// This is what makes windows with top > bottom wrap around the screen.
+#[derive(Default, Copy, Clone, RustcEncodable, RustcDecodable)]
+pub struct WindowFlags {
+	pub win0: bool,
+	pub win1: bool,
+}
+
 // No point in having a secondary screen buffer
 // since the GBA renders in scan lines anyway.
 pub type GbaLcdScreenBuffer = [GbaLcdLine; 160];
@@ -92,12 +123,27 @@ pub struct GbaDisplayLines {
 	pub bg2_enable: bool,
 	pub bg3_enable: bool,
 
-	pub obj_info: ObjLineInfo
+	pub obj_info: ObjLineInfo,
+
+	pub mosaic: MosaicCounters,
+
+	pub windows: WindowFlags,
+
+	/// DISPCNT's frame select bit (mode 4 and 5) latched at the start of the line.
+	pub frame_select: bool,
+
+	/// The GBA colors that come out of blending, they go through the
+	/// color LUT once the whole line has been drawn.
+	pub output: GbaBGLine
 }
 
 pub struct GbaLcd {
 	pub screen_buffer: GbaLcdScreenBuffer,
-	pub lines: GbaDisplayLines
+	pub lines: GbaDisplayLines,
+
+	/// Maps every GBA color to the color that is output for it, see `color::build_lut`.
+	color_lut: ColorLut,
+	color_profile: ColorProfile
 }
 
 impl GbaLcd {
@@ -114,13 +160,38 @@ impl GbaLcd {
 				bg1_enable: false,
 				bg2_enable: false,
 				bg3_enable: false,
-				obj_info: ObjLineInfo { data: [0u8; 240] }
-			}
+				obj_info: ObjLineInfo { data: [0u8; 240] },
+				mosaic: Default::default(),
+				windows: Default::default(),
+				frame_select: false,
+				output: [0; 240]
+			},
+			color_lut: color::build_lut(ColorProfile::Raw),
+			color_profile: ColorProfile::Raw
 		}
 	}
 
 	pub fn render_line(&mut self, memory: &mut GbaMemory, line: u16) {
+		self.render_segment(memory, line, 0, 240);
+		self.end_line(memory, line);
+	}
+
+	/// Draws the line using the current state of the registers and video memory,
+	/// but only pixels in [start, end) are written to the screen buffer.
+	/// This is called several times per line when the game changes things mid-line.
+	pub fn render_segment(&mut self, memory: &mut GbaMemory, line: u16, start: usize, end: usize) {
+		if start >= end { return }
 		let dispcnt = memory.get_reg(ioreg::DISPCNT);
+
+		// Forced blank shows white lines. The CPU's access to VRAM, palette RAM and OAM
+		// is never restricted while drawing here so there's nothing else to do.
+		if ((dispcnt >> 7) & 1) != 0 {
+			for pixel in self.lines.output[start..end].iter_mut() {
+				*pixel = 0x7fff;
+			}
+			return
+		}
+
 		self.clear_obj_line();
 
 		match dispcnt & 0x7 {
@@ -133,7 +204,80 @@ impl GbaLcd {
 			_ => unreachable!()
 		}
 
-		self.blend_line(line, memory);
+		self.blend_line(line, memory, start, end);
+	}
+
+	/// The parts of VRAM (offsets from the start of VRAM) that the rest of the line
+	/// can be drawn from with the registers as they are now. Writes to the rest of
+	/// VRAM don't have to stop the CPU while the line is being drawn, this is what
+	/// keeps games that stream into the bitmap modes from re-rendering every line
+	/// for every write.
+	pub fn visible_vram(&self, memory: &GbaMemory, line: u16) -> [(usize, usize); 2] {
+		let dispcnt = memory.get_reg(ioreg::DISPCNT);
+		if ((dispcnt >> 7) & 1) != 0 { return [(0, 0), (0, 0)] } // forced blank
+
+		let bg2_enabled = ((dispcnt >> 10) & 1) != 0;
+		let row = bg_mosaic_line(memory.get_reg(ioreg::BG2CNT), line, self.lines.mosaic.bg) as usize;
+		let page = if self.lines.frame_select { 0xA000 } else { 0 };
+		let bg = match dispcnt & 0x7 {
+			0 ... 2 if (dispcnt & 0x0F00) != 0 => (0, 0x10000),
+			3 if bg2_enabled => (row * 480, row * 480 + 480),
+			4 if bg2_enabled => (page + row * 240, page + row * 240 + 240),
+			5 if bg2_enabled && row < 128 => (page + row * 320, page + row * 320 + 320),
+			_ => (0, 0),
+		};
+
+		// The OBJ window is drawn from the OBJ tiles even with OBJs turned off.
+		let obj = if (dispcnt & 0x9000) != 0 { (0x10000, 0x18000) } else { (0, 0) };
+		[bg, obj]
+	}
+
+	/// Called at the start of every line to latch the state that can't change mid-line.
+	pub fn start_line(&mut self, memory: &GbaMemory, line: u16) {
+		if line == 0 {
+			let profile = psetting!(color_profile);
+			if profile != self.color_profile {
+				self.color_lut = color::build_lut(profile);
+				self.color_profile = profile;
+			}
+		}
+
+		self.lines.frame_select = ((memory.get_reg(ioreg::DISPCNT) >> 4) & 1) != 0;
+		self.lines.windows.win0 = window_v_flag(self.lines.windows.win0, memory.get_reg(ioreg::WIN0V), line);
+		self.lines.windows.win1 = window_v_flag(self.lines.windows.win1, memory.get_reg(ioreg::WIN1V), line);
+	}
+
+	/// Called once the whole line has been drawn.
+	pub fn end_line(&mut self, memory: &mut GbaMemory, line: u16) {
+		// Green swap (undocumented), every pair of pixels swaps green components.
+		// This happens to the GBA's colors, before they're converted for the screen.
+		if (memory.get_reg(ioreg::GREENSWAP) & 1) != 0 {
+			let output = &mut self.lines.output;
+			for x in 0..120 {
+				let (left, right) = (output[x * 2], output[x * 2 + 1]);
+				output[x * 2] = (left & !0x03e0) | (right & 0x03e0);
+				output[x * 2 + 1] = (right & !0x03e0) | (left & 0x03e0);
+			}
+		}
+
+		{
+			let color_lut = &self.color_lut;
+			let screen_line = &mut self.screen_buffer[line as usize];
+			for x in 0..240 {
+				screen_line[x] = color_lut[(self.lines.output[x] & 0x7fff) as usize];
+			}
+		}
+
+		if line >= 159 {
+			self.lines.mosaic = Default::default();
+		} else {
+			self.lines.mosaic.next_line(memory.get_reg(ioreg::MOSAIC));
+		}
+
+		// The reference points move on every line, even when their BG isn't being drawn.
+		let (bg2_dmx, bg2_dmy) = (memory.get_reg(ioreg::BG2PB), memory.get_reg(ioreg::BG2PD));
+		let (bg3_dmx, bg3_dmy) = (memory.get_reg(ioreg::BG3PB), memory.get_reg(ioreg::BG3PD));
+		memory.internal_regs.advance_bg_refs(bg2_dmx, bg2_dmy, bg3_dmx, bg3_dmy);
 	}
 
 	fn clear_obj_line(&mut self) {
@@ -146,11 +290,11 @@ impl GbaLcd {
 	// #TODO Consider making a second version of this function that doesn't use windows
 	//       at all. This seems to be how VBA does it but right now I'm not sure if the performance
 	//       gain will be worth the added complexity of maintaining both functions.
-	fn blend_line(&mut self, line: u16, memory: &GbaMemory) {
+	fn blend_line(&mut self, line: u16, memory: &GbaMemory, start: usize, end: usize) {
 		let dispcnt = memory.get_reg(ioreg::DISPCNT);
 
 		let backdrop = opaque_rgb5(memory.read16(0x05000000));
-		let output = &mut self.screen_buffer[line as usize][0..240];
+		let output = &mut self.lines.output;
 
 		let bg0_enabled = self.lines.bg0_enable && (((dispcnt >> 8) & 1) != 0) && debug_layer_on!(0); // #TODO remove debug layer code.
 		let bg1_enabled = self.lines.bg1_enable && (((dispcnt >> 9) & 1) != 0) && debug_layer_on!(1); // #TODO remove debug layer code.
@@ -174,10 +318,12 @@ impl GbaLcd {
 		let blend_eva = min!(16, blend_alpha & 0x1f);
 		let blend_evb = min!(16, (blend_alpha >> 8) & 0x1f);
 
+		// EVY (like EVA and EVB) is 0-16, the hardware treats 17-31 as 16. Without this
+		// brightening and darkening would carry out of (or borrow from) the 5-bit channels.
 		let bldy = memory.get_reg(ioreg::BLDY);
-		let blend_evy = bldy & 0x1f;
+		let blend_evy = min!(16, bldy & 0x1f);
 
-		let pixel_brightness_fn: fn(u16, GbaPixel) -> OutputPixel = match blend_mode {
+		let pixel_brightness_fn: fn(u16, GbaPixel) -> GbaPixel = match blend_mode {
 			2 => brighten_pixel,
 			3 => darken_pixel,
 			_ => pixel_lum_nop
@@ -189,33 +335,28 @@ impl GbaLcd {
 
 		let win0h = memory.get_reg(ioreg::WIN0H);
 		let win1h = memory.get_reg(ioreg::WIN1H);
-		let win0v = memory.get_reg(ioreg::WIN0V);
-		let win1v = memory.get_reg(ioreg::WIN1V);
 		let winin = memory.get_reg(ioreg::WININ);
 		let winout = memory.get_reg(ioreg::WINOUT);
 
-		let win0_left = (win0h >> 8) & 0xff; // inclusive
-		let win0_right = min!(240, win0h & 0xff); // exclusive
-		let win0_top = (win0v >> 8) & 0xff; // inclusive
-		let win0_bottom = min!(160, win0v & 0xff); // exclusive
+		// Only the horizontal part of the windows is checked per pixel.
+		let win0_on_line = win0_enabled && self.lines.windows.win0;
+		let win1_on_line = win1_enabled && self.lines.windows.win1;
 
-		let win1_left = (win1h >> 8) & 0xff; // inclusive
-		let win1_right = min!(240, win1h & 0xff); // exclusive
-		let win1_top = (win1v >> 8) & 0xff; // inclusive
-		let win1_bottom = min!(160, win1v & 0xff); // exclusive
+		// The OBJ window is only there if OBJs are being displayed.
+		let win_obj_on_line = win_obj_enabled && ((dispcnt >> 12) & 1) != 0;
 
 		let win0_in = winin & 0x3f;
 		let win1_in = (winin >> 8) & 0x3f;
 		let winout_in = winout & 0x3f;
 		let win_obj_in = (winout >> 8) & 0x3f;
 
-		for pidx in 0..240 {
+		for pidx in start..end {
 			let win_in_bits = if win0_enabled || win1_enabled || win_obj_enabled {
-				if win0_enabled && window_contains(pidx as u16, line, win0_left, win0_right, win0_top, win0_bottom) {
+				if win0_on_line && window_h_contains(pidx as u16, win0h) {
 					win0_in
-				} else if win1_enabled && window_contains(pidx as u16, line, win1_left, win1_right, win1_top, win1_bottom) {
+				} else if win1_on_line && window_h_contains(pidx as u16, win1h) {
 					win1_in
-				} else if self.lines.obj_info.is_window(pidx) {
+				} else if win_obj_on_line && self.lines.obj_info.is_window(pidx) {
 					win_obj_in
 				} else {
 					winout_in
@@ -295,14 +436,14 @@ impl GbaLcd {
 						} else if(blend_targets & blend_target_layer) != 0 { // blending two layers (also checking the second layer down is a 2nd target.)
 							output[pidx] = blend_pixels(blend_eva, blend_evb, output_color, blend_target_color);
 						} else { // No blending / brightness changes will occur.
-							output[pidx] = expand_color(output_color);
+							output[pidx] = output_color & 0x7fff;
 						}
 					} else {
-						output[pidx] = expand_color(output_color);
+						output[pidx] = output_color & 0x7fff;
 					}
 				}
 			} else {
-				output[pidx] = expand_color(output_color);
+				output[pidx] = output_color & 0x7fff;
 			}
 		}
 	}
@@ -318,6 +459,29 @@ pub fn expand_color(rgb5: u16) -> OutputPixel {
 }
 
 
+/// Repeats the first pixel of every horizontal mosaic block across the block.
+pub fn apply_mosaic_h(mosaic_h: u16, bg_line: &mut GbaBGLine) {
+	let mosaic_h = mosaic_h as usize;
+	if mosaic_h <= 1 { return }
+	for x in 0..240 {
+		bg_line[x] = bg_line[x - (x % mosaic_h)];
+	}
+}
+
+/// Returns the line that a BG should be drawn from when mosaic is enabled for it.
+#[inline(always)]
+pub fn bg_mosaic_line(bgcnt: u16, line: u16, mosaic_counter: u16) -> u16 {
+	if ((bgcnt >> 6) & 1) != 0 { line - min!(line, mosaic_counter) } else { line }
+}
+
+/// Applies the horizontal BG mosaic to a line if it is enabled in `bgcnt`.
+#[inline(always)]
+pub fn bg_mosaic_h(bgcnt: u16, memory: &GbaMemory, bg_line: &mut GbaBGLine) {
+	if ((bgcnt >> 6) & 1) != 0 {
+		apply_mosaic_h((memory.get_reg(ioreg::MOSAIC) & 0xf) + 1, bg_line);
+	}
+}
+
 #[inline(always)]
 pub fn is_transparent(pixel: GbaPixel) -> bool {
 	(pixel & 0x8000) == 0
@@ -335,46 +499,66 @@ pub fn opaque_rgb5(rgb5: u16) -> u16 {
 }
 
 #[inline(always)]
-fn window_contains(x: u16, y: u16, w_left: u16, w_right: u16, w_top: u16, w_bottom: u16) -> bool {
-	// #TODO make this handle the cross pattern that occurs when w_right is less than w_left
-	(x >= w_left) && (x < w_right) &&
-	(y >= w_top) && (y < w_bottom)
+/// Returns true if `x` is inside of a window's horizontal range (WINxH).
+/// The left edge is inclusive and the right edge is exclusive. If left > right
+/// the window wraps around the screen, right values past 240 just never turn it off.
+fn window_h_contains(x: u16, winh: u16) -> bool {
+	let left = (winh >> 8) & 0xff;
+	let right = winh & 0xff;
+	if left <= right {
+		x >= left && x < right
+	} else {
+		x >= left || x < right
+	}
 }
 
-fn blend_pixels(eva: u16, evb: u16, front: GbaPixel, back: GbaPixel) -> OutputPixel {
-	let (f_r, f_g, f_b) = expand_color(front);
-	let (b_r, b_g, b_b) = expand_color(back);
+/// Returns a window's vertical flag for `line` given its value on the previous line.
+fn window_v_flag(active: bool, winv: u16, line: u16) -> bool {
+	let top = (winv >> 8) & 0xff;
+	let bottom = winv & 0xff;
+	if line == top {
+		true
+	} else if line == bottom {
+		false
+	} else {
+		active
+	}
+}
 
-	return (
-		min!(255, (((f_r as u16) * eva) >> 4) + (((b_r as u16) * evb) >> 4)) as u8,
-		min!(255, (((f_g as u16) * eva) >> 4) + (((b_g as u16) * evb) >> 4)) as u8,
-		min!(255, (((f_b as u16) * eva) >> 4) + (((b_b as u16) * evb) >> 4)) as u8,
-	);
+// Blending works on the GBA's 5-bit channels the way the hardware does it and the
+// result goes through the color LUT like every other pixel.
+
+/// min(31, I1st*EVA + I2nd*EVB)
+fn blend_pixels(eva: u16, evb: u16, front: GbaPixel, back: GbaPixel) -> GbaPixel {
+	let blend = |shift: u16| -> u16 {
+		let f = (front >> shift) & 0x1f;
+		let b = (back >> shift) & 0x1f;
+		min!(31, ((f * eva) + (b * evb)) >> 4)
+	};
+	blend(0) | (blend(5) << 5) | (blend(10) << 10)
 }
 
 // PIXEL BRIGHTNESS FUNCTIONS:
 
 /// I1st + (31-I1st)*EVY
-fn brighten_pixel(blend_evy: u16, color: GbaPixel) -> OutputPixel {
-	let color = expand_color(color);
-	(
-		((color.0 as u16) + (((255 - (color.0 as u16)) * blend_evy) >> 4)) as u8,
-		((color.1 as u16) + (((255 - (color.1 as u16)) * blend_evy) >> 4)) as u8,
-		((color.2 as u16) + (((255 - (color.2 as u16)) * blend_evy) >> 4)) as u8
-	)
+fn brighten_pixel(blend_evy: u16, color: GbaPixel) -> GbaPixel {
+	let brighten = |shift: u16| -> u16 {
+		let c = (color >> shift) & 0x1f;
+		c + (((31 - c) * blend_evy) >> 4)
+	};
+	brighten(0) | (brighten(5) << 5) | (brighten(10) << 10)
 }
 
 /// I1st - (I1st)*EVY
-fn darken_pixel(blend_evy: u16, color: GbaPixel) -> OutputPixel {
-	let color = expand_color(color);
-	(
-		((color.0 as u16) - (((color.0 as u16) * blend_evy) >> 4)) as u8,
-		((color.1 as u16) - (((color.1 as u16) * blend_evy) >> 4)) as u8,
-		((color.2 as u16) - (((color.2 as u16) * blend_evy) >> 4)) as u8
-	)
+fn darken_pixel(blend_evy: u16, color: GbaPixel) -> GbaPixel {
+	let darken = |shift: u16| -> u16 {
+		let c = (color >> shift) & 0x1f;
+		c - ((c * blend_evy) >> 4)
+	};
+	darken(0) | (darken(5) << 5) | (darken(10) << 10)
 }
 
 /// Just does nothing to the pixel
-fn pixel_lum_nop(_: u16, color: GbaPixel) -> OutputPixel {
-	expand_color(color)
+fn pixel_lum_nop(_: u16, color: GbaPixel) -> GbaPixel {
+	color & 0x7fff
 }
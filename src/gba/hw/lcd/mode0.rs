@@ -9,6 +9,8 @@ pub fn render_mode_0(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 	lines.bg2_enable = true;
 	lines.bg3_enable = true;
 
+	let mosaic_counter = lines.mosaic.bg;
+
 	// I use this in both render_mode0 and render_mode1, maybe I could just make a common function for them or something?
 	// I would only need to pass in dispcnt and line.
 	let try_render_text_bg = |enable: u16, bgcnt: ioreg::IORegister16, bghofs: ioreg::IORegister16, bgvofs: ioreg::IORegister16, 
@@ -20,6 +22,7 @@ pub fn render_mode_0(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 				memory.get_reg(bgvofs) & 0x1ff,
 				memory,
 				line,
+				mosaic_counter,
 				bg_line);
 		}
 	};
@@ -9,6 +9,8 @@ pub fn render_mode_1(dispcnt: u16, memory: &mut GbaMemory, line: u16, lines: &mu
 	lines.bg2_enable = true;
 	lines.bg3_enable = false;
 
+	let mosaic_counter = lines.mosaic.bg;
+
 	// I use this in both render_mode0 and render_mode1, maybe I could just make a common function for them or something?
 	// I would only need to pass in dispcnt and line.
 	let try_render_text_bg = |memory: &GbaMemory, enable: u16, bgcnt: ioreg::IORegister16, bghofs: ioreg::IORegister16, bgvofs: ioreg::IORegister16, 
@@ -20,6 +22,7 @@ pub fn render_mode_1(dispcnt: u16, memory: &mut GbaMemory, line: u16, lines: &mu
 				memory.get_reg(bgvofs) & 0x1ff,
 				memory,
 				line,
+				mosaic_counter,
 				bg_line);
 		}
 	};
@@ -36,7 +39,7 @@ pub fn render_mode_1(dispcnt: u16, memory: &mut GbaMemory, line: u16, lines: &mu
 			dmx_reg: ioreg::BG2PB,
 			dmy_reg: ioreg::BG2PD
 		};
-		draw_tiles_rs_mode(memory.get_reg(ioreg::BG2CNT), params, memory, &mut lines.bg2);
+		draw_tiles_rs_mode(memory.get_reg(ioreg::BG2CNT), params, memory, lines.mosaic.bg, &mut lines.bg2);
 	}
 	
 	draw_objs(
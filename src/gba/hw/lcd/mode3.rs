@@ -24,13 +24,15 @@ pub fn render_mode_3(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 	lines.bg2_enable = true;
 	lines.bg3_enable = false;
 
+	let bg2cnt = memory.get_reg(ioreg::BG2CNT);
 	let vram = memory.get_region(MEM_VRAM);
-	let frame_line_offset = 480 * line as usize;
+	let frame_line_offset = 480 * bg_mosaic_line(bg2cnt, line, lines.mosaic.bg) as usize;
 	for col in 0..240 {
 		let col_offset = frame_line_offset + col * 2;
 		let pixel = opaque_rgb5(vram.direct_read16(col_offset));
 		lines.bg2[col] = pixel;
 	}
+	bg_mosaic_h(bg2cnt, memory, &mut lines.bg2);
 
 	draw_objs(
 		(0x06010000, 0x06017FFF),
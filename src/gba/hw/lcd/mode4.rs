@@ -22,12 +22,13 @@ pub fn render_mode_4(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 	lines.bg3_enable = false;
 	
 	let palette = memory.get_slice(0x05000000, 0x050001FF);
-	let frame_buffer = if ((dispcnt >> 4) & 1) != 0 {
+	let frame_buffer = if lines.frame_select {
 		memory.get_slice(FRAME_1_BUFFER.0, FRAME_1_BUFFER.1)
 	} else {
 		memory.get_slice(FRAME_0_BUFFER.0, FRAME_0_BUFFER.1)
 	};
-	let frame_line_offset = 240 * (line as usize);
+	let bg2cnt = memory.get_reg(ioreg::BG2CNT);
+	let frame_line_offset = 240 * (bg_mosaic_line(bg2cnt, line, lines.mosaic.bg) as usize);
 	for col in 0..240 {
 		let col_offset = frame_line_offset + col;
 		// #TODO handle transparency
@@ -35,6 +36,7 @@ pub fn render_mode_4(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 		let pixel = opaque_rgb5(palette.direct_read16((pal_ref as usize) * 2));
 		lines.bg2[col] = pixel;
 	}
+	bg_mosaic_h(bg2cnt, memory, &mut lines.bg2);
 
 	draw_objs(
 		(0x06010000, 0x06017FFF),
@@ -11,13 +11,15 @@ pub fn render_mode_5(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 	lines.bg2_enable = true;
 	lines.bg3_enable = false;
 	
-	if line < 128 {
-		let frame_buffer = if ((dispcnt >> 4) & 1) != 0 {
+	let bg2cnt = memory.get_reg(ioreg::BG2CNT);
+	let bg_line = bg_mosaic_line(bg2cnt, line, lines.mosaic.bg);
+	if bg_line < 128 {
+		let frame_buffer = if lines.frame_select {
 			memory.get_slice(FRAME_1_BUFFER.0, FRAME_1_BUFFER.1)
 		} else {
 			memory.get_slice(FRAME_0_BUFFER.0, FRAME_0_BUFFER.1)
 		};
-		let frame_line_offset = 160 * (line as usize) * 2;
+		let frame_line_offset = 160 * (bg_line as usize) * 2;
 		for col in 0..160 {
 			let col_offset = frame_line_offset + col * 2;
 			let pixel = opaque_rgb5(frame_buffer.direct_read16(col_offset));
@@ -27,6 +29,7 @@ pub fn render_mode_5(dispcnt: u16, memory: &GbaMemory, line: u16, lines: &mut Gb
 		for col in 160..240 {
 			lines.bg2[col] = 0;
 		}
+		bg_mosaic_h(bg2cnt, memory, &mut lines.bg2);
 	} else {
 		for col in 0..240 {
 			lines.bg2[col] = 0;
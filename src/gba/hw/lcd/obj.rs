@@ -20,7 +20,9 @@ struct ObjData {
 	attr1: u16,
 	attr2: u16,
 	mosaic_x: u16,
-	mosaic_y: u16
+
+	/// Lines since the start of the current vertical mosaic block.
+	mosaic_counter: u16
 }
 
 // These use i16s instead of u16s
@@ -48,7 +50,7 @@ pub fn draw_objs(tiles_region: (u32, u32), one_dim: bool, hblank_free: bool, mem
 
 	let mosaic = memory.get_reg(ioreg::MOSAIC);
 	obj_data.mosaic_x = ((mosaic >> 8) & 0xf) + 1;
-	obj_data.mosaic_y = ((mosaic >> 12) & 0xf) + 1;
+	obj_data.mosaic_counter = lines.mosaic.obj;
 
 	let mut attr_addr = 0;
 
@@ -86,6 +88,14 @@ pub fn draw_objs(tiles_region: (u32, u32), one_dim: bool, hblank_free: bool, mem
 }
 
 
+/// Returns the texture x of the first pixel of the horizontal mosaic block that
+/// screen x `px` is in. Mosaic blocks are aligned to the screen, not to the OBJ.
+#[inline(always)]
+fn mosaic_tx(tx: u16, px: u16, mosaic_x: u16) -> u16 {
+	let back = px % mosaic_x;
+	if tx >= back { tx - back } else { 0 }
+}
+
 // (width, height, shift-per-line) shift-per-line is basically log2(8x8 tiles per line)
 const OBJ_SIZES: [(u16, u16, u16); 16] = [
 	(8, 8, 0), (16, 16, 1), (32, 32, 2), (64, 64, 3), // square
@@ -208,7 +218,7 @@ pub fn get_simple_obj_dot_8bpp_2d(tiles: &[u8], palette: &[u8], attr2: u16, ox:
 /// Draw an object with no rotation/scaling.
 fn draw_simple_obj(one_dimensional: bool, tile_region: &[u8], palette_region: &[u8], obj: ObjData, line: u16, lines: &mut GbaDisplayLines, cycles_remaining: &mut i32) {
 	let semi_transparent = ((obj.attr0 >> 10) & 0x3) == 1;
-	// #TODO implement mosaics
+	let mosaic = ((obj.attr0 >> 12) & 1) == 1;
 	let horizontal_flip = ((obj.attr1 >> 12) & 1) == 1;
 	let vertical_flip = ((obj.attr1 >> 13) & 1) == 1;
 
@@ -236,18 +246,19 @@ fn draw_simple_obj(one_dimensional: bool, tile_region: &[u8], palette_region: &[
 	if (line - py) < height { // negatives will wrap (making them larger)
 		let mut ty = line - py;// texture y
 
-		if ((obj.attr0 >> 12) & 1) == 1 { // if mosaic bit is on.
-			ty -= ty % obj.mosaic_y;
+		if mosaic {
+			// The line used is the first line of the current mosaic block.
+			ty = if ty >= obj.mosaic_counter { ty - obj.mosaic_counter } else { 0 };
 		}
 
-		// #TODO I have no idea wtf mosaic X does exactly.
 
 		let f_ty = if vertical_flip { (height - 1) - ty } else { ty }; // possibly flipped ty
 		let tx_offset = if (px + width) > 512 { 512 - px } else { 0 };
 		if (px < 240) || tx_offset != 0 {
 			for tx in 0..width {
 				if px < 240 && lines.obj_info.get_priority(px as usize) == 0 { // on screen and nothing has been drawn there
-					let f_tx = if horizontal_flip { (width - 1) - tx } else { tx }; // possibly flipped tx.
+					let m_tx = if mosaic { mosaic_tx(tx, px, obj.mosaic_x) } else { tx };
+					let f_tx = if horizontal_flip { (width - 1) - m_tx } else { m_tx }; // possibly flipped tx.
 					let dot = get_dot(tile_region, palette_region, obj.attr2, f_tx, f_ty, (width, height, line_shift));
 					if dot != 0 { // #TODO might want to check for transparency here (bit 15) instead of just zero.
 						lines.obj[px as usize] = dot;
@@ -269,8 +280,7 @@ fn draw_simple_obj(one_dimensional: bool, tile_region: &[u8], palette_region: &[
 fn draw_rot_scale_obj(one_dimensional: bool, tile_region: &[u8], palette_region: &[u8], obj: ObjData, affine: ObjAffineData, line: u16, lines: &mut GbaDisplayLines, cycles_remaining: &mut i32) {
 	*cycles_remaining -= 10;
 	let semi_transparent = ((obj.attr0 >> 10) & 0x3) == 1;
-	// #TODO implement mosaics
-	// let mosaic = ((attr0 >> 12) & 1) == 1;
+	let mosaic = ((obj.attr0 >> 12) & 1) == 1;
 
 	let get_dot: fn(&[u8], &[u8], u16, u16, u16, (u16, u16, u16)) -> GbaPixel = if one_dimensional {
 		if ((obj.attr0 >> 13) & 1) == 1 { get_simple_obj_dot_8bpp_1d }
@@ -306,11 +316,11 @@ fn draw_rot_scale_obj(one_dimensional: bool, tile_region: &[u8], palette_region:
 	if (line - py) < height { // negatives will wrap (making them larger)
 		let mut ty = line - py;// texture y (before transformations and stuff)
 
-		if ((obj.attr0 >> 12) & 1) == 1 { // if mosaic bit is on.
-			ty -= ty % obj.mosaic_y;
+		if mosaic {
+			// The line used is the first line of the current mosaic block.
+			ty = if ty >= obj.mosaic_counter { ty - obj.mosaic_counter } else { 0 };
 		}
 
-		// #TODO I have no idea wtf mosaic X does exactly.
 		let tx_offset = if (px + width) > 512 { 512 - px } else { 0 };
 		if (px < 240) || tx_offset != 0 {
 			// affine x and y
@@ -320,8 +330,13 @@ fn draw_rot_scale_obj(one_dimensional: bool, tile_region: &[u8], palette_region:
 			for _ in 0..width {
 				if px < 240 && lines.obj_info.get_priority(px as usize) == 0 { // nothing has been drawn there
 					// ax & ay without the fractional parts.
-					let i_ax = ax >> 8;
-					let i_ay = ay >> 8;
+					let (i_ax, i_ay) = if mosaic {
+						// Step back to the first pixel of the horizontal mosaic block.
+						let back = (px % obj.mosaic_x) as i16;
+						((ax - back * affine.dx) >> 8, (ay - back * affine.dy) >> 8)
+					} else {
+						(ax >> 8, ay >> 8)
+					};
 
 					if i_ax >= 0 && i_ax < (t_width as i16) && i_ay >= 0 && i_ay < (t_height as i16) && px < 240 {
 						let dot = get_dot(tile_region, palette_region, obj.attr2, i_ax as u16, i_ay as u16, (t_width, t_height, line_shift));
@@ -347,7 +362,7 @@ fn draw_rot_scale_obj(one_dimensional: bool, tile_region: &[u8], palette_region:
 
 /// Draw an object with no rotation/scaling.
 fn draw_simple_obj_window(one_dimensional: bool, tile_region: &[u8], palette_region: &[u8], obj: ObjData, line: u16, lines: &mut GbaDisplayLines) {
-	// #TODO implement mosaics
+	let mosaic = ((obj.attr0 >> 12) & 1) == 1;
 	let horizontal_flip = ((obj.attr1 >> 12) & 1) == 1;
 	let vertical_flip = ((obj.attr1 >> 13) & 1) == 1;
 
@@ -375,11 +390,11 @@ fn draw_simple_obj_window(one_dimensional: bool, tile_region: &[u8], palette_reg
 	if (line - py) < height { // negatives will wrap (making them larger)
 		let mut ty = line - py;// texture y
 
-		if ((obj.attr0 >> 12) & 1) == 1 { // if mosaic bit is on.
-			ty -= ty % obj.mosaic_y;
+		if mosaic {
+			// The line used is the first line of the current mosaic block.
+			ty = if ty >= obj.mosaic_counter { ty - obj.mosaic_counter } else { 0 };
 		}
 
-		// #TODO I have no idea wtf mosaic X does exactly.
 
 		let f_ty = if vertical_flip { (height - 1) - ty } else { ty }; // possibly flipped ty
 		let tx_offset = if (px + width) > 512 { 512 - px } else { 0 };
@@ -387,7 +402,8 @@ fn draw_simple_obj_window(one_dimensional: bool, tile_region: &[u8], palette_reg
 			for tx in 0..width {
 				// #TODO not sure if OBJ windows care about priority.
 				if px < 240 /* && lines.obj_info.get_priority(px as usize) == 0 */ { // on screen and nothing has been drawn there
-					let f_tx = if horizontal_flip { (width - 1) - tx } else { tx }; // possibly flipped tx.
+					let m_tx = if mosaic { mosaic_tx(tx, px, obj.mosaic_x) } else { tx };
+					let f_tx = if horizontal_flip { (width - 1) - m_tx } else { m_tx }; // possibly flipped tx.
 					let dot = get_dot(tile_region, palette_region, obj.attr2, f_tx, f_ty, (width, height, line_shift));
 					if dot != 0 { // #TODO might want to check for transparency here (bit 15) instead of just zero.
 						lines.obj[px as usize] = dot;
@@ -401,8 +417,7 @@ fn draw_simple_obj_window(one_dimensional: bool, tile_region: &[u8], palette_reg
 }
 
 fn draw_rot_scale_obj_window(one_dimensional: bool, tile_region: &[u8], palette_region: &[u8], obj: ObjData, affine: ObjAffineData, line: u16, lines: &mut GbaDisplayLines) {
-	// #TODO implement mosaics
-	// let mosaic = ((attr0 >> 12) & 1) == 1;
+	let mosaic = ((obj.attr0 >> 12) & 1) == 1;
 
 	let get_dot: fn(&[u8], &[u8], u16, u16, u16, (u16, u16, u16)) -> GbaPixel = if one_dimensional {
 		if ((obj.attr0 >> 13) & 1) == 1 { get_simple_obj_dot_8bpp_1d }
@@ -438,11 +453,11 @@ fn draw_rot_scale_obj_window(one_dimensional: bool, tile_region: &[u8], palette_
 	if (line - py) < height { // negatives will wrap (making them larger)
 		let mut ty = line - py;// texture y (before transformations and stuff)
 
-		if ((obj.attr0 >> 12) & 1) == 1 { // if mosaic bit is on.
-			ty -= ty % obj.mosaic_y;
+		if mosaic {
+			// The line used is the first line of the current mosaic block.
+			ty = if ty >= obj.mosaic_counter { ty - obj.mosaic_counter } else { 0 };
 		}
 
-		// #TODO I have no idea wtf mosaic X does exactly.
 		let tx_offset = if (px + width) > 512 { 512 - px } else { 0 };
 		if (px < 240) || tx_offset != 0 {
 			// affine x and y
@@ -453,8 +468,13 @@ fn draw_rot_scale_obj_window(one_dimensional: bool, tile_region: &[u8], palette_
 				// #TODO not sure if OBJ windows care about priority.
 				if px < 240 /* && lines.obj_info.get_priority(px as usize) == 0 */ { // nothing has been drawn there
 					// ax & ay without the fractional parts.
-					let i_ax = ax >> 8;
-					let i_ay = ay >> 8;
+					let (i_ax, i_ay) = if mosaic {
+						// Step back to the first pixel of the horizontal mosaic block.
+						let back = (px % obj.mosaic_x) as i16;
+						((ax - back * affine.dx) >> 8, (ay - back * affine.dy) >> 8)
+					} else {
+						(ax >> 8, ay >> 8)
+					};
 
 					if i_ax >= 0 && i_ax < (t_width as i16) && i_ay >= 0 && i_ay < (t_height as i16) && px < 240 {
 						let dot = get_dot(tile_region, palette_region, obj.attr2, i_ax as u16, i_ay as u16, (t_width, t_height, line_shift));
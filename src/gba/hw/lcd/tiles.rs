@@ -50,14 +50,13 @@ const RS_MODE_SCREEN_SIZES: [(i32, i32); 4] = [
 	(1024, 1024)
 ];
 
-pub fn draw_tiles_text_mode(bgcnt: u16, xoffset: u16, yoffset: u16, memory: &GbaMemory, line: u16, bg_line: &mut GbaBGLine) {
+pub fn draw_tiles_text_mode(bgcnt: u16, xoffset: u16, yoffset: u16, memory: &GbaMemory, line: u16, mosaic_counter: u16, bg_line: &mut GbaBGLine) {
 	measure_start(MEASURE_TILE_RENDER_TIME);
 	measure_iteration(MEASURE_TILE_RENDER_TIME);
 	let vram_tile_data = memory.get_slice(0x06000000, 0x0600FFFF);
 
 	let character_base_block = (((bgcnt >> 2) & 0x3) as u32) * kbytes!(16); // (0-3, in units of 16 KBytes) (=BG Tile Data)
-	// #TODO implement mosaics
-	// let mosaic = ((bgcnt >> 6) & 0x1) == 1;
+	let line = bg_mosaic_line(bgcnt, line, mosaic_counter);
 
 	let palette_type = ((bgcnt >> 7) & 0x1) == 1; // 0=16/16 (4bit), 1=256/1 (8bit)
 	let tile_copy: fn(&[u8], &[u8], &mut [GbaPixel], u16, u32, u32) = if palette_type {
@@ -113,7 +112,6 @@ pub fn draw_tiles_text_mode(bgcnt: u16, xoffset: u16, yoffset: u16, memory: &Gba
 
 	// 232 because we don't want to draw the last tile unless it's being shown completely.
 	while column < 232 {
-		// #TODO handle mosaic
 		let pixel_x = (column + (xoffset as u32)) & __sw_mask;
 		let sc = ((pixel_x >> 8) & 1) + (((pixel_y >> 8) & 1) << (screen_width >> 9));
 		let tile_x = (pixel_x & 255) >> 3;
@@ -143,6 +141,8 @@ pub fn draw_tiles_text_mode(bgcnt: u16, xoffset: u16, yoffset: u16, memory: &Gba
 				map_tile_info, 0, pixel_y & 7);
 		}
 	}
+
+	bg_mosaic_h(bgcnt, memory, bg_line);
 	measure_end(MEASURE_TILE_RENDER_TIME);
 }
 
@@ -165,23 +165,32 @@ pub struct BGRotScaleParams {
 	pub dmy_reg: IORegister16 // pd
 }
 
-pub fn draw_tiles_rs_mode(bgcnt: u16, params: BGRotScaleParams, memory: &mut GbaMemory, bg_line: &mut GbaBGLine) {
+pub fn draw_tiles_rs_mode(bgcnt: u16, params: BGRotScaleParams, memory: &GbaMemory, mosaic_counter: u16, bg_line: &mut GbaBGLine) {
 	// I work with signed types in this function instead of the normal u32
 	// for reasons that I immediately forgot after writing it. (◕‿◕✿)
-	let sx = memory.internal_regs.bg2x as i32;
-	let sy = memory.internal_regs.bg2y as i32;
-	let mut x = sx;
-	let mut y = sy;
+	// The internal reference points are advanced in GbaLcd::end_line.
+	let (sx, sy) = if params.ref_x_reg.0 == ioreg::BG3X.0 {
+		(memory.internal_regs.bg3x as i32, memory.internal_regs.bg3y as i32)
+	} else {
+		(memory.internal_regs.bg2x as i32, memory.internal_regs.bg2y as i32)
+	};
 	let dx = (memory.get_reg(params.dx_reg) as i16) as i32; // sign extension from 16bits to 32 bits
 	let dmx = (memory.get_reg(params.dmx_reg) as i16) as i32; // sign extension from 16bits to 32 bits
 	let dy = (memory.get_reg(params.dy_reg) as i16) as i32; // sign extension from 16bits to 32 bits
 	let dmy = (memory.get_reg(params.dmy_reg) as i16) as i32; // sign extension from 16bits to 32 bits
 
+	// With vertical mosaic every line in a block is drawn from the block's first line,
+	// so step the reference point back to where it was on that line.
+	let (mut x, mut y) = if ((bgcnt >> 6) & 1) != 0 {
+		let back = mosaic_counter as i32;
+		(sx.wrapping_sub(dmx.wrapping_mul(back)), sy.wrapping_sub(dmy.wrapping_mul(back)))
+	} else {
+		(sx, sy)
+	};
+
 	{
 		let vram_tile_data = memory.get_slice(0x06000000, 0x0600FFFF);
 		let character_base_block = (((bgcnt >> 2) & 0x3) as u32) * kbytes!(16); // (0-3, in units of 16 KBytes) (=BG Tile Data)
-		// #TODO implement mosaics
-		// let mosaic = ((bgcnt >> 6) & 0x1) == 1;
 		let palette = memory.get_slice(0x05000000, 0x050001FF);
 		let screen_base_block = (((bgcnt >> 8) & 0x1f) as u32) * kbytes!(2); // (0-31, in units of 2 KBytes) (=BG Map Data)
 		let wraparound = ((bgcnt >> 13) & 0x1) == 1; // (0=Transparent, 1=Wraparound; BG2CNT/BG3CNT only)
@@ -252,8 +261,7 @@ pub fn draw_tiles_rs_mode(bgcnt: u16, params: BGRotScaleParams, memory: &mut Gba
 		}
 	}
 
-	memory.internal_regs.bg2x += dmx as u32;
-	memory.internal_regs.bg2y += dmy as u32;
+	bg_mosaic_h(bgcnt, memory, bg_line);
 }
 
 fn copy_tile_line4bpp(palette: &[u8], char_data: &[u8], output: &mut [GbaPixel], tile_info: u16, tx: u32, ty: u32) {
@@ -5,4 +5,5 @@ pub mod lcd;
 pub mod joypad;
 pub mod dma;
 pub mod audio;
-pub mod timers;
\ No newline at end of file
+pub mod timers;
+pub mod serial;
\ No newline at end of file
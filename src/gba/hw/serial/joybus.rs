@@ -0,0 +1,107 @@
+//! JOY Bus mode, the other end of the port is a `JoyBusHost`.
+//!
+//! JOYCNT:
+//!   0     Device Reset Flag     (Command FFh)     (Read/Acknowledge)
+//!   1     Receive Complete Flag (Command 15h)     (Read/Acknowledge)
+//!   2     Send Complete Flag    (Command 14h)     (Read/Acknowledge)
+//!   6     IRQ when receiving a command            (0=Disable, 1=Enable)
+//!
+//! JOY_STAT:
+//!   1     Receive Status Flag   (0=JOY_RECV empty, 1=JOY_RECV holds new data) (Read Only)
+//!   3     Send Status Flag      (0=JOY_TRANS was read, 1=JOY_TRANS holds new data) (Read Only)
+//!   4-5   General Purpose Flags (Read/Write)
+
+use super::super::super::core::cpu::ArmCpu;
+use super::super::super::core::memory::*;
+use super::super::super::device::joybus::{JoyBusHost, JoyBusCommand};
+use super::super::super::INT_SERIAL;
+use super::SerialMode;
+
+const JOYCNT_RESET: u16 = 0x01;
+const JOYCNT_RECEIVE: u16 = 0x02;
+const JOYCNT_SEND: u16 = 0x04;
+const JOYCNT_IRQ: u16 = 0x40;
+
+const JOYSTAT_RECEIVE: u16 = 0x02;
+const JOYSTAT_SEND: u16 = 0x08;
+const JOYSTAT_GENERAL: u16 = 0x30;
+
+/// GBA device type reported in the reply to Reset and Status.
+const DEVICE_TYPE: [u8; 2] = [0x00, 0x04];
+
+/// Runs every command from the host that is due.
+pub fn sync(cpu: &mut ArmCpu, host: &mut Option<JoyBusHost>) {
+	let host = match *host {
+		Some(ref mut host) => host,
+		None => return,
+	};
+	if super::mode(cpu) != SerialMode::JoyBus { return }
+
+	while let Some(command) = host.next_command(cpu.clock.cycles) {
+		let reply = command_received(cpu, command);
+		host.on_reply(command, reply);
+	}
+}
+
+/// Rebuilds JOYCNT and JOY_STAT after the game wrote to them or to JOY_TRANS.
+/// JOYCNT's flags are acknowledged in `InternalRegisters::on_reg_write`.
+pub fn on_write(cpu: &mut ArmCpu) {
+	let joycnt = cpu.memory.get_reg(ioreg::JOYCNT);
+	let joystat = cpu.memory.get_reg(ioreg::JOY_STAT);
+	let (joycnt_flags, joystat_flags) = {
+		let serial = &cpu.memory.internal_regs.serial;
+		(serial.joycnt_flags, serial.joystat_flags.get())
+	};
+	cpu.memory.set_reg(ioreg::JOYCNT, (joycnt & JOYCNT_IRQ) | joycnt_flags);
+	cpu.memory.set_reg(ioreg::JOY_STAT, (joystat & JOYSTAT_GENERAL) | joystat_flags);
+}
+
+/// Updates JOY_STAT after the game read JOY_RECV, see `GbaMemory::on_read`.
+pub fn tick(cpu: &mut ArmCpu) {
+	let joystat = cpu.memory.get_reg(ioreg::JOY_STAT);
+	let flags = cpu.memory.internal_regs.serial.joystat_flags.get();
+	if (joystat & JOYSTAT_RECEIVE) != (flags & JOYSTAT_RECEIVE) {
+		on_write(cpu);
+	}
+}
+
+/// Handles a command sent by the host and returns the GBA's reply.
+pub fn command_received(cpu: &mut ArmCpu, command: JoyBusCommand) -> Vec<u8> {
+	let mut reply = Vec::with_capacity(5);
+	let joycnt_flag = match command {
+		JoyBusCommand::Reset => {
+			reply.extend_from_slice(&DEVICE_TYPE);
+			JOYCNT_RESET
+		},
+		JoyBusCommand::Status => {
+			reply.extend_from_slice(&DEVICE_TYPE);
+			0
+		},
+		JoyBusCommand::Read => {
+			let trans = cpu.memory.get_reg(ioreg::JOY_TRANS);
+			reply.push(trans as u8);
+			reply.push((trans >> 8) as u8);
+			reply.push((trans >> 16) as u8);
+			reply.push((trans >> 24) as u8);
+			let flags = cpu.memory.internal_regs.serial.joystat_flags.get();
+			cpu.memory.internal_regs.serial.joystat_flags.set(flags & !JOYSTAT_SEND);
+			JOYCNT_SEND
+		},
+		JoyBusCommand::Write(value) => {
+			cpu.memory.set_reg(ioreg::JOY_RECV, value);
+			let flags = cpu.memory.internal_regs.serial.joystat_flags.get();
+			cpu.memory.internal_regs.serial.joystat_flags.set(flags | JOYSTAT_RECEIVE);
+			JOYCNT_RECEIVE
+		},
+	};
+
+	cpu.memory.internal_regs.serial.joycnt_flags |= joycnt_flag;
+	on_write(cpu);
+	reply.push(cpu.memory.get_reg(ioreg::JOY_STAT) as u8);
+
+	if joycnt_flag != 0 && (cpu.memory.get_reg(ioreg::JOYCNT) & JOYCNT_IRQ) != 0 {
+		cpu.hardware_interrupt(INT_SERIAL);
+	}
+
+	reply
+}
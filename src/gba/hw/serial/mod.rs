@@ -0,0 +1,63 @@
+//! Serial port (SIO). RCNT and SIOCNT select which mode the port is in,
+//! each emulated mode lives in its own module.
+
+pub mod multiplayer;
+pub mod joybus;
+pub mod uart;
+
+use super::super::core::cpu::ArmCpu;
+use super::super::core::memory::*;
+use super::super::device::GbaDevice;
+
+/// Baud rates selected by SIOCNT bits 0-1.
+pub const BAUD_RATES: [u64; 4] = [9600, 38400, 57600, 115200];
+
+pub const SIOCNT_BUSY: u16 = 0x0080;
+pub const SIOCNT_IRQ: u16 = 0x4000;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SerialMode {
+	Normal8,
+	Normal32,
+	MultiPlayer,
+	Uart,
+	GeneralPurpose,
+	JoyBus,
+}
+
+/// Returns the mode selected by RCNT bits 14-15 and SIOCNT bits 12-13.
+pub fn mode(cpu: &ArmCpu) -> SerialMode {
+	let rcnt = cpu.memory.get_reg(ioreg::RCNT);
+	if (rcnt & 0x8000) != 0 {
+		if (rcnt & 0x4000) != 0 { SerialMode::JoyBus }
+		else { SerialMode::GeneralPurpose }
+	} else {
+		match (cpu.memory.get_reg(ioreg::SIOCNT) >> 12) & 0x3 {
+			0 => SerialMode::Normal8,
+			1 => SerialMode::Normal32,
+			2 => SerialMode::MultiPlayer,
+			_ => SerialMode::Uart,
+		}
+	}
+}
+
+/// Called once before running the CPU for a chunk of cycles.
+/// This is where devices on the other end of the port get to talk to the GBA.
+pub fn sync(cpu: &mut ArmCpu, device: &mut GbaDevice) {
+	multiplayer::sync(cpu, &mut device.link);
+	joybus::sync(cpu, &mut device.joybus);
+	uart::sync(cpu, &mut device.uart);
+}
+
+/// Called after every CPU step.
+pub fn tick(cpu: &mut ArmCpu, device: &mut GbaDevice) {
+	if cpu.memory.internal_regs.serial.dirty {
+		cpu.memory.internal_regs.serial.dirty = false;
+		multiplayer::on_write(cpu, &mut device.link);
+		joybus::on_write(cpu);
+		uart::on_write(cpu);
+	}
+	multiplayer::tick(cpu, &mut device.link);
+	joybus::tick(cpu);
+	uart::tick(cpu, &mut device.uart);
+}
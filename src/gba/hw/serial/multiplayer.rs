@@ -0,0 +1,151 @@
+//! Multi-Player mode, the other end of the cable is a `LinkDevice`.
+
+use super::super::super::core::cpu::ArmCpu;
+use super::super::super::core::memory::*;
+use super::super::super::device::link::LinkDevice;
+use super::super::super::{INT_SERIAL, CLOCK_RATE};
+use super::{SerialMode, BAUD_RATES, SIOCNT_BUSY, SIOCNT_IRQ};
+
+/// SIOCNT bits that are set by the hardware and not by writes.
+const SIOCNT_READ_ONLY: u16 = 0x007C;
+
+fn multiplayer_mode(cpu: &ArmCpu) -> bool {
+	super::mode(cpu) == SerialMode::MultiPlayer
+}
+
+/// The number of cycles that a transfer takes.
+/// Every GBA on the cable sends a start bit, 16 data bits and a stop bit.
+fn transfer_cycles(siocnt: u16, players: usize) -> u64 {
+	let baud = BAUD_RATES[(siocnt & 3) as usize];
+	(18 * players as u64 * CLOCK_RATE) / baud
+}
+
+/// Called once before running the CPU for a chunk of cycles.
+/// Keeps the link cable in sync with the CPU and picks up transfers
+/// started by the parent on the other end.
+pub fn sync(cpu: &mut ArmCpu, link: &mut Option<LinkDevice>) {
+	let link = match *link {
+		Some(ref mut link) => link,
+		None => return,
+	};
+
+	link.sync(cpu.clock.cycles);
+
+	if !link.is_parent() && !cpu.memory.internal_regs.serial.busy {
+		if let Some((_, end)) = link.take_transfer() {
+			if multiplayer_mode(cpu) {
+				cpu.memory.internal_regs.serial.busy = true;
+				cpu.memory.internal_regs.serial.transfer_end = end;
+				let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+				cpu.memory.set_reg(ioreg::SIOCNT, siocnt | SIOCNT_BUSY);
+			}
+		}
+	}
+}
+
+/// Called after every CPU step.
+pub fn tick(cpu: &mut ArmCpu, link: &mut Option<LinkDevice>) {
+	if cpu.memory.internal_regs.serial.busy && cpu.clock.cycles >= cpu.memory.internal_regs.serial.transfer_end {
+		complete_transfer(cpu, link);
+	}
+}
+
+/// Restores the read-only bits of SIOCNT after the game has written to it
+/// and starts a transfer if this GBA is the parent.
+pub fn on_write(cpu: &mut ArmCpu, link: &mut Option<LinkDevice>) {
+	if !multiplayer_mode(cpu) { return }
+
+	let send = cpu.memory.get_reg(ioreg::SIOMLT_SEND);
+	let written = cpu.memory.get_reg(ioreg::SIOCNT);
+	let status = status_bits(&cpu.memory.internal_regs.serial, link);
+	let is_parent = (status & 0x4) == 0;
+	let busy = cpu.memory.internal_regs.serial.busy;
+
+	let mut siocnt = (written & !(SIOCNT_READ_ONLY | SIOCNT_BUSY)) | status;
+	if busy { siocnt |= SIOCNT_BUSY; }
+
+	if let Some(ref mut link) = *link {
+		link.set_send_value(send);
+	}
+
+	// Only the parent can start a transfer, bit 7 is read only for the children.
+	if is_parent && !busy && (written & SIOCNT_BUSY) != 0 {
+		let players = match *link {
+			Some(ref link) => link.players(),
+			None => 1,
+		};
+		let start = cpu.clock.cycles;
+		let end = start + transfer_cycles(written, players);
+
+		cpu.memory.internal_regs.serial.busy = true;
+		cpu.memory.internal_regs.serial.transfer_end = end;
+		siocnt |= SIOCNT_BUSY;
+
+		cpu.memory.set_reg(ioreg::SIOMULTI0, 0xFFFF);
+		cpu.memory.set_reg(ioreg::SIOMULTI1, 0xFFFF);
+		cpu.memory.set_reg(ioreg::SIOMULTI2, 0xFFFF);
+		cpu.memory.set_reg(ioreg::SIOMULTI3, 0xFFFF);
+
+		if let Some(ref mut link) = *link {
+			link.start_transfer(start, end, send);
+		}
+	}
+
+	cpu.memory.set_reg(ioreg::SIOCNT, siocnt);
+}
+
+fn complete_transfer(cpu: &mut ArmCpu, link: &mut Option<LinkDevice>) {
+	let data = match *link {
+		Some(ref mut link) => {
+			if link.is_parent() { Some(link.finish_transfer()) }
+			else { link.wait_complete() }
+		},
+		None => Some([cpu.memory.get_reg(ioreg::SIOMLT_SEND), 0xFFFF, 0xFFFF, 0xFFFF]),
+	};
+
+	// A player that timed out or went away during the transfer is an error
+	// even if the transfer itself completed with 0xFFFF in its place.
+	let cable_error = match *link {
+		Some(ref mut link) => link.take_error(),
+		None => false,
+	};
+
+	cpu.memory.internal_regs.serial.busy = false;
+	if let Some(data) = data {
+		cpu.memory.set_reg(ioreg::SIOMULTI0, data[0]);
+		cpu.memory.set_reg(ioreg::SIOMULTI1, data[1]);
+		cpu.memory.set_reg(ioreg::SIOMULTI2, data[2]);
+		cpu.memory.set_reg(ioreg::SIOMULTI3, data[3]);
+		cpu.memory.internal_regs.serial.error = cable_error;
+	} else {
+		cpu.memory.internal_regs.serial.error = true;
+	}
+
+	cpu.memory.internal_regs.serial.multiplayer_id = match *link {
+		Some(ref link) => link.id as u16,
+		None => 0,
+	};
+
+	let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+	let status = status_bits(&cpu.memory.internal_regs.serial, link);
+	cpu.memory.set_reg(ioreg::SIOCNT, (siocnt & !(SIOCNT_READ_ONLY | SIOCNT_BUSY)) | status);
+
+	if (siocnt & SIOCNT_IRQ) != 0 {
+		cpu.hardware_interrupt(INT_SERIAL);
+	}
+}
+
+/// Builds SIOCNT bits 2-6:
+///   2     SI-Terminal  (0=Parent, 1=Child)
+///   3     SD-Terminal  (0=Bad connection, 1=All GBAs Ready)
+///   4-5   Multi-Player ID (0=Parent, 1-3=1st-3rd child)
+///   6     Multi-Player Error (0=Normal, 1=Error)
+fn status_bits(serial: &ioreg::SerialInternalReg, link: &Option<LinkDevice>) -> u16 {
+	let mut status = (serial.multiplayer_id & 3) << 4;
+	if serial.error { status |= 0x40; }
+	if let Some(ref link) = *link {
+		if !link.is_parent() { status |= 0x4; }
+		if link.connected() { status |= 0x8; }
+	}
+	status
+}
@@ -0,0 +1,159 @@
+//! UART mode, the other end of the port is a `UartDevice`.
+//!
+//! SIOCNT in UART mode:
+//!   0-1   Baud Rate              (0-3: 9600,38400,57600,115200 bps)
+//!   2     CTS Flag               (0=Send always, 1=Send only when SC=LOW)
+//!   3     Parity Control         (0=Even, 1=Odd)
+//!   4     Send Data Flag         (0=Not Full, 1=Full)       (Read Only)
+//!   5     Receive Data Flag      (0=Not Empty, 1=Empty)     (Read Only)
+//!   6     Error Flag             (0=No Error, 1=Error)      (Read Only)
+//!   7     Data Length            (0=7bits, 1=8bits)
+//!   8     FIFO Enable Flag       (0=Disable, 1=Enable)
+//!   9     Parity Enable Flag     (0=Disable, 1=Enable)
+//!   10    Send Enable Flag       (0=Disable, 1=Enable)
+//!   11    Receive Enable Flag    (0=Disable, 1=Enable)
+//!   14    IRQ Enable             (0=Disable, 1=IRQ when any Bit 4-6 become set)
+
+use super::super::super::core::cpu::ArmCpu;
+use super::super::super::core::memory::*;
+use super::super::super::device::uart::UartDevice;
+use super::super::super::{INT_SERIAL, CLOCK_RATE};
+use super::{SerialMode, BAUD_RATES, SIOCNT_IRQ};
+
+const SIOCNT_READ_ONLY: u16 = 0x0070;
+const SIOCNT_SEND_FULL: u16 = 0x0010;
+const SIOCNT_RECEIVE_EMPTY: u16 = 0x0020;
+const SIOCNT_8BIT: u16 = 0x0080;
+const SIOCNT_FIFO: u16 = 0x0100;
+const SIOCNT_PARITY: u16 = 0x0200;
+const SIOCNT_SEND_ENABLE: u16 = 0x0400;
+const SIOCNT_RECEIVE_ENABLE: u16 = 0x0800;
+
+/// The FIFOs are 4 bytes deep when enabled and 1 byte deep otherwise.
+fn fifo_size(siocnt: u16) -> usize {
+	if (siocnt & SIOCNT_FIFO) != 0 { 4 } else { 1 }
+}
+
+/// The number of cycles it takes to send one byte:
+/// a start bit, the data bits, the parity bit and a stop bit.
+fn byte_cycles(siocnt: u16) -> u64 {
+	let baud = BAUD_RATES[(siocnt & 3) as usize];
+	let mut bits = 2 + if (siocnt & SIOCNT_8BIT) != 0 { 8 } else { 7 };
+	if (siocnt & SIOCNT_PARITY) != 0 { bits += 1; }
+	(bits * CLOCK_RATE) / baud
+}
+
+/// Moves bytes injected by the host into the receive FIFO.
+pub fn sync(cpu: &mut ArmCpu, uart: &mut Option<UartDevice>) {
+	let uart = match *uart {
+		Some(ref mut uart) => uart,
+		None => return,
+	};
+	if super::mode(cpu) != SerialMode::Uart { return }
+
+	let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+	if (siocnt & SIOCNT_RECEIVE_ENABLE) == 0 { return }
+
+	let was_empty = cpu.memory.internal_regs.serial.uart_receive.is_empty();
+	while cpu.memory.internal_regs.serial.uart_receive.len() < fifo_size(siocnt) {
+		match uart.receive() {
+			Some(byte) => cpu.memory.internal_regs.serial.uart_receive.push(byte),
+			None => break,
+		}
+	}
+
+	if was_empty && !cpu.memory.internal_regs.serial.uart_receive.is_empty() {
+		update_data_register(cpu);
+		update_status(cpu, true);
+	}
+}
+
+/// Queues bytes written to SIODATA8 and restores SIOCNT's read only bits.
+pub fn on_write(cpu: &mut ArmCpu) {
+	if super::mode(cpu) != SerialMode::Uart {
+		cpu.memory.internal_regs.serial.uart_data_written = false;
+		return
+	}
+
+	let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+	if cpu.memory.internal_regs.serial.uart_data_written {
+		cpu.memory.internal_regs.serial.uart_data_written = false;
+		let mut byte = cpu.memory.get_reg(ioreg::SIODATA8) as u8;
+		if (siocnt & SIOCNT_8BIT) == 0 { byte &= 0x7F; }
+
+		{
+			let serial = &mut cpu.memory.internal_regs.serial;
+			if (siocnt & SIOCNT_SEND_ENABLE) != 0 && serial.uart_send.len() < fifo_size(siocnt) {
+				if serial.uart_send.is_empty() {
+					serial.uart_send_end = cpu.clock.cycles + byte_cycles(siocnt);
+				}
+				serial.uart_send.push(byte);
+			}
+		}
+
+		// The data register reads back from the receive FIFO.
+		update_data_register(cpu);
+	}
+
+	update_status(cpu, false);
+}
+
+/// Finishes sending bytes and pops the receive FIFO once the game has read SIODATA8.
+pub fn tick(cpu: &mut ArmCpu, uart: &mut Option<UartDevice>) {
+	if cpu.memory.internal_regs.serial.uart_send.is_empty() && !cpu.memory.internal_regs.serial.uart_data_read.get() {
+		return
+	}
+
+	if super::mode(cpu) != SerialMode::Uart {
+		cpu.memory.internal_regs.serial.uart_data_read.set(false);
+		return
+	}
+
+	if cpu.memory.internal_regs.serial.uart_data_read.get() {
+		cpu.memory.internal_regs.serial.uart_data_read.set(false);
+		if !cpu.memory.internal_regs.serial.uart_receive.is_empty() {
+			cpu.memory.internal_regs.serial.uart_receive.remove(0);
+			update_data_register(cpu);
+			update_status(cpu, false);
+		}
+	}
+
+	if !cpu.memory.internal_regs.serial.uart_send.is_empty() && cpu.clock.cycles >= cpu.memory.internal_regs.serial.uart_send_end {
+		let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+		let byte = cpu.memory.internal_regs.serial.uart_send.remove(0);
+		if let Some(ref mut uart) = *uart {
+			uart.send(byte);
+		}
+
+		let send_empty = {
+			let serial = &mut cpu.memory.internal_regs.serial;
+			if !serial.uart_send.is_empty() {
+				serial.uart_send_end += byte_cycles(siocnt);
+			}
+			serial.uart_send.is_empty()
+		};
+		update_status(cpu, send_empty);
+	}
+}
+
+/// SIODATA8 shows the byte at the front of the receive FIFO.
+fn update_data_register(cpu: &mut ArmCpu) {
+	let front = match cpu.memory.internal_regs.serial.uart_receive.first() {
+		Some(&byte) => byte,
+		None => return,
+	};
+	cpu.memory.set_reg(ioreg::SIODATA8, front as u16);
+}
+
+/// Rebuilds SIOCNT bits 4-6 and fires an interrupt if `irq` is set and the IRQ is enabled.
+fn update_status(cpu: &mut ArmCpu, irq: bool) {
+	let siocnt = cpu.memory.get_reg(ioreg::SIOCNT);
+	let mut status = 0;
+	if cpu.memory.internal_regs.serial.uart_send.len() >= fifo_size(siocnt) { status |= SIOCNT_SEND_FULL; }
+	if cpu.memory.internal_regs.serial.uart_receive.is_empty() { status |= SIOCNT_RECEIVE_EMPTY; }
+	cpu.memory.set_reg(ioreg::SIOCNT, (siocnt & !SIOCNT_READ_ONLY) | status);
+
+	if irq && (siocnt & SIOCNT_IRQ) != 0 {
+		cpu.hardware_interrupt(INT_SERIAL);
+	}
+}
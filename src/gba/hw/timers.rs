@@ -7,6 +7,23 @@ macro_rules! timer {
 	)
 }
 
+/// Cycles until the next timer that counts cycles (not overflows) overflows,
+/// None if none of them are running.
+pub fn cycles_until_overflow(cpu: &ArmCpu) -> Option<u32> {
+	let mut next = None;
+	for t in 0..4 {
+		let timer = &timer!(cpu, t);
+		if timer.operate && !timer.count_up {
+			let cycles = ((0x10000 - timer.counter) << timer.prescaler) - timer.unscaled_counter;
+			next = Some(match next {
+				Some(n) => min!(n, cycles),
+				None => cycles,
+			});
+		}
+	}
+	next
+}
+
 pub fn increment(cpu: &mut ArmCpu, amount: u32) {
 	let mut last_timer_overflowed = false;
 	for t in 0..4 {
@@ -27,11 +44,19 @@ pub fn increment(cpu: &mut ArmCpu, amount: u32) {
 					::debug::debugger::get_debugger().timer_ov_counter += 1;
 				}
 
+				// How long ago the timer actually overflowed during the last `amount` cycles.
+				let late = if timer!(cpu, t).count_up {
+					0
+				} else {
+					((timer!(cpu, t).counter - 0x10000) << timer!(cpu, t).prescaler) + timer!(cpu, t).unscaled_counter
+				};
+				let overflow_cycle = cpu.clock.cycles - min!(cpu.clock.cycles, late as u64);
+
 				timer!(cpu, t).counter = timer!(cpu, t).reload;
 				timer!(cpu, t).unscaled_counter = 0;
 				last_timer_overflowed = true;
 
-				channel_ab::timer_overflow(cpu, t as u16);
+				channel_ab::timer_overflow(cpu, t as u16, overflow_cycle);
 
 				if timer!(cpu, t).irq_enabled {
 					cpu.hardware_interrupt(0x08 << t);
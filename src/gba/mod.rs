@@ -2,36 +2,58 @@ pub mod core;
 pub mod hw;
 pub mod device;
 pub mod serialization;
+pub mod cartridge;
+pub mod state_check;
+#[cfg(test)] mod tests;
 use time;
 
-use std::thread;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
 use ::util::measure::*;
 use self::core::memory::*;
 use self::core::cpu::registers;
 use self::core::cpu::ArmCpu;
 use self::device::GbaDevice;
+use self::device::link::{self, LinkDevice};
+use self::device::screenshot;
+use self::device::recorder::Recorder;
+use self::device::audio_dump::AudioDump;
+use self::device::audio::AudioOutput;
+use self::device::pacer::FastForwardAudio;
+use self::serialization::BinarySerialization;
+use self::cartridge::CartridgeInfo;
+use self::device::movie;
+use self::device::movie::Movie;
+use self::device::bindings::Action;
 use self::hw::lcd::GbaLcd;
 use self::hw::joypad::GbaJoypad;
 use self::hw::dma;
 use self::hw::audio;
 use self::hw::timers;
+use self::hw::serial;
 
 use super::debug::debugger;
 // use super::debug::debugger::GbaDebugger;
 
-// #TODO remove this debug code.
-// I'm using vsync now and because my monitor's refresh rate is 60Hz
-// I don't limit the FPS, this is bad to leave false though :P
-const LIMIT_FPS: bool = false;
+/// The number of CPU ticks between catching the APU up to the CPU.
+/// The APU keeps its own time so this only decides how closely
+/// writes to the sound registers line up with the samples around them.
+const AUDIO_TICK_RATE: u32 = 128;
 
-/// The number of CPU ticks per audio tick.
-/// The higher this value is, the better the audio will sound for some games
-/// that frequenty change the value in the sound registers (e.g. Fire Emblem).
-const AUDIO_TICK_RATE: u32 = 6144;
+/// The number of cycles spent drawing the visible part of a line.
+const HDRAW_CYCLES: u64 = 960;
 
-/// delay for a 60fps frame in nanoseconds.
-const FPS_60_DELTA_NS: u64 = 16000000; // 16666667
+/// The number of cycles into a line at which the HBlank flag is set.
+/// This is a little after the LCD has actually finished drawing.
+const HBLANK_FLAG_CYCLES: u64 = 1006;
+
+/// The number of cycles in a line, including HBlank.
+const LINE_CYCLES: u64 = 1232;
+
+/// The GBA's clock rate in Hz.
+pub const CLOCK_RATE: u64 = 16777216;
+
+/// The number of cycles in a frame, 228 lines including VBlank.
+pub const CYCLES_PER_FRAME: u64 = 280896;
 
 /// #TODO remove this debug code.
 /// true if the starting address should be 0 in SVC mode.
@@ -72,13 +94,77 @@ pub const INT_GAMEPAK: u16 = 0x2000;
 pub struct GbaExtras {
 	pub paused: bool,
 	pub request_pause: bool,
+
+	/// A screenshot is saved at the end of the frame when this is set.
+	pub request_screenshot: bool,
+
+	/// Starts or stops recording at the end of the frame when this is set.
+	pub request_recording_toggle: bool,
+
+	/// Starts or stops dumping audio at the end of the frame when this is set.
+	pub request_audio_dump_toggle: bool,
+
+	/// Runs at the fast-forward speed from the settings while this is set.
+	pub fast_forward: bool,
+
+	/// The speed when not fast-forwarding, 1.0 normally or less than that for slow motion.
+	pub slow_motion: f64,
+
+	/// Plays the snapshots in the rewind buffer backwards while this is set.
+	pub rewinding: bool,
+
+	/// The slot that save states go to and are loaded from.
+	pub save_state_slot: u8,
+
+	/// Saves a state to `save_state_slot` at the end of the frame when this is set.
+	pub request_save_state: bool,
+
+	/// Loads the state in `save_state_slot` at the end of the frame when this is set.
+	pub request_load_state: bool,
+
+	/// Starts or stops recording an input movie at the end of the frame when this is set.
+	pub request_movie_toggle: bool,
+
+	/// Set by the key binding editor, the next key that is pressed gets bound to this.
+	pub rebinding: Option<Action>,
 }
 
 impl GbaExtras {
+	/// How fast the emulator should run compared to a GBA, None for as fast as possible.
+	pub fn speed(&self) -> Option<f64> {
+		if self.fast_forward {
+			psetting!(fast_forward_speed).multiplier()
+		} else {
+			Some(self.slow_motion)
+		}
+	}
+
+	/// Goes from normal speed to half speed to quarter speed and back.
+	pub fn cycle_slow_motion(&mut self) {
+		self.slow_motion = if self.slow_motion > 0.5 {
+			0.5
+		} else if self.slow_motion > 0.25 {
+			0.25
+		} else {
+			1.0
+		};
+	}
+
 	pub fn new() -> GbaExtras {
 		GbaExtras {
 			paused: false,
 			request_pause: false,
+			request_screenshot: false,
+			request_recording_toggle: false,
+			request_audio_dump_toggle: false,
+			fast_forward: false,
+			slow_motion: 1.0,
+			rewinding: false,
+			save_state_slot: 0,
+			request_save_state: false,
+			request_load_state: false,
+			request_movie_toggle: false,
+			rebinding: None,
 		}
 	}
 }
@@ -88,8 +174,19 @@ pub struct Gba {
 	pub lcd: GbaLcd,
 	pub device: GbaDevice,
 	pub joypad: GbaJoypad,
+	pub cartridge: CartridgeInfo,
 	pub request_exit: bool,
-	pub extras: GbaExtras
+	pub extras: GbaExtras,
+
+	/// Headless GBAs in the same process that are plugged into this one's link cable.
+	/// They run a line at a time right after this GBA so they never get far enough
+	/// apart for the cable to have to wait on one of them.
+	pub linked_players: Vec<Gba>,
+
+	/// The cycle that the CPU is being run up to. Each part of a line is counted from
+	/// the end of the last one rather than from the clock, so the cycles that an instruction
+	/// runs over the end of one part don't push back every part after it.
+	cycle_target: u64,
 }
 
 impl Gba {
@@ -99,16 +196,72 @@ impl Gba {
 			lcd: GbaLcd::new(),
 			device: GbaDevice::new(),
 			joypad: GbaJoypad::new(),
+			cartridge: CartridgeInfo::none(),
 			request_exit: false,
 			extras: GbaExtras::new(),
+			linked_players: Vec::new(),
+			cycle_target: 0,
+		}
+	}
+
+	/// A GBA with its audio going to `output` instead of the one picked in the settings.
+	pub fn with_audio(output: AudioOutput) -> Gba {
+		Gba {
+			cpu: ArmCpu::new(),
+			lcd: GbaLcd::new(),
+			device: GbaDevice::with_audio(output),
+			joypad: GbaJoypad::new(),
+			cartridge: CartridgeInfo::none(),
+			request_exit: false,
+			extras: GbaExtras::new(),
+			linked_players: Vec::new(),
+			cycle_target: 0,
+		}
+	}
+
+	/// A GBA without a window or audio output.
+	pub fn headless() -> Gba {
+		Gba {
+			cpu: ArmCpu::new(),
+			lcd: GbaLcd::new(),
+			device: GbaDevice::headless(),
+			joypad: GbaJoypad::new(),
+			cartridge: CartridgeInfo::none(),
+			request_exit: false,
+			extras: GbaExtras::new(),
+			linked_players: Vec::new(),
+			cycle_target: 0,
 		}
 	}
 
 	pub fn load_cartridge(&mut self, data: Vec<u8>) {
+		self.cartridge = CartridgeInfo::new(&data);
 		self.cpu.memory.rom = data;
 	}
 
+	/// Plugs `players` GBAs running the same BIOS and cartridge as this one into
+	/// a link cable with this GBA as the parent.
+	pub fn link_local_players(&mut self, players: usize) {
+		let mut cable = link::local_cable(players + 1);
+		let children: Vec<LinkDevice> = cable.drain(1..).collect();
+		self.device.link = cable.pop();
+
+		let bios_range = MEM_BIOS.local_addr..(MEM_BIOS.local_addr + MEM_BIOS.size);
+		for child in children {
+			let mut player = Gba::headless();
+			player.cpu.memory.internal_data[bios_range.clone()].copy_from_slice(&self.cpu.memory.internal_data[bios_range.clone()]);
+			player.load_cartridge(self.cpu.memory.rom.clone());
+			debug_info!("Linked player {} is running in this process.", child.id);
+			player.device.link = Some(child);
+			self.linked_players.push(player);
+		}
+	}
+
 	pub fn init(&mut self) {
+		for player in self.linked_players.iter_mut() {
+			player.init();
+		}
+
 		self.cpu.registers.setf_f(); // The FIQ flag should always be high.
 
 		if STARTUP_BIOS {
@@ -117,32 +270,32 @@ impl Gba {
 			self.cpu.set_pc(0x00000000);
 			self.cpu.registers.set_mode(registers::MODE_SVC);
 		} else {
-			self.cpu.set_pc(0x08000000);
-			self.cpu.registers.setf_i(); // Disables IRQ interrupts.
-			self.cpu.registers.setf_f(); // Disables FIQ interrupts. (They are impossible on the GBA, but this is high by default.)
-			self.cpu.registers.set_mode(registers::MODE_SYS);
-			self.cpu.registers.set_with_mode(registers::MODE_USR, registers::REG_SP, 0x03007F00); // Also System
-			self.cpu.registers.set_with_mode(registers::MODE_IRQ, registers::REG_SP, 0x03007FA0);
-			self.cpu.registers.set_with_mode(registers::MODE_SVC, registers::REG_SP, 0x03007FE0);
-			// #TODO some IO registers need to be set here.
+			self.skip_bios();
 		}
 
 		self.cpu.memory.set_reg(ioreg::KEYINPUT, 0xffff); // make sure all keys are marked as released.
+		self.cycle_target = self.cpu.clock.cycles;
+	}
+
+	/// Starts at the cartridge's entry point with the CPU set up the way the BIOS leaves it.
+	pub fn skip_bios(&mut self) {
+		self.cpu.set_pc(0x08000000);
+		self.cpu.registers.setf_i(); // Disables IRQ interrupts.
+		self.cpu.registers.setf_f(); // Disables FIQ interrupts. (They are impossible on the GBA, but this is high by default.)
+		self.cpu.registers.set_mode(registers::MODE_SYS);
+		self.cpu.registers.set_with_mode(registers::MODE_USR, registers::REG_SP, 0x03007F00); // Also System
+		self.cpu.registers.set_with_mode(registers::MODE_IRQ, registers::REG_SP, 0x03007FA0);
+		self.cpu.registers.set_with_mode(registers::MODE_SVC, registers::REG_SP, 0x03007FE0);
+		// #TODO some IO registers need to be set here.
 	}
 
 	pub fn run(&mut self) {
 		self.init();
 		'running: loop {
-			if LIMIT_FPS {
-				let start_time = time::precise_time_ns();
-				self.tick();
-				let delta = time::precise_time_ns() - start_time;
-				let sleep_time_millis = if delta > FPS_60_DELTA_NS { 0 } else { FPS_60_DELTA_NS - delta } / 1000000;
-				thread::sleep(Duration::from_millis(sleep_time_millis));
-			} else { // #TODO remove debug code.
-				self.tick();
-			}
+			self.tick();
 			if self.request_exit { break 'running; }
+			let speed = self.extras.speed();
+			self.device.pacer.wait(speed);
 		}
 		self.request_exit = false; // in case we don't actually close here.
 
@@ -153,25 +306,131 @@ impl Gba {
 		debug_info!("-- Shutdown successfully.");
 	}
 
+	/// Runs `frames` frames as fast as possible and then shuts down.
+	/// This is meant for headless GBAs.
+	pub fn run_frames(&mut self, frames: u64) {
+		self.init();
+		for _ in 0..frames {
+			self.tick();
+			if self.request_exit { break; }
+		}
+		self.request_exit = false;
+		self.device.close();
+		debug_info!("-- Ran {} frames.", frames);
+	}
+
 	pub fn tick(&mut self) {
 		profiler_begin!("Pyrite Frame");
 		let frame_start_time = time::precise_time_ns();
-		if !self.extras.paused {
+
+		let speed = self.extras.speed();
+		let audio_speed = speed.unwrap_or(self.device.pacer.measured_speed);
+		// Rewinding would throw movies out of sync.
+		let rewinding = self.extras.rewinding && psetting!(rewind_enabled) && self.device.movie.is_none();
+		let mute = rewinding || (self.extras.fast_forward && psetting!(fast_forward_audio) == FastForwardAudio::Mute);
+		self.device.audio.set_speed(audio_speed, mute);
+		self.device.audio.apply_settings();
+
+		let mut ran_frame = false;
+		if !self.extras.paused && rewinding {
+			self.rewind_frame();
+		} else if !self.extras.paused {
+			self.begin_movie_frame();
 			self.frame();
+			self.end_movie_frame();
+			self.device.input.next_frame();
+			self.capture_rewind_snapshot();
+			ran_frame = true;
 		} else {
 			self.poll_device_events();
 		}
-
-		// IMGUI:
-		profiler_begin!("ImGui");
-		self.device.video.prepare_imgui();
+		self.device.audio.update_rate_control();
 
 		let render_start_time = time::precise_time_ns();
-		debugger::render_debugger(self);
-		profiler_end!();
-		profiler_begin!("Render Call");
-		self.device.video.render(&self.lcd.screen_buffer);
-		profiler_end!();
+		let present = self.device.video.is_some() && self.device.pacer.should_present(speed);
+		let record_filtered = ran_frame && self.device.recorder.as_ref().map_or(false, |r| r.filtered);
+		if present || self.extras.request_screenshot || record_filtered {
+			self.device.filter.apply(&self.lcd.screen_buffer);
+		}
+
+		if ran_frame {
+			let result = match self.device.recorder {
+				Some(ref mut recorder) => if recorder.filtered {
+					recorder.write_filtered(&self.device.filter.frame)
+				} else {
+					recorder.write_native(&self.lcd.screen_buffer)
+				},
+				None => Ok(()),
+			};
+			if let Err(e) = result {
+				console_error!("{}", e);
+				self.device.stop_recording();
+			}
+		}
+
+		if self.extras.request_screenshot {
+			self.extras.request_screenshot = false;
+			match self.save_screenshot(None) {
+				Ok(path) => console_log!("Saved screenshot to {}", path.display()),
+				Err(e) => console_error!("{}", e),
+			}
+		}
+
+		if self.extras.request_save_state {
+			self.extras.request_save_state = false;
+			let slot = self.extras.save_state_slot;
+			match self.save_state_slot(slot) {
+				Ok(path) => console_log!("Saved state {} to {}", slot, path.display()),
+				Err(e) => console_error!("{}", e),
+			}
+		}
+
+		if self.extras.request_load_state {
+			self.extras.request_load_state = false;
+			let slot = self.extras.save_state_slot;
+			match self.load_state_slot(slot) {
+				Ok(path) => console_log!("Loaded state {} from {}", slot, path.display()),
+				Err(e) => console_error!("{}", e),
+			}
+		}
+
+		if self.extras.request_movie_toggle {
+			self.extras.request_movie_toggle = false;
+			if self.device.movie.is_some() {
+				self.stop_movie();
+			} else if let Err(e) = self.start_movie_recording(None) {
+				console_error!("{}", e);
+			}
+		}
+
+		if self.extras.request_recording_toggle {
+			self.extras.request_recording_toggle = false;
+			if self.device.recorder.is_some() {
+				self.device.stop_recording();
+			} else if let Err(e) = self.start_recording(None) {
+				console_error!("{}", e);
+			}
+		}
+
+		if self.extras.request_audio_dump_toggle {
+			self.extras.request_audio_dump_toggle = false;
+			if self.device.audio_dump.is_some() {
+				self.device.stop_audio_dump();
+			} else if let Err(e) = self.start_audio_dump(None, psetting!(audio_dump_stems)) {
+				console_error!("{}", e);
+			}
+		}
+
+		if present {
+			// IMGUI:
+			profiler_begin!("ImGui");
+			if let Some(ref mut video) = self.device.video { video.prepare_imgui(); }
+			debugger::render_debugger(self);
+			profiler_end!();
+			profiler_begin!("Render Call");
+			if let Some(ref mut video) = self.device.video { video.render(&self.device.filter.frame); }
+			profiler_end!();
+		}
 		let render_end_time = time::precise_time_ns();
 
 		let mut debugger = debugger::get_debugger();
@@ -189,6 +448,206 @@ impl Gba {
 		profiler_clear!();
 	}
 
+	/// Adds a snapshot to the rewind buffer every few frames.
+	fn capture_rewind_snapshot(&mut self) {
+		if !psetting!(rewind_enabled) {
+			if self.device.rewind.len() > 0 { self.device.rewind.clear(); }
+			return
+		}
+
+		let interval = max!(1, psetting!(rewind_interval));
+		let keys = self.joypad.keys();
+		if self.device.rewind.frame(interval, keys) {
+			let mut snapshot = Vec::new();
+			self.serialize_uncompressed(&mut snapshot);
+			let max_snapshots = max!(1, (psetting!(rewind_length) as usize * 60) / interval as usize);
+			let max_memory = psetting!(rewind_memory) as usize * 1024 * 1024;
+			self.device.rewind.push(snapshot, max_snapshots, max_memory);
+		}
+	}
+
+	/// Goes back one frame by loading the snapshot before it from the rewind buffer
+	/// and running the frames from there up to it, each with the keys that were
+	/// held at the end of it when it was first played.
+	fn rewind_frame(&mut self) {
+		match self.device.rewind.step_back() {
+			Some((snapshot, keys)) => {
+				if let Err(e) = self.deserialize_uncompressed(&mut &snapshot[..]) {
+					console_error!("Failed to rewind: {}", e);
+					self.device.rewind.clear();
+					return
+				}
+				self.device.rewind.active = true;
+				for k in keys {
+					self.joypad.set_keys(k);
+					self.frame();
+				}
+				self.device.rewind.active = false;
+			},
+			None => self.poll_device_events(),
+		}
+	}
+
+	/// Loads the movie's start state if it has one and feeds the movie's
+	/// input for the frame that is about to run into the joypad.
+	fn begin_movie_frame(&mut self) {
+		let start_state = match self.device.movie {
+			Some(ref mut movie) => movie.take_start_state(),
+			None => return,
+		};
+		if let Some(state) = start_state {
+			if let Err(e) = self.deserialize(&mut &state[..]) {
+				console_error!("Failed to load the movie's save state: {}", e);
+				self.stop_movie();
+				return
+			}
+		}
+
+		let live_keys = self.device.input.keyinput(psetting!(turbo_rate));
+		let keys = match self.device.movie {
+			Some(ref mut movie) => {
+				movie.set_live_keys(live_keys);
+				movie.begin_frame()
+			},
+			None => None,
+		};
+		match keys {
+			Some(keys) => {
+				self.joypad.set_keys(keys);
+				self.cpu.memory.keyinput_read.set(false);
+			},
+			None => {
+				console_log!("The movie has ended.");
+				self.stop_movie();
+			},
+		}
+	}
+
+	fn end_movie_frame(&mut self) {
+		let lagged = !self.cpu.memory.keyinput_read.get();
+		let keys = self.joypad.keys();
+		if let Some(ref mut movie) = self.device.movie {
+			movie.end_frame(keys, lagged);
+		}
+	}
+
+	/// Describes how the GBA boots, movies that start at power-on only sync if this matches.
+	pub fn boot_flags(&self) -> u8 {
+		let mut flags = 0;
+		if STARTUP_BIOS { flags |= movie::BOOT_FLAG_BIOS; }
+		if cfg!(feature = "emulate-swi") { flags |= movie::BOOT_FLAG_EMULATE_SWI; }
+		flags
+	}
+
+	/// Starts recording an input movie to `path`, or to a timestamped file in the movie
+	/// directory from the settings if `path` is None. If the GBA hasn't run yet the movie
+	/// starts at power-on, otherwise it starts from a save state of the current frame.
+	pub fn start_movie_recording(&mut self, path: Option<&str>) -> Result<PathBuf, String> {
+		self.stop_movie();
+		let path = match path {
+			Some(p) => PathBuf::from(p),
+			None => screenshot::timestamped_path(&::pyrite::get_settings().movie_dir, "pmv"),
+		};
+		let start_state = if self.cpu.clock.cycles == 0 {
+			None
+		} else {
+			let mut state = Vec::new();
+			self.serialize(&mut state);
+			Some(state)
+		};
+		self.device.movie = Some(Movie::record(&path, &self.cartridge, self.boot_flags(), start_state));
+		console_log!("Recording a movie to {}", path.display());
+		Ok(path)
+	}
+
+	/// Plays the movie at `path`. Movies that start at power-on can only be played before
+	/// the GBA has run. Unless `read_only` is set, pressing a key during playback
+	/// continues recording from there.
+	pub fn play_movie(&mut self, path: &str, read_only: bool) -> Result<(), String> {
+		self.stop_movie();
+		let movie = match Movie::load(Path::new(path), read_only) {
+			Ok(movie) => movie,
+			Err(e) => return Err(e),
+		};
+		if let Err(e) = movie.check(&self.cartridge, self.boot_flags()) {
+			return Err(e)
+		}
+		if !movie.starts_from_state() && self.cpu.clock.cycles != 0 {
+			return Err("This movie starts at power-on, it has to be played when Pyrite starts (--play-movie).".to_string())
+		}
+		console_log!("Playing a movie with {} frames from {}", movie.frame_count(), path);
+		self.device.movie = Some(movie);
+		Ok(())
+	}
+
+	/// Stops the current movie and gives the joypad back to the keyboard.
+	pub fn stop_movie(&mut self) {
+		self.device.stop_movie();
+		let keys = self.device.input.keyinput(psetting!(turbo_rate));
+		self.joypad.set_keys(keys);
+	}
+
+	/// Saves the current frame as a PNG to `path`, or to a timestamped file
+	/// in the screenshot directory from the settings if `path` is None.
+	pub fn save_screenshot(&mut self, path: Option<&str>) -> Result<PathBuf, String> {
+		let path = match path {
+			Some(p) => PathBuf::from(p),
+			None => screenshot::timestamped_path(&::pyrite::get_settings().screenshot_dir, "png"),
+		};
+
+		let result = if psetting!(screenshot_filtered) {
+			// The filters only run when something needs their output.
+			if self.device.filter.frame.width == 0 {
+				self.device.filter.apply(&self.lcd.screen_buffer);
+			}
+			screenshot::save_filtered(&self.device.filter.frame, &path)
+		} else {
+			screenshot::save_native(&self.lcd.screen_buffer, &path)
+		};
+
+		match result {
+			Ok(_) => Ok(path),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Starts recording video to `path` and audio next to it with a .wav extension,
+	/// or to a timestamped file in the recording directory from the settings if `path` is None.
+	pub fn start_recording(&mut self, path: Option<&str>) -> Result<PathBuf, String> {
+		self.device.stop_recording();
+		let path = match path {
+			Some(p) => PathBuf::from(p),
+			None => screenshot::timestamped_path(&::pyrite::get_settings().recording_dir, "y4m"),
+		};
+		match Recorder::new(&path, self.device.audio.sample_rate, psetting!(recording_filtered)) {
+			Ok(recorder) => {
+				self.device.recorder = Some(recorder);
+				console_log!("Recording to {}", path.display());
+				Ok(path)
+			},
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Starts dumping audio to `path`, or to a timestamped file in the audio dump
+	/// directory from the settings if `path` is None. If `stems` is set every
+	/// sound channel is also written to its own file next to it.
+	pub fn start_audio_dump(&mut self, path: Option<&str>, stems: bool) -> Result<PathBuf, String> {
+		self.device.stop_audio_dump();
+		let path = match path {
+			Some(p) => PathBuf::from(p),
+			None => screenshot::timestamped_path(&::pyrite::get_settings().audio_dump_dir, "wav"),
+		};
+		match AudioDump::new(&path, self.device.audio.sample_rate, stems) {
+			Ok(dump) => {
+				self.device.audio_dump = Some(dump);
+				console_log!("Dumping audio to {}", path.display());
+				Ok(path)
+			},
+			Err(e) => Err(e),
+		}
+	}
+
 	fn frame(&mut self) {
 		profiler_begin!("GBA Frame");
 		// Clears the VBlank flag.
@@ -203,45 +662,70 @@ impl Gba {
 		profiler_map!("rendering-line", "Rendering Line");
 		profiler_map!("polling-events", "Polling Events");
 		for vcount in 0..160 {
-			self.cpu.memory.set_reg(ioreg::VCOUNT, vcount);
-			self.check_line_coincidence(vcount);
-			self.do_vdraw_line(vcount);
+			self.line(vcount);
+			self.linked_players_line(vcount);
 		}
 		profiler_end!();
 
 		profiler_begin!("V-BLANK");
 		profiler_map!("running-cpu", "Running CPU");
-		// Sets the VBlank flag.
-		{
-			let mut dispstat = self.cpu.memory.get_reg(ioreg::DISPSTAT);
-			dispstat |= 0x1;
-			self.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat);
+		for vcount in 160..228 {
+			self.line(vcount);
+			self.linked_players_line(vcount);
 		}
+		profiler_end!();
 
-		// We do the first iteration of vblank here in order
-		// to fire the interrupt once.
-		self.cpu.memory.set_reg(ioreg::VCOUNT, 160);
-		self.check_line_coincidence(160);
-		self.try_fire_vblank_int();
-		self.check_dmas(dma::DMA_TIMING_VBLANK);
-		self.do_vblank_line();
+		self.on_frame_end();
+		profiler_end!();
+	}
 
-		for vcount in 161..228 {
+	/// Runs one of the 228 lines of a frame, `frame` clears the VBlank flag before line 0.
+	fn line(&mut self, vcount: u16) {
+		if vcount < 160 {
 			self.cpu.memory.set_reg(ioreg::VCOUNT, vcount);
 			self.check_line_coincidence(vcount);
+			self.lcd.start_line(&self.cpu.memory, vcount);
+			self.do_vdraw_line(vcount);
+		} else if vcount == 160 {
+			// Sets the VBlank flag.
+			{
+				let mut dispstat = self.cpu.memory.get_reg(ioreg::DISPSTAT);
+				dispstat |= 0x1;
+				self.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat);
+			}
+
+			// We do the first iteration of vblank here in order
+			// to fire the interrupt once.
+			self.cpu.memory.set_reg(ioreg::VCOUNT, 160);
+			self.cpu.memory.internal_regs.on_vblank();
+			self.check_line_coincidence(160);
+			self.lcd.start_line(&self.cpu.memory, 160);
+			self.try_fire_vblank_int();
+			self.check_dmas(dma::DMA_TIMING_VBLANK);
+			self.do_vblank_line();
+		} else {
+			self.cpu.memory.set_reg(ioreg::VCOUNT, vcount);
+			self.check_line_coincidence(vcount);
+			self.lcd.start_line(&self.cpu.memory, vcount);
 			self.do_vblank_line();
 		}
-		profiler_end!();
+	}
 
-		self.on_frame_end();
-		profiler_end!();
+	/// Keeps the GBAs linked in this process a line behind this one.
+	/// They all run the same line up to the same cycle that this one just did.
+	fn linked_players_line(&mut self, vcount: u16) {
+		let line_start = self.cycle_target - LINE_CYCLES;
+		for player in self.linked_players.iter_mut() {
+			if vcount == 0 {
+				let dispstat = player.cpu.memory.get_reg(ioreg::DISPSTAT);
+				player.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat & !0x1);
+			}
+			player.cycle_target = line_start;
+			player.line(vcount);
+		}
 	}
 
 	fn on_frame_end(&mut self) {
-		self.cpu.memory.internal_regs.on_frame_end(
-			&self.cpu.memory.internal_data[MEM_IOREG.local_addr..(MEM_IOREG.local_addr+MEM_IOREG.size)]
-		);
-
 		// pyrite_debugging!({
 		// 	use std::sync::atomic::Ordering;
 		// 	let _write_misses = self.device.audio.ring_buffer._stat_write_misses.load(Ordering::Relaxed);
@@ -331,13 +815,7 @@ impl Gba {
 		self.poll_device_events();
 		profiler_end_id!("polling-events");
 
-		profiler_begin_id!("running-cpu");
-		self.do_hdraw();
-		profiler_end_id!("running-cpu");
-
-		profiler_begin_id!("rendering-line");
-		self.lcd.render_line(&mut self.cpu.memory, line);
-		profiler_end_id!("rendering-line");
+		self.do_hdraw(line);
 
 		profiler_begin_id!("running-cpu");
 		self.do_hblank();
@@ -361,62 +839,138 @@ Display status and Interrupt control. The H-Blank conditions are generated once
 
 	fn do_vblank_line(&mut self) {
 		profiler_begin_id!("running-cpu");
-		self.run_cpu_cycles(1232);
+		self.set_hblank_flag(false);
+		self.run_cpu_cycles(HBLANK_FLAG_CYCLES);
+		self.set_hblank_flag(true);
+		self.run_cpu_cycles(LINE_CYCLES - HBLANK_FLAG_CYCLES);
 		profiler_end_id!("running-cpu");
 	}
 
-	fn do_hdraw(&mut self) {
-		// Clears the HBlank flag:
-		let mut dispstat = self.cpu.memory.get_reg(ioreg::DISPSTAT);
-		dispstat &= !0x2;
-		self.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat);
-		self.run_cpu_cycles(960);
+	/// Runs the CPU while the line is being drawn. Whenever the game changes the display
+	/// registers, palette RAM or the part of VRAM that the rest of the line is drawn from,
+	/// the pixels up to that point are drawn with the old values so that mid-line changes
+	/// show up where they happened.
+	fn do_hdraw(&mut self, line: u16) {
+		self.set_hblank_flag(false);
+
+		let line_start = self.cycle_target;
+		let target = line_start + HDRAW_CYCLES;
+		self.cycle_target = target;
+		let mut drawn = 0;
+
+		self.cpu.memory.display_writes.clear();
+		self.cpu.memory.display_vram = self.lcd.visible_vram(&self.cpu.memory, line);
+		self.cpu.memory.track_display_writes = true;
+		loop {
+			profiler_begin_id!("running-cpu");
+			self.run_cpu_until(target, true);
+			profiler_end_id!("running-cpu");
+
+			if !self.cpu.memory.display_writes.is_empty() {
+				// The LCD draws one dot every 4 cycles.
+				let dot = min!(240, ((self.cpu.clock.cycles - line_start) / 4) as usize);
+				if dot > drawn {
+					profiler_begin_id!("rendering-line");
+					self.cpu.memory.undo_display_writes();
+					self.lcd.render_segment(&mut self.cpu.memory, line, drawn, dot);
+					self.cpu.memory.redo_display_writes();
+					profiler_end_id!("rendering-line");
+					drawn = dot;
+				}
+				self.cpu.memory.display_writes.clear();
+				self.cpu.memory.display_vram = self.lcd.visible_vram(&self.cpu.memory, line);
+			}
+
+			if self.cpu.clock.cycles >= target { break }
+		}
+		self.cpu.memory.track_display_writes = false;
+
+		profiler_begin_id!("rendering-line");
+		self.lcd.render_segment(&mut self.cpu.memory, line, drawn, 240);
+		self.lcd.end_line(&mut self.cpu.memory, line);
+		profiler_end_id!("rendering-line");
 	}
 
 	fn do_hblank(&mut self) {
-		// Sets the HBlank flag:
-		let mut dispstat = self.cpu.memory.get_reg(ioreg::DISPSTAT);
-		dispstat |= 0x2;
-		self.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat);
+		// The HBlank flag, interrupt and DMAs come a little after the LCD stops drawing.
+		self.run_cpu_cycles(HBLANK_FLAG_CYCLES - HDRAW_CYCLES);
+		self.set_hblank_flag(true);
 		self.try_fire_hblank_int();
 		self.check_dmas(dma::DMA_TIMING_HBLANK);
-		self.run_cpu_cycles(272);
+		self.run_cpu_cycles(LINE_CYCLES - HBLANK_FLAG_CYCLES);
 	}
 
+	fn set_hblank_flag(&mut self, hblank: bool) {
+		let mut dispstat = self.cpu.memory.get_reg(ioreg::DISPSTAT);
+		if hblank { dispstat |= 0x2; } else { dispstat &= !0x2; }
+		self.cpu.memory.set_reg(ioreg::DISPSTAT, dispstat);
+	}
+
+	/// Runs the CPU for `cycles` more cycles after the last target.
 	fn run_cpu_cycles(&mut self, cycles: u64) {
-		if self.cpu.memory.internal_regs.halted || self.cpu.memory.internal_regs.stopped { return }
-		let target = self.cpu.clock.cycles + cycles;
+		self.cycle_target += cycles;
+		let target = self.cycle_target;
+		self.run_cpu_until(target, false);
+	}
+
+	/// Runs the CPU until the clock reaches `target`.
+	/// If `stop_on_display_write` is set this also returns early after the game writes
+	/// to memory that the LCD is reading from, see `do_hdraw`.
+	fn run_cpu_until(&mut self, target: u64, stop_on_display_write: bool) {
+		serial::sync(&mut self.cpu, &mut self.device);
 
 		//dma_ongoing(&mut self.cpu)
 
 		measure_start(MEASURE_CPU_TICKS_TIME);
 		measure_start(MEASURE_DMA_TICKS_TIME);
 
-		'cpu_loop: while self.cpu.clock.cycles < target  && !(self.cpu.memory.internal_regs.halted || self.cpu.memory.internal_regs.stopped) {
+		'cpu_loop: while self.cpu.clock.cycles < target {
+			if stop_on_display_write && !self.cpu.memory.display_writes.is_empty() {
+				break 'cpu_loop;
+			}
+
 			if dma::ongoing(&self.cpu) {
 				measure_iteration(MEASURE_DMA_TICKS_TIME);
 				dma::tick(&mut self.cpu);
+			} else if self.cpu.memory.internal_regs.halted || self.cpu.memory.internal_regs.stopped {
+				// The clock keeps running while the CPU is halted so that the timers,
+				// the APU and the other end of the link cable see time passing.
+				let cycles = self.halted_cycles(target);
+				self.cpu.clock.internal(cycles);
+			} else if self.cpu.executable() {
+				measure_iteration(MEASURE_CPU_TICKS_TIME);
+				self.cpu.tick();
 			} else {
-				if self.cpu.executable() {
-					measure_iteration(MEASURE_CPU_TICKS_TIME);
-					self.cpu.tick();
-					self.increment_timers();
-					if self.cpu.clock.audio_clock > AUDIO_TICK_RATE {
-						audio::tick(&mut self.cpu, &mut self.device.audio);
-						self.cpu.clock.audio_clock = 0;
-					}
-					self.check_dmas(dma::DMA_TIMING_IMMEDIATE);
-				} else {
-					self.cpu.reg_dump_pretty();
-					panic!("Attempting to execute at unexecutable address 0x{:08x}!", self.cpu.get_exec_address());
-				}
+				self.cpu.reg_dump_pretty();
+				panic!("Attempting to execute at unexecutable address 0x{:08x}!", self.cpu.get_exec_address());
 			}
+
+			self.increment_timers();
+			if self.cpu.clock.audio_clock >= AUDIO_TICK_RATE {
+				audio::tick(&mut self.cpu, &mut self.device);
+				self.cpu.clock.audio_clock = 0;
+			}
+			self.check_dmas(dma::DMA_TIMING_IMMEDIATE);
+			serial::tick(&mut self.cpu, &mut self.device);
 		}
 
 		measure_end(MEASURE_CPU_TICKS_TIME);
 		measure_end(MEASURE_DMA_TICKS_TIME);
 	}
 
+	/// How far to skip ahead while the CPU is halted: up to the next timer overflow
+	/// (which might fire an IRQ that wakes it up) or the next APU tick, whichever comes first.
+	fn halted_cycles(&self, target: u64) -> u64 {
+		let mut cycles = target - self.cpu.clock.cycles;
+		if let Some(overflow) = timers::cycles_until_overflow(&self.cpu) {
+			cycles = min!(cycles, overflow as u64);
+		}
+		if self.cpu.clock.audio_clock < AUDIO_TICK_RATE {
+			cycles = min!(cycles, (AUDIO_TICK_RATE - self.cpu.clock.audio_clock) as u64);
+		}
+		max!(1, cycles)
+	}
+
 	fn increment_timers(&mut self) {
 		let timer_inc = self.cpu.clock.timer_cycles;
 		self.cpu.clock.timer_cycles = 0;
@@ -1,13 +1,28 @@
+//! Save states. A state is the Pyrite header and a format version followed by
+//! a list of chunks, each with a four character tag, its own version and its length.
+//! Every part of the GBA gets its own chunk so that changing one of them only means
+//! bumping that chunk's version and teaching `upgrade_chunk` how to read the old one,
+//! and chunks that this version of Pyrite doesn't know about are skipped.
+//!
+//! States written to files also start with a metadata chunk (which ROM the state
+//! is for, when it was made and by what version of Pyrite) and a thumbnail, and the
+//! whole thing is deflated. Rewind snapshots are just the state chunks.
+
 use super::Gba;
 use super::core::memory::MEM_BIOS;
+use super::core::memory::ioreg::{DMAInternalReg, TimerInternalReg, SerialInternalReg};
+use super::core::memory::ioreg::{GbaChannel1, GbaChannel2, GbaChannel3, GbaChannel4, GbaChannelFIFO, GbaAudioSequencer};
+use ::util::png;
+use ::util::io::create_parent_dir;
 
 use bincode::rustc_serialize::{encode, decode_from};
 use bincode::SizeLimit;
+use time;
 
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::fs::File;
-use std::path::Path;
-use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
 
 use flate2::Compression;
 use flate2::write::DeflateEncoder;
@@ -17,127 +32,536 @@ use flate2::read::DeflateDecoder;
 const PYRITE_HEADER: [u8; 8] = [0x70, 0x79, 0x72, 0x69, 0x74, 0x65, 0x39, 0x36];
 
 /// The version of the current format.
-const VERSION: u8 = 2;
+const VERSION: u8 = 3;
+
+/// The format before chunks, a single stream of CPU state, joypad, IO registers and RAM.
+/// Those states can't be loaded anymore, the registers in them don't match the ones
+/// Pyrite has now closely enough to be converted.
+const LEGACY_VERSION: u8 = 2;
+
+/// The number of save state slots per game.
+pub const SAVE_STATE_SLOTS: u8 = 10;
+
+type ChunkTag = [u8; 4];
+
+const CHUNK_METADATA: ChunkTag = [b'M', b'E', b'T', b'A'];
+const CHUNK_THUMBNAIL: ChunkTag = [b'T', b'H', b'M', b'B'];
+const CHUNK_CPU: ChunkTag = [b'C', b'P', b'U', b' '];
+const CHUNK_JOYPAD: ChunkTag = [b'J', b'O', b'Y', b'P'];
+const CHUNK_IOREGS: ChunkTag = [b'I', b'R', b'E', b'G'];
+const CHUNK_DMA: ChunkTag = [b'D', b'M', b'A', b' '];
+const CHUNK_TIMERS: ChunkTag = [b'T', b'I', b'M', b'R'];
+const CHUNK_SERIAL: ChunkTag = [b'S', b'I', b'O', b' '];
+const CHUNK_AUDIO: ChunkTag = [b'A', b'P', b'U', b' '];
+const CHUNK_RAM: ChunkTag = [b'R', b'A', b'M', b' '];
+const CHUNK_LCD: ChunkTag = [b'L', b'C', b'D', b' '];
+const CHUNK_END: ChunkTag = [b'E', b'N', b'D', b' '];
+
+/// The version that each chunk is currently written at.
+const CHUNK_VERSIONS: [(ChunkTag, u16); 12] = [
+	(CHUNK_METADATA, 1),
+	(CHUNK_THUMBNAIL, 1),
+	(CHUNK_CPU, 1),
+	(CHUNK_JOYPAD, 1),
+	(CHUNK_IOREGS, 1),
+	(CHUNK_DMA, 1),
+	(CHUNK_TIMERS, 1),
+	(CHUNK_SERIAL, 1),
+	(CHUNK_AUDIO, 1),
+	(CHUNK_RAM, 1),
+	(CHUNK_LCD, 1),
+	(CHUNK_END, 1),
+];
+
+/// The size of the thumbnail, half of the GBA's screen.
+const THUMBNAIL_WIDTH: usize = 120;
+const THUMBNAIL_HEIGHT: usize = 80;
+
+/// What a save state file says about itself.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct SaveStateInfo {
+	pub emulator_version: String,
+
+	/// When the state was saved in seconds since the Unix epoch.
+	pub timestamp: i64,
+
+	pub rom_title: String,
+	pub rom_game_code: String,
+	pub rom_crc: u32,
+}
+
+impl SaveStateInfo {
+	/// The local time the state was saved at, e.g. `2016-10-19 15:30:12`.
+	pub fn time_string(&self) -> String {
+		let tm = time::at(time::Timespec::new(self.timestamp, 0));
+		time::strftime("%Y-%m-%d %H:%M:%S", &tm).unwrap_or_else(|_| "?".to_string())
+	}
+}
+
+struct Chunk {
+	tag: ChunkTag,
+	version: u16,
+	data: Vec<u8>,
+}
 
 pub trait BinarySerialization {
+	/// Writes a full save state with metadata and a thumbnail, deflated.
 	fn serialize(&self, writer: &mut Write);
-	fn deserialize(&mut self, reader: &mut Read) -> Result<&'static str, &'static str>;
+	fn deserialize(&mut self, reader: &mut Read) -> Result<&'static str, String>;
+
+	/// Only the state chunks without the deflate, for snapshots that stay in memory.
+	fn serialize_uncompressed(&self, writer: &mut Write);
+	fn deserialize_uncompressed(&mut self, reader: &mut Read) -> Result<&'static str, String>;
 
-	// #TODO remove this temporary code.
 	fn save_to_file<'a>(&self, file_path: &'a str) -> Result<&'static str, String>;
 	fn load_from_file<'a>(&mut self, file_path: &'a str) -> Result<&'static str, String>;
 }
 
 impl BinarySerialization for Gba {
-	// #TODO remove this temporary code.
 	fn save_to_file<'a>(&self, file_path: &'a str) -> Result<&'static str, String> {
 		let _save_path = Path::new(file_path);
-		let _save_path_parent = _save_path.parent().expect("Getting save file path parent directory");
-		create_dir_all(_save_path_parent).expect("Creating save file path parent directories");
+		if let Err(e) = create_parent_dir(_save_path) {
+			return Err(e)
+		}
 
-		let mut f = match File::create(file_path) {
+		let f = match File::create(file_path) {
 			Ok(file) => file,
 			Err(_) => return Err(format!("Failed to open {}", file_path))
 		};
-		self.serialize(&mut f);
+		self.serialize(&mut BufWriter::new(f));
 		return Ok("ok")
 	}
 
-	// #TODO remove this temporary code.
 	fn load_from_file<'a>(&mut self, file_path: &'a str) -> Result<&'static str, String> {
+		// The movie would keep going from its frame with a different state under it.
+		if self.device.movie.is_some() {
+			return Err("Stop the movie before loading a state, it would go out of sync.".to_string())
+		}
 		let mut f = match File::open(file_path) {
 			Ok(file) => file,
 			Err(_) => return Err(format!("Failed to open {}", file_path))
 		};
-		self.deserialize(&mut f).unwrap();
-		return Ok("ok")
+		match self.deserialize(&mut f) {
+			Ok(_) => Ok("ok"),
+			Err(e) => Err(format!("Failed to load {}: {}", file_path, e)),
+		}
 	}
 
 	fn serialize(&self, w_unwrapped: &mut Write) {
 		let mut w = DeflateEncoder::new(w_unwrapped, Compression::Default);
+		write_header(&mut w);
 
-		w.write(&PYRITE_HEADER).expect("Write pyrite header.");
-
-		let data = [
-			VERSION,
-			if self.cpu.branched {1} else {0},
-			self.cpu.prefetch_wait
-		];
-		w.write(&data).expect("Write version & CPU data.");
-
-		let register_data: Vec<u8> = encode(&self.cpu.registers, SizeLimit::Infinite).expect("Encode CPU registers.");
-		w.write(&register_data).expect("Write CPU registers.");
-
-		let clock_data: Vec<u8> = encode(&self.cpu.clock, SizeLimit::Infinite).expect("Encode CPU clock.");
-		w.write(&clock_data).expect("Write CPU clock data.");
-
-		let joypad_data: Vec<u8> = encode(&self.joypad, SizeLimit::Infinite).expect("Encode Joypad");
-		w.write(&joypad_data).expect("Write Joypad data.");
-
-		let internal_regs: Vec<u8> = encode(&self.cpu.memory.internal_regs, SizeLimit::Infinite).expect("Encode internal registers.");
-		w.write(&internal_regs).expect("Write internal registers.");
+		let info = SaveStateInfo {
+			emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+			timestamp: time::get_time().sec,
+			rom_title: self.cartridge.title.clone(),
+			rom_game_code: self.cartridge.game_code.clone(),
+			rom_crc: self.cartridge.crc,
+		};
+		let info_data: Vec<u8> = encode(&info, SizeLimit::Infinite).expect("Encode save state metadata.");
+		write_chunk(&mut w, CHUNK_METADATA, &info_data);
+		write_chunk(&mut w, CHUNK_THUMBNAIL, &self.thumbnail());
 
-		let ram = &self.cpu.memory.internal_data[MEM_BIOS.size..];
-		w.write(&ram).expect("Write RAM.");
+		self.write_state_chunks(&mut w);
+		w.finish().expect("Finish compressing save state.");
 	}
 
-	fn deserialize(&mut self, r_unwrapped: &mut Read) -> Result<&'static str, &'static str> {
+	fn deserialize(&mut self, r_unwrapped: &mut Read) -> Result<&'static str, String> {
 		let mut r = DeflateDecoder::new(r_unwrapped);
+		self.deserialize_uncompressed(&mut r)
+	}
 
-		let mut buffer = [0u8; 16];
+	fn serialize_uncompressed(&self, w: &mut Write) {
+		write_header(w);
+		self.write_state_chunks(w);
+	}
 
-		if r.read_exact(&mut buffer[0..8]).is_ok() {
-			if &buffer[0..8] != &PYRITE_HEADER {
-				return Err("Header does not match Pyrite header.");
-			}
-		} else {
-			return Err("Failed to read header.");
+	fn deserialize_uncompressed(&mut self, r: &mut Read) -> Result<&'static str, String> {
+		match read_header(r) {
+			Ok(VERSION) => {},
+			Ok(LEGACY_VERSION) => return Err("This save state is from an older version of Pyrite and can't be loaded anymore.".to_string()),
+			Ok(version) => return Err(format!("Save state version {} isn't supported by this version of Pyrite.", version)),
+			Err(e) => return Err(e),
 		}
 
-		if r.read_exact(&mut buffer[0..3]).is_ok() {
-			let version = buffer[0];
-			if version != VERSION {
-				return Err("Version did not match Pyrite's current serialization version.");
+		let chunks = match read_chunks(r) {
+			Ok(chunks) => chunks,
+			Err(e) => return Err(e),
+		};
+
+		// Everything is decoded before any of it is applied so that
+		// a broken state can't leave the GBA half loaded.
+		let mut cpu = None;
+		let mut joypad = None;
+		let mut internal_regs = None;
+		let mut dma = None;
+		let mut timers = None;
+		let mut serial = None;
+		let mut audio = None;
+		let mut ram = None;
+		let mut lcd = None;
+
+		for chunk in chunks {
+			if chunk.tag == CHUNK_METADATA {
+				let info: SaveStateInfo = match decode_from(&mut &chunk.data[..], SizeLimit::Infinite) {
+					Ok(info) => info,
+					Err(_) => return Err("Failed to decode the save state metadata.".to_string()),
+				};
+				if info.rom_crc != self.cartridge.crc {
+					return Err(format!("This save state was made with a different ROM ({} {} {:08X}) than the one that is loaded ({} {} {:08X}).",
+						info.rom_title, info.rom_game_code, info.rom_crc,
+						self.cartridge.title, self.cartridge.game_code, self.cartridge.crc))
+				}
+			} else if chunk.tag == CHUNK_CPU {
+				let mut data = &chunk.data[..];
+				if data.len() < 2 {
+					return Err("The CPU chunk is too short.".to_string())
+				}
+				let branched = data[0] != 0;
+				let prefetch_wait = data[1];
+				data = &data[2..];
+				let registers = match decode_from(&mut data, SizeLimit::Infinite) {
+					Ok(registers) => registers,
+					Err(_) => return Err("Failed to decode the CPU registers.".to_string()),
+				};
+				let clock = match decode_from(&mut data, SizeLimit::Infinite) {
+					Ok(clock) => clock,
+					Err(_) => return Err("Failed to decode the CPU clock.".to_string()),
+				};
+				cpu = Some((branched, prefetch_wait, registers, clock));
+			} else if chunk.tag == CHUNK_JOYPAD {
+				match decode_from(&mut &chunk.data[..], SizeLimit::Infinite) {
+					Ok(j) => joypad = Some(j),
+					Err(_) => return Err("Failed to decode the Joypad data.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_IOREGS {
+				let decoded: Result<(u32, u32, u32, u32, [u32; 4], bool, bool), _> = decode_from(&mut &chunk.data[..], SizeLimit::Infinite);
+				match decoded {
+					Ok(regs) => internal_regs = Some(regs),
+					Err(_) => return Err("Failed to decode the internal registers.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_DMA {
+				let decoded: Result<(bool, [DMAInternalReg; 4]), _> = decode_from(&mut &chunk.data[..], SizeLimit::Infinite);
+				match decoded {
+					Ok(d) => dma = Some(d),
+					Err(_) => return Err("Failed to decode the DMA registers.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_TIMERS {
+				let decoded: Result<[TimerInternalReg; 4], _> = decode_from(&mut &chunk.data[..], SizeLimit::Infinite);
+				match decoded {
+					Ok(t) => timers = Some(t),
+					Err(_) => return Err("Failed to decode the timers.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_SERIAL {
+				let decoded: Result<SerialInternalReg, _> = decode_from(&mut &chunk.data[..], SizeLimit::Infinite);
+				match decoded {
+					Ok(s) => serial = Some(s),
+					Err(_) => return Err("Failed to decode the serial registers.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_AUDIO {
+				let decoded: Result<(GbaChannel1, GbaChannel2, GbaChannel3, GbaChannel4, GbaChannelFIFO, GbaChannelFIFO, GbaAudioSequencer), _> =
+					decode_from(&mut &chunk.data[..], SizeLimit::Infinite);
+				match decoded {
+					Ok(a) => audio = Some(a),
+					Err(_) => return Err("Failed to decode the audio registers.".to_string()),
+				}
+			} else if chunk.tag == CHUNK_RAM {
+				if chunk.data.len() != self.cpu.memory.internal_data.len() - MEM_BIOS.size {
+					return Err(format!("RAM is {} bytes instead of {}.", chunk.data.len(), self.cpu.memory.internal_data.len() - MEM_BIOS.size))
+				}
+				ram = Some(chunk.data);
+			} else if chunk.tag == CHUNK_LCD {
+				match decode_from(&mut &chunk.data[..], SizeLimit::Infinite) {
+					Ok(l) => lcd = Some(l),
+					Err(_) => return Err("Failed to decode the LCD state.".to_string()),
+				}
+			} else if chunk.tag != CHUNK_THUMBNAIL {
+				console_warn!("Skipping unknown save state chunk {}.", tag_name(&chunk.tag));
 			}
-			let branched = buffer[1];
-			let prefetch_wait = buffer[2];
-			self.cpu.branched = branched != 0;
-			self.cpu.prefetch_wait = prefetch_wait;
-		} else {
-			return Err("Failed to read CPU and version information.");
 		}
 
-		if let Ok(cpu_registers) = decode_from(&mut r, SizeLimit::Infinite) {
-			self.cpu.registers = cpu_registers;
-		} else {
-			return Err("Failed to decode the CPU registers.");
+		let (branched, prefetch_wait, registers, clock) = match cpu {
+			Some(cpu) => cpu,
+			None => return Err("The save state has no CPU chunk.".to_string()),
+		};
+		let joypad = match joypad {
+			Some(joypad) => joypad,
+			None => return Err("The save state has no Joypad chunk.".to_string()),
+		};
+		let (bg2x, bg2y, bg3x, bg3y, bg_ref_io, halted, stopped) = match internal_regs {
+			Some(regs) => regs,
+			None => return Err("The save state has no internal registers chunk.".to_string()),
+		};
+		let (dma_dirty, dma_registers) = match dma {
+			Some(dma) => dma,
+			None => return Err("The save state has no DMA chunk.".to_string()),
+		};
+		let timers = match timers {
+			Some(timers) => timers,
+			None => return Err("The save state has no timers chunk.".to_string()),
+		};
+		let serial = match serial {
+			Some(serial) => serial,
+			None => return Err("The save state has no serial chunk.".to_string()),
+		};
+		let (channel1, channel2, channel3, channel4, fifo_a, fifo_b, sequencer) = match audio {
+			Some(audio) => audio,
+			None => return Err("The save state has no audio chunk.".to_string()),
+		};
+		let ram = match ram {
+			Some(ram) => ram,
+			None => return Err("The save state has no RAM chunk.".to_string()),
+		};
+
+		self.cpu.branched = branched;
+		self.cpu.prefetch_wait = prefetch_wait;
+		self.cpu.registers = registers;
+		self.cpu.clock = clock;
+		self.cycle_target = self.cpu.clock.cycles;
+		self.joypad = joypad;
+		{
+			let regs = &mut self.cpu.memory.internal_regs;
+			regs.bg2x = bg2x;
+			regs.bg2y = bg2y;
+			regs.bg3x = bg3x;
+			regs.bg3y = bg3y;
+			regs.bg_ref_io = bg_ref_io;
+			regs.halted = halted;
+			regs.stopped = stopped;
+			regs.dma_dirty = dma_dirty;
+			regs.dma_registers = dma_registers;
+			regs.timers = timers;
+			regs.serial = serial;
+			regs.audio_channel1 = channel1;
+			regs.audio_channel2 = channel2;
+			regs.audio_channel3 = channel3;
+			regs.audio_channel4 = channel4;
+			regs.audio_fifo_a = fifo_a;
+			regs.audio_fifo_b = fifo_b;
+			regs.audio_sequencer = sequencer;
 		}
+		self.cpu.memory.internal_data[MEM_BIOS.size..].copy_from_slice(&ram);
 
-		if let Ok(cpu_clock) = decode_from(&mut r, SizeLimit::Infinite) {
-			self.cpu.clock = cpu_clock;
-		} else {
-			return Err("Failed to decode the CPU clock.");
+		// States from before the LCD chunk just keep the current LCD state,
+		// it only matters for windows that are open across the end of a frame.
+		if let Some((windows, mosaic, frame_select)) = lcd {
+			self.lcd.lines.windows = windows;
+			self.lcd.lines.mosaic = mosaic;
+			self.lcd.lines.frame_select = frame_select;
 		}
 
-		if let Ok(joypad) = decode_from(&mut r, SizeLimit::Infinite) {
-			self.joypad = joypad;
-		} else {
-			return Err("Failed to decode the Joypad data.");
+		return Ok("OK")
+	}
+}
+
+impl Gba {
+	/// Where save states for the loaded ROM go for `slot`.
+	pub fn save_state_path(&self, slot: u8) -> PathBuf {
+		Path::new(&::pyrite::get_settings().save_state_dir).join(format!("{}.ss{}", self.cartridge.file_name(), slot))
+	}
+
+	pub fn save_state_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+		let path = self.save_state_path(slot);
+		match self.save_to_file(&path.to_string_lossy()) {
+			Ok(_) => Ok(path),
+			Err(e) => Err(e),
 		}
+	}
 
-		if let Ok(internal_regs) = decode_from(&mut r, SizeLimit::Infinite) {
-			self.cpu.memory.internal_regs = internal_regs;
-		} else {
-			return Err("Failed to decode the internal registers.");
+	pub fn load_state_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+		let path = self.save_state_path(slot);
+		if !path.exists() {
+			return Err(format!("Slot {} is empty.", slot))
+		}
+		match self.load_from_file(&path.to_string_lossy()) {
+			Ok(_) => Ok(path),
+			Err(e) => Err(e),
 		}
+	}
 
-		{
-			let ram = &mut self.cpu.memory.internal_data[MEM_BIOS.size..];
-			if r.read_exact(ram).is_err() {
-				return Err("Failed to read RAM");
+	fn write_state_chunks(&self, w: &mut Write) {
+		let mut cpu_data = vec![if self.cpu.branched {1} else {0}, self.cpu.prefetch_wait];
+		cpu_data.extend(encode(&self.cpu.registers, SizeLimit::Infinite).expect("Encode CPU registers."));
+		cpu_data.extend(encode(&self.cpu.clock, SizeLimit::Infinite).expect("Encode CPU clock."));
+		write_chunk(w, CHUNK_CPU, &cpu_data);
+
+		let joypad_data: Vec<u8> = encode(&self.joypad, SizeLimit::Infinite).expect("Encode Joypad");
+		write_chunk(w, CHUNK_JOYPAD, &joypad_data);
+
+		let regs = &self.cpu.memory.internal_regs;
+		let ioregs_data: Vec<u8> = encode(&(regs.bg2x, regs.bg2y, regs.bg3x, regs.bg3y, regs.bg_ref_io, regs.halted, regs.stopped),
+			SizeLimit::Infinite).expect("Encode internal registers.");
+		write_chunk(w, CHUNK_IOREGS, &ioregs_data);
+
+		let dma_data: Vec<u8> = encode(&(regs.dma_dirty, &regs.dma_registers), SizeLimit::Infinite).expect("Encode DMA registers.");
+		write_chunk(w, CHUNK_DMA, &dma_data);
+
+		let timers_data: Vec<u8> = encode(&regs.timers, SizeLimit::Infinite).expect("Encode timers.");
+		write_chunk(w, CHUNK_TIMERS, &timers_data);
+
+		let serial_data: Vec<u8> = encode(&regs.serial, SizeLimit::Infinite).expect("Encode serial registers.");
+		write_chunk(w, CHUNK_SERIAL, &serial_data);
+
+		let audio_data: Vec<u8> = encode(&(&regs.audio_channel1, &regs.audio_channel2, &regs.audio_channel3, &regs.audio_channel4,
+			&regs.audio_fifo_a, &regs.audio_fifo_b, &regs.audio_sequencer), SizeLimit::Infinite).expect("Encode audio registers.");
+		write_chunk(w, CHUNK_AUDIO, &audio_data);
+
+		write_chunk(w, CHUNK_RAM, &self.cpu.memory.internal_data[MEM_BIOS.size..]);
+
+		let lines = &self.lcd.lines;
+		let lcd_data: Vec<u8> = encode(&(lines.windows, lines.mosaic, lines.frame_select), SizeLimit::Infinite).expect("Encode LCD state.");
+		write_chunk(w, CHUNK_LCD, &lcd_data);
+		write_chunk(w, CHUNK_END, &[]);
+	}
+
+	/// The screen scaled down by half as a PNG.
+	fn thumbnail(&self) -> Vec<u8> {
+		let screen = &self.lcd.screen_buffer;
+		let mut pixels = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+		for y in 0..THUMBNAIL_HEIGHT {
+			for x in 0..THUMBNAIL_WIDTH {
+				let a = screen[y * 2][x * 2];
+				let b = screen[y * 2][x * 2 + 1];
+				let c = screen[y * 2 + 1][x * 2];
+				let d = screen[y * 2 + 1][x * 2 + 1];
+				pixels.push((
+					((a.0 as u16 + b.0 as u16 + c.0 as u16 + d.0 as u16) / 4) as u8,
+					((a.1 as u16 + b.1 as u16 + c.1 as u16 + d.1 as u16) / 4) as u8,
+					((a.2 as u16 + b.2 as u16 + c.2 as u16 + d.2 as u16) / 4) as u8,
+				));
 			}
 		}
 
-		return Ok("OK")
+		let mut data = Vec::new();
+		png::write_png(&mut data, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, &pixels).expect("Encode thumbnail.");
+		data
+	}
+}
+
+/// Reads only the metadata of the save state at `path` without loading it.
+/// States from before metadata was added don't have any.
+pub fn read_save_state_info(path: &Path) -> Result<SaveStateInfo, String> {
+	let f = match File::open(path) {
+		Ok(file) => file,
+		Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+	};
+	let mut r = DeflateDecoder::new(f);
+	match read_header(&mut r) {
+		Ok(VERSION) => {},
+		Ok(_) => return Err(format!("{} has no metadata.", path.display())),
+		Err(e) => return Err(e),
+	}
+
+	// The metadata is always the first chunk.
+	let chunk = match read_chunk(&mut r) {
+		Ok(chunk) => chunk,
+		Err(e) => return Err(e),
+	};
+	if chunk.tag != CHUNK_METADATA {
+		return Err(format!("{} has no metadata.", path.display()))
+	}
+	match decode_from(&mut &chunk.data[..], SizeLimit::Infinite) {
+		Ok(info) => Ok(info),
+		Err(_) => Err("Failed to decode the save state metadata.".to_string()),
+	}
+}
+
+fn write_header(w: &mut Write) {
+	w.write_all(&PYRITE_HEADER).expect("Write pyrite header.");
+	w.write_all(&[VERSION]).expect("Write version.");
+}
+
+/// Checks the header and returns the format version.
+fn read_header(r: &mut Read) -> Result<u8, String> {
+	let mut buffer = [0u8; 9];
+	if r.read_exact(&mut buffer).is_err() {
+		return Err("Failed to read header.".to_string())
+	}
+	if &buffer[0..8] != &PYRITE_HEADER {
+		return Err("Header does not match Pyrite header.".to_string())
 	}
-}
\ No newline at end of file
+	Ok(buffer[8])
+}
+
+fn chunk_version(tag: &ChunkTag) -> Option<u16> {
+	CHUNK_VERSIONS.iter().find(|&&(t, _)| t == *tag).map(|&(_, version)| version)
+}
+
+fn tag_name(tag: &ChunkTag) -> String {
+	String::from_utf8_lossy(&tag[..]).trim_right().to_string()
+}
+
+/// Chunks are the tag, the version as a little endian u16, the length
+/// of the data as a little endian u32 and then the data.
+fn write_chunk(w: &mut Write, tag: ChunkTag, data: &[u8]) {
+	let version = chunk_version(&tag).expect("Chunk has a version.");
+	let len = data.len() as u32;
+	let header = [
+		tag[0], tag[1], tag[2], tag[3],
+		version as u8, (version >> 8) as u8,
+		len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8,
+	];
+	w.write_all(&header).expect("Write chunk header.");
+	w.write_all(data).expect("Write chunk data.");
+}
+
+fn read_chunk(r: &mut Read) -> Result<Chunk, String> {
+	let mut header = [0u8; 10];
+	if r.read_exact(&mut header).is_err() {
+		return Err("Failed to read a chunk header, the save state is cut off.".to_string())
+	}
+	let tag = [header[0], header[1], header[2], header[3]];
+	let version = (header[4] as u16) | ((header[5] as u16) << 8);
+	let len = (header[6] as usize) | ((header[7] as usize) << 8) | ((header[8] as usize) << 16) | ((header[9] as usize) << 24);
+
+	let mut data = vec![0u8; len];
+	if r.read_exact(&mut data).is_err() {
+		return Err(format!("Failed to read the {} chunk, the save state is cut off.", tag_name(&tag)))
+	}
+	Ok(Chunk { tag: tag, version: version, data: data })
+}
+
+/// Reads every chunk up to the end chunk and brings them up to their current versions.
+fn read_chunks(r: &mut Read) -> Result<Vec<Chunk>, String> {
+	let mut chunks = Vec::new();
+	loop {
+		let chunk = match read_chunk(r) {
+			Ok(chunk) => chunk,
+			Err(e) => return Err(e),
+		};
+		if chunk.tag == CHUNK_END {
+			return Ok(chunks)
+		}
+		match migrate_chunk(chunk) {
+			Ok(chunk) => chunks.push(chunk),
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Upgrades a chunk one version at a time until it's at the current version.
+/// Chunks that aren't known are left alone.
+fn migrate_chunk(mut chunk: Chunk) -> Result<Chunk, String> {
+	let current = match chunk_version(&chunk.tag) {
+		Some(version) => version,
+		None => return Ok(chunk),
+	};
+	if chunk.version > current {
+		return Err(format!("The {} chunk is version {} but this version of Pyrite only knows up to version {}.",
+			tag_name(&chunk.tag), chunk.version, current))
+	}
+	while chunk.version < current {
+		chunk = match upgrade_chunk(chunk) {
+			Ok(chunk) => chunk,
+			Err(e) => return Err(e),
+		};
+	}
+	Ok(chunk)
+}
+
+/// The migration hook: turns a chunk into the next version of itself. When a chunk's
+/// layout changes, its version goes up in CHUNK_VERSIONS and a case that converts
+/// the old layout goes here. Every chunk is still at its first version.
+fn upgrade_chunk(chunk: Chunk) -> Result<Chunk, String> {
+	Err(format!("There's no way to upgrade the {} chunk from version {}.", tag_name(&chunk.tag), chunk.version))
+}
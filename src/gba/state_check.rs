@@ -0,0 +1,100 @@
+//! Checks that save states capture everything. One GBA runs for a while and
+//! saves a state, a second GBA loads it, and then both run side by side.
+//! If anything was left out of the state the two drift apart and the first
+//! difference in the screen, the audio or the rest of the state is reported.
+
+use super::Gba;
+use super::serialization::BinarySerialization;
+
+/// Runs `original` for `save_at` frames, saves a state and loads it into `loaded`,
+/// then runs both for `frames` frames comparing them after every frame.
+/// Both GBAs should have the same BIOS and ROM loaded and not have been run yet.
+pub fn check_save_state(original: &mut Gba, loaded: &mut Gba, save_at: u64, frames: u64) -> Result<(), String> {
+	original.init();
+	loaded.init();
+	check_booted_save_state(original, loaded, save_at, frames)
+}
+
+/// The same as `check_save_state` for GBAs that have already been booted the same way.
+pub fn check_booted_save_state(original: &mut Gba, loaded: &mut Gba, save_at: u64, frames: u64) -> Result<(), String> {
+	for _ in 0..save_at {
+		original.tick();
+	}
+
+	let mut state = Vec::new();
+	original.serialize(&mut state);
+	if let Err(e) = loaded.deserialize(&mut &state[..]) {
+		return Err(format!("Failed to load the state saved at frame {}: {}", save_at, e))
+	}
+
+	original.device.audio_capture = Some(Vec::new());
+	loaded.device.audio_capture = Some(Vec::new());
+
+	for frame in 0..frames {
+		original.tick();
+		loaded.tick();
+		let frame_number = save_at + frame + 1;
+
+		if let Err(e) = compare_screens(original, loaded) {
+			return Err(format!("Frame {}: {}", frame_number, e))
+		}
+
+		if let Err(e) = compare_audio(original, loaded) {
+			return Err(format!("Frame {}: {}", frame_number, e))
+		}
+
+		if let Err(e) = compare_states(original, loaded) {
+			return Err(format!("Frame {}: {}", frame_number, e))
+		}
+	}
+
+	Ok(())
+}
+
+fn compare_screens(original: &Gba, loaded: &Gba) -> Result<(), String> {
+	for (y, (a, b)) in original.lcd.screen_buffer.iter().zip(loaded.lcd.screen_buffer.iter()).enumerate() {
+		for x in 0..240 {
+			if a[x] != b[x] {
+				return Err(format!("The screens differ at ({}, {}): {:?} vs {:?}", x, y, a[x], b[x]))
+			}
+		}
+	}
+	Ok(())
+}
+
+fn compare_audio(original: &Gba, loaded: &Gba) -> Result<(), String> {
+	let a = original.device.audio_capture.as_ref().expect("Audio is being captured.");
+	let b = loaded.device.audio_capture.as_ref().expect("Audio is being captured.");
+	if a.len() != b.len() {
+		return Err(format!("The APU made {} samples vs {} after loading", a.len(), b.len()))
+	}
+	for (index, (sa, sb)) in a.iter().zip(b.iter()).enumerate() {
+		if sa != sb {
+			return Err(format!("The audio differs at sample {}: {:?} vs {:?}", index, sa, sb))
+		}
+	}
+	Ok(())
+}
+
+/// Compares everything that goes into a save state (CPU, memory, IO registers,
+/// audio and LCD state) by serializing both GBAs.
+fn compare_states(original: &Gba, loaded: &Gba) -> Result<(), String> {
+	let mut a = Vec::new();
+	let mut b = Vec::new();
+	original.serialize_uncompressed(&mut a);
+	loaded.serialize_uncompressed(&mut b);
+
+	if a.len() != b.len() {
+		return Err(format!("The states are {} bytes vs {} after loading", a.len(), b.len()))
+	}
+	for index in 0..a.len() {
+		if a[index] != b[index] {
+			return Err(format!("The states differ at byte {}: {:02X} vs {:02X}", index, a[index], b[index]))
+		}
+	}
+
+	if original.cpu.memory.internal_data[..] != loaded.cpu.memory.internal_data[..] {
+		return Err("Memory differs.".to_string())
+	}
+	Ok(())
+}
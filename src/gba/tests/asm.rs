@@ -0,0 +1,119 @@
+//! Just enough of an ARM assembler to write the little programs the tests run.
+
+use std::collections::HashMap;
+
+pub const EQ: u32 = 0x0;
+pub const NE: u32 = 0x1;
+pub const AL: u32 = 0xE;
+
+const OP_AND: u32 = 0x0;
+const OP_SUB: u32 = 0x2;
+const OP_ADD: u32 = 0x4;
+const OP_TST: u32 = 0x8;
+const OP_CMP: u32 = 0xA;
+const OP_ORR: u32 = 0xC;
+const OP_MOV: u32 = 0xD;
+
+pub struct Asm {
+	code: Vec<u32>,
+	labels: HashMap<&'static str, usize>,
+
+	/// (instruction index, condition, label) of branches that still need their offsets.
+	branches: Vec<(usize, u32, &'static str)>,
+}
+
+impl Asm {
+	pub fn new() -> Asm {
+		Asm {
+			code: Vec::new(),
+			labels: HashMap::new(),
+			branches: Vec::new(),
+		}
+	}
+
+	pub fn label(&mut self, name: &'static str) -> &mut Asm {
+		self.labels.insert(name, self.code.len());
+		self
+	}
+
+	pub fn mov(&mut self, rd: u32, imm: u32) -> &mut Asm { self.data_imm(OP_MOV, false, 0, rd, imm) }
+	pub fn add(&mut self, rd: u32, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_ADD, false, rn, rd, imm) }
+	pub fn and(&mut self, rd: u32, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_AND, false, rn, rd, imm) }
+	pub fn orr(&mut self, rd: u32, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_ORR, false, rn, rd, imm) }
+	pub fn subs(&mut self, rd: u32, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_SUB, true, rn, rd, imm) }
+	pub fn tst(&mut self, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_TST, true, rn, 0, imm) }
+	pub fn cmp(&mut self, rn: u32, imm: u32) -> &mut Asm { self.data_imm(OP_CMP, true, rn, 0, imm) }
+
+	/// STRH rd, [rn, #offset]
+	pub fn strh(&mut self, rd: u32, rn: u32, offset: u32) -> &mut Asm {
+		assert!(offset < 0x100, "halfword offsets are 8 bits");
+		self.emit(0xE1C000B0 | (rn << 16) | (rd << 12) | ((offset & 0xF0) << 4) | (offset & 0xF))
+	}
+
+	/// LDRH rd, [rn, #offset]
+	pub fn ldrh(&mut self, rd: u32, rn: u32, offset: u32) -> &mut Asm {
+		assert!(offset < 0x100, "halfword offsets are 8 bits");
+		self.emit(0xE1D000B0 | (rn << 16) | (rd << 12) | ((offset & 0xF0) << 4) | (offset & 0xF))
+	}
+
+	/// STR rd, [rn, #offset]
+	pub fn str(&mut self, rd: u32, rn: u32, offset: u32) -> &mut Asm {
+		assert!(offset < 0x1000, "word offsets are 12 bits");
+		self.emit(0xE5800000 | (rn << 16) | (rd << 12) | offset)
+	}
+
+	/// STRB rd, [rn, #offset]
+	pub fn strb(&mut self, rd: u32, rn: u32, offset: u32) -> &mut Asm {
+		assert!(offset < 0x1000, "byte offsets are 12 bits");
+		self.emit(0xE5C00000 | (rn << 16) | (rd << 12) | offset)
+	}
+
+	/// B<cond> label, the label can come before or after the branch.
+	pub fn b(&mut self, cond: u32, label: &'static str) -> &mut Asm {
+		let index = self.code.len();
+		self.branches.push((index, cond, label));
+		self.emit(0)
+	}
+
+	/// The program as a cartridge that starts running at 0x08000000.
+	pub fn rom(&self) -> Vec<u8> {
+		let mut code = self.code.clone();
+		for &(index, cond, label) in self.branches.iter() {
+			let target = *self.labels.get(label).expect("branch to a label that doesn't exist");
+			// The PC is two instructions ahead.
+			let offset = (target as i32) - (index as i32) - 2;
+			code[index] = (cond << 28) | 0x0A000000 | ((offset as u32) & 0x00FFFFFF);
+		}
+
+		let mut rom = Vec::with_capacity(code.len() * 4);
+		for word in code {
+			rom.push(word as u8);
+			rom.push((word >> 8) as u8);
+			rom.push((word >> 16) as u8);
+			rom.push((word >> 24) as u8);
+		}
+		rom
+	}
+
+	fn data_imm(&mut self, opcode: u32, set_flags: bool, rn: u32, rd: u32, imm: u32) -> &mut Asm {
+		let (rotate, imm8) = encode_imm(imm);
+		let s = if set_flags { 1 << 20 } else { 0 };
+		self.emit((AL << 28) | (1 << 25) | (opcode << 21) | s | (rn << 16) | (rd << 12) | (rotate << 8) | imm8)
+	}
+
+	fn emit(&mut self, instr: u32) -> &mut Asm {
+		self.code.push(instr);
+		self
+	}
+}
+
+/// Finds the 8 bit value and even rotation that make `value`.
+fn encode_imm(value: u32) -> (u32, u32) {
+	for rotate in 0..16 {
+		let imm8 = value.rotate_left(rotate * 2);
+		if imm8 < 0x100 {
+			return (rotate, imm8)
+		}
+	}
+	panic!("0x{:08X} can't be encoded as an immediate", value)
+}
@@ -0,0 +1,86 @@
+//! Golden frames: ROMs are run headless with the BIOS for a number of frames the
+//! same way `--headless --frames <n> --screenshot <file.png>` does, and the last
+//! frame is compared with a known good PNG in misc/golden. Run the tests with
+//! PYRITE_BLESS=1 to write the PNGs after checking that the screenshots in
+//! target/golden look right. These are skipped when the BIOS isn't there.
+
+use std::env;
+use std::fs::File;
+use std::path::Path;
+
+use ::util::png;
+use super::super::Gba;
+
+const GOLDEN_DIR: &'static str = "misc/golden";
+const OUTPUT_DIR: &'static str = "target/golden";
+
+/// Runs `rom` for `frames` frames and compares the screen with misc/golden/`name`.png.
+fn check_golden_frame(name: &str, rom: &str, frames: u64) {
+	if !Path::new(::BIOS_PATH).exists() {
+		println!("Skipping the {} golden frame, there is no BIOS at {}.", name, ::BIOS_PATH);
+		return
+	}
+
+	let mut gba = Box::new(Gba::headless());
+	::load_bios(&mut gba.cpu.memory);
+	::load_rom(&mut gba, rom.to_string());
+	gba.run_frames(frames);
+
+	let output = Path::new(OUTPUT_DIR).join(format!("{}.png", name));
+	if let Err(e) = gba.save_screenshot(Some(&output.to_string_lossy()[..])) {
+		panic!("Failed to save the {} screenshot: {}", name, e)
+	}
+
+	let golden = Path::new(GOLDEN_DIR).join(format!("{}.png", name));
+	if env::var("PYRITE_BLESS").is_ok() {
+		if let Err(e) = gba.save_screenshot(Some(&golden.to_string_lossy()[..])) {
+			panic!("Failed to save the {} golden frame: {}", name, e)
+		}
+		println!("Blessed {}.", golden.display());
+		return
+	}
+	if !golden.exists() {
+		panic!("There is no golden frame at {}, check {} and run with PYRITE_BLESS=1 to make it the golden frame.",
+			golden.display(), output.display())
+	}
+
+	let (expected_width, expected_height, expected) = read_frame(&golden);
+	let (width, height, actual) = read_frame(&output);
+	assert_eq!((width, height), (expected_width, expected_height), "{} is the wrong size", name);
+
+	let mut different = 0;
+	let mut first = None;
+	for (index, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+		if a != e {
+			if first.is_none() { first = Some((index % width, index / width, *a, *e)); }
+			different += 1;
+		}
+	}
+	if let Some((x, y, a, e)) = first {
+		panic!("{} pixels of {} don't match {}, the first is at ({}, {}): got {:?}, expected {:?}. The frame is in {}.",
+			different, name, golden.display(), x, y, a, e, output.display())
+	}
+}
+
+fn read_frame(path: &Path) -> (usize, usize, Vec<(u8, u8, u8)>) {
+	let mut file = match File::open(path) {
+		Ok(f) => f,
+		Err(e) => panic!("Failed to open {}: {}", path.display(), e),
+	};
+	match png::read_png(&mut file) {
+		Ok(frame) => frame,
+		Err(e) => panic!("Failed to read {}: {}", path.display(), e),
+	}
+}
+
+/// Mosaic on a background and a sprite.
+#[test]
+fn mos_demo() {
+	check_golden_frame("mos_demo", "roms/tonc/mos_demo.gba", 60);
+}
+
+/// Two windows over the backgrounds, the object window and the outside window.
+#[test]
+fn win_demo() {
+	check_golden_frame("win_demo", "roms/tonc/win_demo.gba", 60);
+}
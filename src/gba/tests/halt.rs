@@ -0,0 +1,37 @@
+//! Time keeps passing for everything but the CPU while it's halted.
+
+use super::asm::*;
+use super::{test_gba, boot};
+
+/// Starts timer 0 overflowing every 4096 cycles with its IRQ enabled and then halts
+/// over and over, counting how many times it was woken up at 0x03000000.
+/// IRQs are disabled in the CPSR so the CPU just wakes up and keeps going.
+fn timer_halt_program() -> Vec<u8> {
+	let mut asm = Asm::new();
+	asm.mov(1, 0x04000000).add(1, 1, 0x100)
+		.mov(2, 0x04000000).add(2, 2, 0x200)
+		.mov(3, 0x04000000).add(3, 3, 0x300)
+		.mov(4, 0x03000000)
+		.mov(5, 0)
+		.mov(0, 0xF000).strh(0, 1, 0x0) // TM0CNT_L = reload at 0xF000
+		.mov(0, 0xC0).strh(0, 1, 0x2) // TM0CNT_H = start with IRQ
+		.mov(0, 0x8).strh(0, 2, 0x0) // IE = timer 0
+		.mov(0, 1).strh(0, 2, 0x8) // IME = 1
+
+		.label("halt").mov(0, 0).strb(0, 3, 0x1) // HALTCNT = halt
+		.add(5, 5, 1).str(5, 4, 0)
+		.b(AL, "halt");
+	asm.rom()
+}
+
+#[test]
+fn timer_irqs_wake_the_cpu_from_halt() {
+	let mut gba = test_gba(timer_halt_program());
+	boot(&mut gba);
+
+	for _ in 0..10 { gba.frame(); }
+
+	// 10 frames of 280896 cycles with an overflow every 4096 cycles.
+	let wakeups = gba.cpu.memory.read32(0x03000000);
+	assert!(wakeups >= 680 && wakeups <= 686, "woke up {} times instead of about 685", wakeups);
+}
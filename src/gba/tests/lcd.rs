@@ -0,0 +1,313 @@
+//! Register level tests of the LCD, these draw lines straight from memory
+//! without running the CPU.
+
+use super::super::hw::lcd::{GbaLcdLine, OutputPixel};
+use super::*;
+
+const DISPCNT: u32 = 0x04000000;
+const GREENSWAP: u32 = 0x04000002;
+const BLDCNT: u32 = 0x04000050;
+const BLDY: u32 = 0x04000054;
+const BG2CNT: u32 = 0x0400000C;
+const WIN0H: u32 = 0x04000040;
+const WIN1H: u32 = 0x04000042;
+const WIN0V: u32 = 0x04000044;
+const WIN1V: u32 = 0x04000046;
+const WININ: u32 = 0x04000048;
+const WINOUT: u32 = 0x0400004A;
+const MOSAIC: u32 = 0x0400004C;
+
+fn draw_line(gba: &mut Gba, line: u16) -> GbaLcdLine {
+	gba.lcd.start_line(&gba.cpu.memory, line);
+	gba.lcd.render_line(&mut gba.cpu.memory, line);
+	gba.lcd.screen_buffer[line as usize]
+}
+
+/// Draws a whole frame the way the GBA does, starting every line including the ones in VBlank.
+fn draw_frame(gba: &mut Gba) {
+	for line in 0..228 {
+		gba.lcd.start_line(&gba.cpu.memory, line);
+		if line < 160 {
+			gba.lcd.render_line(&mut gba.cpu.memory, line);
+		}
+	}
+}
+
+/// A line of mode 3 where every pixel pair is `left` and `right`.
+fn mode3_line(gba: &mut Gba, left: u16, right: u16) {
+	gba.cpu.memory.write16(DISPCNT, 0x0403); // Mode 3 with BG2
+	for x in 0..120 {
+		gba.cpu.memory.write16(0x06000000 + x * 4, left);
+		gba.cpu.memory.write16(0x06000000 + x * 4 + 2, right);
+	}
+}
+
+#[test]
+fn forced_blank_is_white() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.write16(0x05000000, 0x7fff); // White backdrop
+	gba.cpu.memory.write16(DISPCNT, 0x0000);
+	let white = draw_line(&mut gba, 0);
+
+	gba.cpu.memory.write16(0x05000000, 0x001f); // Red backdrop
+	gba.cpu.memory.write16(DISPCNT, 0x0080);
+	let blank = draw_line(&mut gba, 0);
+	assert!(blank.iter().zip(white.iter()).all(|(a, b)| a == b), "forced blank should look like a white backdrop");
+}
+
+#[test]
+fn green_swap_swaps_gba_colors() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	// Red and green swapped give yellow and black.
+	mode3_line(&mut gba, 0x03ff, 0x0000);
+	let expected = draw_line(&mut gba, 0);
+
+	mode3_line(&mut gba, 0x001f, 0x03e0);
+	gba.cpu.memory.write16(GREENSWAP, 1);
+	let swapped = draw_line(&mut gba, 0);
+	assert!(swapped.iter().zip(expected.iter()).all(|(a, b)| a == b));
+
+	gba.cpu.memory.write16(GREENSWAP, 0);
+	let normal = draw_line(&mut gba, 0);
+	assert!(normal[0] != swapped[0] && normal[1] != swapped[1]);
+}
+
+#[test]
+fn frame_select_is_latched_at_the_start_of_the_line() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.write16(0x05000002, 0x001f); // Red
+	gba.cpu.memory.write16(0x05000004, 0x7c00); // Blue
+	for x in 0..(120 * 3) {
+		gba.cpu.memory.write16(0x06000000 + x * 2, 0x0101); // Frame 0 is red
+		gba.cpu.memory.write16(0x0600A000 + x * 2, 0x0202); // Frame 1 is blue
+	}
+
+	gba.cpu.memory.write16(DISPCNT, 0x0404); // Mode 4 with BG2, frame 0
+	let red = draw_line(&mut gba, 0);
+	gba.cpu.memory.write16(DISPCNT, 0x0414);
+	let blue = draw_line(&mut gba, 0);
+	assert!(red[0] != blue[0]);
+
+	// Switching frames while the line is being drawn waits for the next line.
+	gba.cpu.memory.write16(DISPCNT, 0x0404);
+	gba.lcd.start_line(&gba.cpu.memory, 1);
+	gba.cpu.memory.write16(DISPCNT, 0x0414);
+	gba.lcd.render_line(&mut gba.cpu.memory, 1);
+	assert_eq!(gba.lcd.screen_buffer[1][0], red[0]);
+
+	let next = draw_line(&mut gba, 2);
+	assert_eq!(next[0], blue[0]);
+}
+
+#[test]
+fn evy_above_16_is_16() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	gba.cpu.memory.write16(DISPCNT, 0x0000);
+
+	gba.cpu.memory.write16(0x05000000, 0x7fff);
+	let white = draw_line(&mut gba, 0);
+	gba.cpu.memory.write16(0x05000000, 0x0000);
+	let black = draw_line(&mut gba, 0);
+
+	gba.cpu.memory.write16(0x05000000, 0x3def); // Gray backdrop
+	for &evy in [16, 17, 31].iter() {
+		gba.cpu.memory.write16(BLDY, evy);
+		gba.cpu.memory.write16(BLDCNT, 0x00a0); // Brighten the backdrop
+		assert_eq!(draw_line(&mut gba, 0)[0], white[0], "brightening with EVY={}", evy);
+		gba.cpu.memory.write16(BLDCNT, 0x00e0); // Darken the backdrop
+		assert_eq!(draw_line(&mut gba, 0)[0], black[0], "darkening with EVY={}", evy);
+	}
+}
+
+/// Fills the mode 3 frame with a different color for every pixel in the top 128 lines.
+fn mode3_gradient(gba: &mut Gba) {
+	for y in 0..160 {
+		for x in 0..240 {
+			gba.cpu.memory.write16(0x06000000 + (y * 240 + x) * 2, ((y & 0x7f) << 8 | x) as u16);
+		}
+	}
+}
+
+#[test]
+fn bg_mosaic_repeats_the_top_left_pixel_of_each_block() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	mode3_gradient(&mut gba);
+	gba.cpu.memory.write16(DISPCNT, 0x0403); // Mode 3 with BG2
+	gba.cpu.memory.write16(MOSAIC, 0x0032); // BG blocks 3 wide and 4 tall
+	draw_frame(&mut gba);
+	let plain = gba.lcd.screen_buffer;
+
+	gba.cpu.memory.write16(BG2CNT, 0x0040);
+	draw_frame(&mut gba);
+	for y in 0..160 {
+		for x in 0..240 {
+			assert_eq!(gba.lcd.screen_buffer[y][x], plain[y - y % 4][x - x % 3], "pixel ({}, {})", x, y);
+		}
+	}
+}
+
+#[test]
+fn vertical_mosaic_counter_follows_mosaic_changes() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	mode3_gradient(&mut gba);
+	gba.cpu.memory.write16(DISPCNT, 0x0403);
+	draw_frame(&mut gba);
+	let plain = gba.lcd.screen_buffer;
+
+	gba.cpu.memory.write16(BG2CNT, 0x0040);
+	gba.cpu.memory.write16(MOSAIC, 0x0030); // 4 lines tall
+	for line in 0..6 {
+		draw_line(&mut gba, line);
+	}
+	// The counter is at 2 for line 6, passes the new size of 2 lines
+	// at the end of it and starts a new block on line 7.
+	gba.cpu.memory.write16(MOSAIC, 0x0010);
+	for line in 6..12 {
+		draw_line(&mut gba, line);
+	}
+
+	let rows = [0, 0, 0, 0, 4, 4, 4, 7, 7, 9, 9, 11];
+	for (line, &row) in rows.iter().enumerate() {
+		assert!(gba.lcd.screen_buffer[line].iter().zip(plain[row].iter()).all(|(a, b)| a == b),
+			"line {} should be drawn from line {}", line, row);
+	}
+}
+
+#[test]
+fn obj_mosaic_blocks_are_aligned_to_the_screen() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	// A 16x16 256 color OBJ at (2, 0) where every dot has its own color.
+	for i in 0..256 {
+		gba.cpu.memory.write16(0x05000200 + i * 2, (i * 127) as u16);
+	}
+	for oy in 0..16 {
+		for ox in 0..8 {
+			let tile = (oy / 8) * 2 + (ox * 2) / 8;
+			let address = 0x06014000 + tile * 64 + (oy % 8) * 8 + (ox * 2) % 8;
+			let dot = oy * 16 + ox * 2;
+			gba.cpu.memory.write16(address, (dot | (dot + 1) << 8) as u16);
+		}
+	}
+	gba.cpu.memory.write16(0x07000000, 0x2000); // 256 colors, square
+	gba.cpu.memory.write16(0x07000002, 0x4002); // 16x16 at x = 2
+	gba.cpu.memory.write16(0x07000004, 512);
+	for obj in 1..128 {
+		gba.cpu.memory.write16(0x07000000 + obj * 8, 0x0200); // Not displayed
+	}
+	gba.cpu.memory.write16(DISPCNT, 0x1043); // Mode 3 with OBJs and 1D mapping
+	gba.cpu.memory.write16(MOSAIC, 0x1300); // OBJ blocks 4 wide and 2 tall
+	draw_frame(&mut gba);
+	let plain = gba.lcd.screen_buffer;
+
+	gba.cpu.memory.write16(0x07000000, 0x3000); // Mosaic on
+	draw_frame(&mut gba);
+	for y in 0..16 {
+		for x in 2..18 {
+			// The first block only has the OBJ's first two dots in it.
+			let block_x = max!(2, x - x % 4);
+			assert_eq!(gba.lcd.screen_buffer[y][x], plain[y - y % 2][block_x], "pixel ({}, {})", x, y);
+		}
+	}
+}
+
+/// Fills mode 3 with red over a blue backdrop and returns the two colors, the
+/// window tests only let BG2 through in some places.
+fn window_colors(gba: &mut Gba) -> (OutputPixel, OutputPixel) {
+	gba.cpu.memory.write16(0x05000000, 0x7c00);
+	mode3_line(gba, 0x001f, 0x001f);
+	for y in 1..160 {
+		for x in 0..240 {
+			gba.cpu.memory.write16(0x06000000 + (y * 240 + x) * 2, 0x001f);
+		}
+	}
+	let red = draw_line(gba, 0)[0];
+	gba.cpu.memory.write16(DISPCNT, 0x0003);
+	let blue = draw_line(gba, 0)[0];
+	(red, blue)
+}
+
+fn check_window(gba: &Gba, red: OutputPixel, blue: OutputPixel, inside: &Fn(usize, usize) -> bool) {
+	for y in 0..160 {
+		for x in 0..240 {
+			let expected = if inside(x, y) { red } else { blue };
+			assert_eq!(gba.lcd.screen_buffer[y][x], expected, "pixel ({}, {})", x, y);
+		}
+	}
+}
+
+#[test]
+fn windows_wrap_around_the_screen() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	let (red, blue) = window_colors(&mut gba);
+
+	gba.cpu.memory.write16(WIN0H, (200 << 8) | 40);
+	gba.cpu.memory.write16(WIN0V, (150 << 8) | 10);
+	gba.cpu.memory.write16(WININ, 0x0004); // Only BG2 inside
+	gba.cpu.memory.write16(WINOUT, 0x0000);
+	gba.cpu.memory.write16(DISPCNT, 0x2403); // Mode 3 with BG2 and WIN0
+
+	// The vertical flag turned on at line 150 stays on through VBlank into the next frame.
+	draw_frame(&mut gba);
+	draw_frame(&mut gba);
+	check_window(&gba, red, blue, &|x, y| (x >= 200 || x < 40) && (y >= 150 || y < 10));
+}
+
+#[test]
+fn window_edges_past_the_screen() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	let (red, blue) = window_colors(&mut gba);
+
+	// Right never turns the window off and the top is only reached in VBlank.
+	gba.cpu.memory.write16(WIN0H, (100 << 8) | 250);
+	gba.cpu.memory.write16(WIN0V, (200 << 8) | 5);
+	gba.cpu.memory.write16(WININ, 0x0004);
+	gba.cpu.memory.write16(WINOUT, 0x0000);
+	gba.cpu.memory.write16(DISPCNT, 0x2403);
+
+	draw_frame(&mut gba);
+	draw_frame(&mut gba);
+	check_window(&gba, red, blue, &|x, y| x >= 100 && y < 5);
+}
+
+#[test]
+fn window_0_beats_window_1_beats_the_obj_window() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+	let (red, blue) = window_colors(&mut gba);
+
+	// An 8x8 OBJ window in the top left corner.
+	for offset in 0..16 {
+		gba.cpu.memory.write16(0x06014000 + offset * 2, 0x1111);
+	}
+	gba.cpu.memory.write16(0x05000202, 0x7fff);
+	gba.cpu.memory.write16(0x07000000, 0x0800);
+	gba.cpu.memory.write16(0x07000002, 0x0000);
+	gba.cpu.memory.write16(0x07000004, 512);
+	for obj in 1..128 {
+		gba.cpu.memory.write16(0x07000000 + obj * 8, 0x0200); // Not displayed
+	}
+
+	gba.cpu.memory.write16(WIN0H, (4 << 8) | 60);
+	gba.cpu.memory.write16(WIN1H, (40 << 8) | 100);
+	gba.cpu.memory.write16(WIN0V, 160);
+	gba.cpu.memory.write16(WIN1V, 160);
+	gba.cpu.memory.write16(WININ, 0x0400); // Nothing in WIN0, BG2 in WIN1
+	gba.cpu.memory.write16(WINOUT, 0x0400); // Nothing outside, BG2 in the OBJ window
+	gba.cpu.memory.write16(DISPCNT, 0xF443); // Mode 3 with BG2, OBJs, all three windows and 1D mapping
+
+	draw_frame(&mut gba);
+	check_window(&gba, red, blue, &|x, y| (x < 4 && y < 8) || (x >= 60 && x < 100));
+}
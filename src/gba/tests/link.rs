@@ -0,0 +1,124 @@
+//! Two GBAs on an in-process link cable doing Multi-Player transfers.
+
+use super::{test_gba, boot};
+use super::asm::*;
+use super::super::core::memory::*;
+
+const PARENT_SEND: u16 = 0x1100;
+const CHILD_SEND: u16 = 0x2200;
+
+/// Both GBAs run this. Each one puts the port in Multi-Player mode and looks at
+/// SIOCNT to see if it's the parent. The parent waits a bit for the child to be ready
+/// and starts a transfer, then starts another one every time 0x03000000 is set.
+fn multiplayer_program() -> Vec<u8> {
+	let mut asm = Asm::new();
+	asm.mov(0, 0x04000000).add(0, 0, 0x100) // r0 = 0x04000100
+		.mov(4, 0x03000000)
+		.mov(1, 0).strh(1, 0, 0x34) // RCNT = 0
+		.mov(1, 0x2000).orr(1, 1, 3).strh(1, 0, 0x28) // SIOCNT = Multi-Player at 115200 baud
+		.ldrh(2, 0, 0x28).tst(2, 0x4).b(NE, "child")
+
+		.mov(3, PARENT_SEND as u32).strh(3, 0, 0x2A)
+		.mov(3, 0x4000)
+		.label("delay").subs(3, 3, 1).b(NE, "delay")
+		.label("transfer").orr(3, 1, 0x80).strh(3, 0, 0x28)
+		.label("busy").ldrh(2, 0, 0x28).tst(2, 0x80).b(NE, "busy")
+		.label("wait").ldrh(2, 4, 0).cmp(2, 0).b(EQ, "wait")
+		.mov(2, 0).strh(2, 4, 0)
+		.b(AL, "transfer")
+
+		.label("child").mov(3, CHILD_SEND as u32).strh(3, 0, 0x2A)
+		.label("idle").b(AL, "idle");
+	asm.rom()
+}
+
+#[test]
+fn multiplayer_transfer_and_disconnect() {
+	let mut parent = test_gba(multiplayer_program());
+	parent.link_local_players(1);
+	boot(&mut parent);
+
+	for _ in 0..2 { parent.frame(); }
+
+	{
+		let child = &parent.linked_players[0];
+		for gba in [&parent, child].iter() {
+			assert_eq!(gba.cpu.memory.get_reg(ioreg::SIOMULTI0), PARENT_SEND);
+			assert_eq!(gba.cpu.memory.get_reg(ioreg::SIOMULTI1), CHILD_SEND);
+			assert_eq!(gba.cpu.memory.get_reg(ioreg::SIOMULTI2), 0xFFFF);
+			assert_eq!(gba.cpu.memory.get_reg(ioreg::SIOMULTI3), 0xFFFF);
+		}
+
+		let parent_siocnt = parent.cpu.memory.get_reg(ioreg::SIOCNT);
+		assert_eq!(parent_siocnt & 0x80, 0, "the transfer should be done");
+		assert_eq!(parent_siocnt & 0x04, 0, "the parent's SI terminal is low");
+		assert_eq!((parent_siocnt >> 4) & 3, 0, "the parent is player 0");
+		assert_eq!(parent_siocnt & 0x40, 0, "no error");
+
+		let child_siocnt = child.cpu.memory.get_reg(ioreg::SIOCNT);
+		assert_eq!(child_siocnt & 0x04, 0x04, "the child's SI terminal is high");
+		assert_eq!((child_siocnt >> 4) & 3, 1, "the child is player 1");
+		assert_eq!(child_siocnt & 0x40, 0, "no error");
+	}
+
+	// Unplug the child and have the parent try again.
+	parent.linked_players.clear();
+	parent.cpu.memory.write16(0x03000000, 1);
+	for _ in 0..2 { parent.frame(); }
+
+	let parent_siocnt = parent.cpu.memory.get_reg(ioreg::SIOCNT);
+	assert_eq!(parent.cpu.memory.read16(0x03000000), 0, "the parent should have started another transfer");
+	assert_eq!(parent_siocnt & 0x80, 0, "the transfer should be done");
+	assert_eq!(parent_siocnt & 0x40, 0x40, "the child going away is an error");
+	assert_eq!(parent.cpu.memory.get_reg(ioreg::SIOMULTI1), 0xFFFF);
+}
+
+/// Both GBAs run this. They halt until every VBlank, then the parent starts a
+/// transfer and waits for it while the child just counts VBlanks at 0x03000000.
+/// The parent counts finished transfers there instead.
+fn halting_program() -> Vec<u8> {
+	let mut asm = Asm::new();
+	asm.mov(0, 0x04000000).add(0, 0, 0x100) // r0 = 0x04000100
+		.mov(4, 0x03000000)
+		.mov(5, 0)
+		.mov(6, 0x04000000)
+		.mov(7, 0x04000000).add(7, 7, 0x200)
+		.mov(8, 0x04000000).add(8, 8, 0x300)
+		.mov(1, 0).strh(1, 0, 0x34) // RCNT = 0
+		.mov(1, 0x2000).orr(1, 1, 3).strh(1, 0, 0x28) // SIOCNT = Multi-Player at 115200 baud
+		.mov(2, 0x8).strh(2, 6, 0x4) // DISPSTAT = VBlank IRQ
+		.mov(2, 0x1).strh(2, 7, 0x0) // IE = VBlank
+		.mov(2, 0x1).strh(2, 7, 0x8) // IME = 1
+		.ldrh(2, 0, 0x28).tst(2, 0x4).b(NE, "child")
+
+		.mov(3, PARENT_SEND as u32).strh(3, 0, 0x2A)
+		.label("parent").mov(2, 0).strb(2, 8, 0x1) // HALTCNT = halt
+		.orr(3, 1, 0x80).strh(3, 0, 0x28)
+		.label("busy").ldrh(2, 0, 0x28).tst(2, 0x80).b(NE, "busy")
+		.add(5, 5, 1).str(5, 4, 0)
+		.b(AL, "parent")
+
+		.label("child").mov(3, CHILD_SEND as u32).strh(3, 0, 0x2A)
+		.label("vblank").mov(2, 0).strb(2, 8, 0x1) // HALTCNT = halt
+		.add(5, 5, 1).str(5, 4, 0)
+		.b(AL, "vblank");
+	asm.rom()
+}
+
+#[test]
+fn halting_players_stay_in_step() {
+	let mut parent = test_gba(halting_program());
+	parent.link_local_players(1);
+	boot(&mut parent);
+
+	for _ in 0..300 { parent.frame(); }
+
+	let child = &parent.linked_players[0];
+	let apart = (parent.cpu.clock.cycles as i64 - child.cpu.clock.cycles as i64).abs();
+	assert!(apart < 64, "the players are {} cycles apart", apart);
+
+	assert_eq!(parent.cpu.memory.read32(0x03000000), 300, "the parent should have finished a transfer every frame");
+	assert_eq!(child.cpu.memory.read32(0x03000000), 300, "the child should have woken up every frame");
+	assert_eq!(parent.cpu.memory.get_reg(ioreg::SIOCNT) & 0x40, 0, "no error");
+	assert_eq!(parent.cpu.memory.get_reg(ioreg::SIOMULTI1), CHILD_SEND);
+}
@@ -0,0 +1,104 @@
+//! Reads with side effects only happen when the CPU loads from memory.
+
+use super::super::core::memory::ioreg;
+use super::super::device::joybus::JoyBusCommand;
+use super::super::hw::serial::joybus;
+use super::*;
+
+#[test]
+fn debug_reads_leave_siodata8_alone() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.read8(0x0400012A);
+	gba.cpu.memory.read16(0x04000128);
+	gba.cpu.memory.read32(0x04000128);
+	assert!(!gba.cpu.memory.internal_regs.serial.uart_data_read.get(), "a debug read popped the UART FIFO");
+
+	gba.cpu.mread16_al(0x04000128);
+	assert!(!gba.cpu.memory.internal_regs.serial.uart_data_read.get(), "SIOCNT isn't SIODATA8");
+
+	gba.cpu.mread32_al(0x04000128);
+	assert!(gba.cpu.memory.internal_regs.serial.uart_data_read.get(), "the CPU read SIODATA8");
+}
+
+#[test]
+fn reading_joy_recv_clears_the_receive_flag() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	joybus::command_received(&mut gba.cpu, JoyBusCommand::Write(0x12345678));
+	assert_eq!(gba.cpu.memory.get_reg(ioreg::JOY_STAT) & 0x2, 0x2);
+
+	// Acknowledging Receive Complete in JOYCNT doesn't touch JOY_STAT.
+	gba.cpu.memory.write16(0x04000140, 0x2);
+	joybus::on_write(&mut gba.cpu);
+	assert_eq!(gba.cpu.memory.get_reg(ioreg::JOYCNT) & 0x2, 0);
+	assert_eq!(gba.cpu.memory.get_reg(ioreg::JOY_STAT) & 0x2, 0x2);
+
+	gba.cpu.memory.read32(0x04000150);
+	joybus::tick(&mut gba.cpu);
+	assert_eq!(gba.cpu.memory.get_reg(ioreg::JOY_STAT) & 0x2, 0x2, "a debug read acknowledged JOY_RECV");
+
+	assert_eq!(gba.cpu.mread16_al(0x04000152), 0x1234);
+	joybus::tick(&mut gba.cpu);
+	assert_eq!(gba.cpu.memory.get_reg(ioreg::JOY_STAT) & 0x2, 0);
+}
+
+#[test]
+fn debug_reads_of_keyinput_are_not_input() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.read16(0x04000130);
+	gba.cpu.memory.read32(0x04000130);
+	assert!(!gba.cpu.memory.keyinput_read.get(), "a debug read polled the buttons");
+
+	gba.cpu.mread8_al(0x04000131);
+	assert!(gba.cpu.memory.keyinput_read.get(), "the CPU polled the buttons");
+}
+
+#[test]
+fn undoing_display_writes_restores_the_reference_points() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.write32(0x04000028, 0x00001000); // BG2X
+	gba.cpu.memory.internal_regs.advance_bg_refs(0x0100, 0, 0, 0);
+	assert_eq!(gba.cpu.memory.internal_regs.bg2x, 0x1100);
+
+	gba.cpu.memory.display_writes.clear();
+	gba.cpu.memory.track_display_writes = true;
+	gba.cpu.memory.write32(0x04000028, 0x00002000);
+	gba.cpu.memory.track_display_writes = false;
+	assert_eq!(gba.cpu.memory.internal_regs.bg2x, 0x2000);
+
+	gba.cpu.memory.undo_display_writes();
+	assert_eq!(gba.cpu.memory.internal_regs.bg2x, 0x1100, "the latch from before the write");
+	assert_eq!(gba.cpu.memory.read32(0x04000028), 0x1000);
+
+	gba.cpu.memory.redo_display_writes();
+	assert_eq!(gba.cpu.memory.internal_regs.bg2x, 0x2000);
+	assert_eq!(gba.cpu.memory.read32(0x04000028), 0x2000);
+}
+
+#[test]
+fn only_visible_vram_writes_are_tracked() {
+	let mut gba = test_gba(Vec::new());
+	boot(&mut gba);
+
+	gba.cpu.memory.write16(0x04000000, 0x0403); // Mode 3 with BG2
+	gba.lcd.start_line(&gba.cpu.memory, 10);
+	gba.cpu.memory.display_vram = gba.lcd.visible_vram(&gba.cpu.memory, 10);
+	gba.cpu.memory.display_writes.clear();
+	gba.cpu.memory.track_display_writes = true;
+
+	gba.cpu.memory.write16(0x06000000 + 9 * 480, 0x7fff); // line 9
+	gba.cpu.memory.write16(0x06000000 + 11 * 480, 0x7fff); // line 11
+	gba.cpu.memory.write16(0x06000000 + 10 * 480, 0x0000); // line 10, but nothing changes
+	assert!(gba.cpu.memory.display_writes.is_empty());
+
+	gba.cpu.memory.write16(0x06000000 + 10 * 480 + 2, 0x7fff);
+	assert_eq!(gba.cpu.memory.display_writes.len(), 2);
+	gba.cpu.memory.track_display_writes = false;
+}
@@ -0,0 +1,30 @@
+//! Tests that run small generated programs on a headless GBA. They don't need
+//! the BIOS, the CPU starts at the cartridge the way the BIOS would leave it.
+//! The golden frame tests are the exception, they run real ROMs.
+
+mod asm;
+mod golden;
+mod halt;
+mod lcd;
+mod link;
+mod memory;
+mod rewind;
+mod state;
+
+use super::Gba;
+
+/// A headless GBA that will run `rom` from the start.
+fn test_gba(rom: Vec<u8>) -> Gba {
+	let mut gba = Gba::headless();
+	gba.load_cartridge(rom);
+	gba
+}
+
+/// Boots `gba` and everything linked to it straight into the cartridge.
+fn boot(gba: &mut Gba) {
+	gba.init();
+	gba.skip_bios();
+	for player in gba.linked_players.iter_mut() {
+		player.skip_bios();
+	}
+}
@@ -0,0 +1,72 @@
+//! The rewind buffer's deltas and going back one frame at a time.
+
+use super::super::device::rewind::{RewindBuffer, encode_delta, decode_delta};
+
+fn round_trip(a: &[u8], b: &[u8]) -> Vec<u8> {
+	let delta = encode_delta(a, b);
+	decode_delta(b, &delta)
+}
+
+#[test]
+fn deltas_between_snapshots_of_different_lengths() {
+	let long: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+	let short: Vec<u8> = (0..600).map(|i| (i * 3) as u8).collect();
+
+	assert_eq!(round_trip(&long, &short), long);
+	assert_eq!(round_trip(&short, &long), short);
+	assert_eq!(round_trip(&long, &[]), long);
+	assert_eq!(round_trip(&[], &long), Vec::<u8>::new());
+}
+
+#[test]
+fn deltas_leave_out_long_zero_runs() {
+	let mut a = vec![0u8; 100000];
+	let mut b = a.clone();
+	a[0] = 1;
+	a[50000] = 2;
+	b[50003] = 3;
+	a[99999] = 4;
+	b[99999] = 5;
+
+	let delta = encode_delta(&a, &b);
+	assert!(delta.len() < 64, "the delta is {} bytes", delta.len());
+	assert_eq!(decode_delta(&b, &delta), a);
+	assert_eq!(round_trip(&b, &a), b);
+}
+
+#[test]
+fn step_back_goes_back_one_frame_at_a_time() {
+	// The state after each frame, they aren't all the same length.
+	let snapshot = |frame: u8| vec![frame; 64 + frame as usize * 16];
+
+	let mut buffer = RewindBuffer::new();
+	for frame in 1..7 {
+		// The keys held during each frame are its number.
+		if buffer.frame(2, frame as u16) {
+			buffer.push(snapshot(frame), 100, 1024 * 1024);
+		}
+	}
+	assert_eq!(buffer.len(), 3);
+
+	// At frame 6, every step loads the snapshot before the frame and runs up to it
+	// with the keys that were held the first time.
+	assert_eq!(buffer.step_back(), Some((snapshot(4), vec![5])));
+	assert_eq!(buffer.step_back(), Some((snapshot(2), vec![3, 4])));
+	assert_eq!(buffer.step_back(), Some((snapshot(2), vec![3])));
+	// There's nothing before frame 2.
+	assert_eq!(buffer.step_back(), Some((snapshot(2), vec![3])));
+	assert_eq!(buffer.len(), 1);
+
+	// Playing again from frame 3 takes the next snapshot after frame 4, two frames after the last one.
+	assert!(buffer.frame(2, 14));
+	buffer.push(snapshot(4), 100, 1024 * 1024);
+	assert_eq!(buffer.step_back(), Some((snapshot(2), vec![3])));
+}
+
+#[test]
+fn step_back_needs_a_frame_after_the_snapshot() {
+	let mut buffer = RewindBuffer::new();
+	assert!(buffer.frame(1, 0));
+	buffer.push(vec![1; 64], 100, 1024 * 1024);
+	assert_eq!(buffer.step_back(), None);
+}
@@ -0,0 +1,72 @@
+//! Saving a state, loading it into another GBA and running both side by side.
+
+use super::asm::*;
+use super::super::state_check;
+use super::super::serialization::BinarySerialization;
+use std::env;
+use std::fs;
+use super::{test_gba, boot};
+
+/// Draws a gradient in mode 3 that keeps going past the end of the screen, counts
+/// in IWRAM and plays channel 2, restarting it at a new frequency every so often.
+fn busy_program() -> Vec<u8> {
+	let mut asm = Asm::new();
+	asm.mov(0, 0x04000000)
+		.mov(1, 0x400).orr(1, 1, 3).strh(1, 0, 0x00) // DISPCNT = mode 3 with BG2
+		.mov(1, 0x80).strh(1, 0, 0x84) // SOUNDCNT_X = master enable
+		.mov(1, 0x2200).orr(1, 1, 0x77).strh(1, 0, 0x80) // SOUNDCNT_L = channel 2 left and right at full volume
+		.mov(1, 2).strh(1, 0, 0x82) // SOUNDCNT_H = PSG at 100%
+		.mov(1, 0xF000).orr(1, 1, 0x80).strh(1, 0, 0x68) // SOUND2CNT_L = volume 15, 50% duty
+		.mov(1, 0x8000).orr(1, 1, 0x400).strh(1, 0, 0x6C) // SOUND2CNT_H = restart
+		.mov(4, 0x03000000)
+		.mov(5, 0)
+		.mov(6, 0x06000000)
+
+		.label("loop").add(5, 5, 1)
+		.strh(5, 6, 0).add(6, 6, 2)
+		.str(5, 4, 0)
+		.tst(5, 0xFF0).b(NE, "loop")
+		.and(3, 5, 0x700).orr(3, 3, 0x8000).strh(3, 0, 0x6C)
+		.b(AL, "loop");
+	asm.rom()
+}
+
+#[test]
+fn loaded_states_run_the_same_as_the_original() {
+	let mut original = test_gba(busy_program());
+	let mut loaded = test_gba(busy_program());
+	boot(&mut original);
+	boot(&mut loaded);
+
+	if let Err(e) = state_check::check_booted_save_state(&mut original, &mut loaded, 10, 20) {
+		panic!("{}", e);
+	}
+
+	// Make sure there was something to compare.
+	let audio = loaded.device.audio_capture.as_ref().expect("Audio is being captured.");
+	assert!(audio.iter().any(|sample| *sample != audio[0]), "the program didn't make any sound");
+	assert!(loaded.lcd.screen_buffer.iter().any(|line| line.iter().any(|pixel| *pixel != line[0])), "the program didn't draw anything");
+	assert!(loaded.cpu.memory.read32(0x03000000) > 0, "the program didn't run");
+}
+
+#[test]
+fn states_are_not_loaded_under_a_movie() {
+	let mut gba = test_gba(busy_program());
+	boot(&mut gba);
+	let path = env::temp_dir().join("pyrite-movie-state-test.pss");
+	let path = path.to_string_lossy().into_owned();
+	gba.save_to_file(&path).expect("Save the state.");
+
+	for _ in 0..5 { gba.frame(); }
+	let movie_path = env::temp_dir().join("pyrite-movie-state-test.pmv");
+	gba.start_movie_recording(Some(&movie_path.to_string_lossy()[..])).expect("Start recording.");
+	let cycles = gba.cpu.clock.cycles;
+
+	assert!(gba.load_from_file(&path).is_err());
+	assert_eq!(gba.cpu.clock.cycles, cycles);
+
+	// Drop the movie without saving it.
+	gba.device.movie = None;
+	assert!(gba.load_from_file(&path).is_ok());
+	let _ = fs::remove_file(&path);
+}
@@ -20,6 +20,11 @@ use std::fs::File;
 
 use gba::core::memory::GbaMemory;
 use gba::Gba;
+use gba::device::link;
+use gba::device::multiboot;
+use gba::device::joybus::JoyBusHost;
+use gba::device::uart::UartDevice;
+use gba::device::audio::AudioOutput;
 
 const BIOS_PATH: &'static str = "roms/legal/gba.bin";
 
@@ -47,9 +52,8 @@ pub fn load_bios(memory: &mut GbaMemory) {
 	debug_info!("Loaded BIOS at {}.", BIOS_PATH);
 }
 
-pub fn load_rom(gba: &mut Gba, rom_path: String) {
-	let filepath = rom_path;
-	let mut f = match File::open(filepath.clone()) {
+pub fn load_file(filepath: &str) -> Vec<u8> {
+	let mut f = match File::open(filepath) {
 		Ok(file) => file,
 		Err(error) => panic!("Error while opening file '{}': {}", filepath, error)
 	};
@@ -59,8 +63,13 @@ pub fn load_rom(gba: &mut Gba, rom_path: String) {
 		Ok(_) => {},
 		Err(error) => panic!("Error while reading file `{}`: {}", filepath, error)
 	}
+	buffer
+}
+
+pub fn load_rom(gba: &mut Gba, rom_path: String) {
+	let buffer = load_file(&rom_path);
 	gba.load_cartridge(buffer);
-	debug_info!("Loaded ROM {}.", filepath);
+	debug_info!("Loaded ROM {}.", rom_path);
 }
 
 pub fn load_memory(rom_path: String) -> GbaMemory {
@@ -96,12 +105,46 @@ Usage:
 	pyrite (-d | --disasm) [(-t | --thumb)] <rom>
 	pyrite (-h | --help)
 	pyrite (-v | --version)
+	pyrite [--headless] [--frames <n>] [--link-host <address> [--link-players <n>] | --link-connect <address>] <rom>
+	pyrite [--headless] [--frames <n>] --link-local <n> <rom>
+	pyrite [--headless] [--frames <n>] --multiboot <image.mb>
+	pyrite [--headless] [--frames <n>] --joybus-script <script> <rom>
+	pyrite [--headless] [--frames <n>] --uart <sink> [--uart-input <input>] <rom>
+	pyrite [--headless] [--frames <n>] --screenshot <file.png> <rom>
+	pyrite [--headless] [--frames <n>] --record <file.y4m> <rom>
+	pyrite [--headless] [--frames <n>] --dump-audio <file.wav> [--stems] <rom>
+	pyrite [--headless] [--frames <n>] --audio <output> <rom>
+	pyrite [--headless] [--frames <n>] --record-movie <file.pmv> <rom>
+	pyrite [--headless] [--frames <n>] --play-movie <file.pmv> [--movie-read-write] <rom>
+	pyrite --check-save-state <n> [--frames <m>] <rom>
 
 Options:
-	-d --disasm     Disassembles the ROM.
-	-t --thumb      Will disassemble in thumb mode.
-	-h --help       Show this screen.
-	-v --version    Prints the version and exits.
+	-d --disasm             Disassembles the ROM.
+	-t --thumb              Will disassemble in thumb mode.
+	-h --help               Show this screen.
+	-v --version            Prints the version and exits.
+	--headless              Runs without a window or audio output (unless --audio is given).
+	--frames <n>            Exits after running <n> frames.
+	--link-host <address>   Waits for other instances to connect to the link cable at <address>.
+	--link-players <n>      The number of GBAs on the link cable including this one (2-4) [default: 2].
+	--link-connect <address> Connects to the link cable of another instance at <address>.
+	--link-local <n>        Links <n> GBAs (2-4) running the same ROM in this process, only the first one is shown.
+	--multiboot <image.mb>  Boots without a cartridge and uploads <image.mb> over the link cable.
+	--joybus-script <script> Drives the serial port in JOY Bus mode from <script>.
+	--uart <sink>           Sends bytes from the serial port in UART mode to stdout, console or file:<path>.
+	--uart-input <input>    Bytes received in UART mode come from stdin or from the file <input>.
+	--screenshot <file.png> Saves the last frame to <file.png> before exiting.
+	--record <file.y4m>     Records video to <file.y4m> and audio to <file.wav>.
+	--dump-audio <file.wav> Writes the final audio mix to <file.wav>.
+	--stems                 Also writes every sound channel next to the audio dump.
+	--audio <output>        Sends audio to portaudio, null, wav or wav:<path> instead of the output in the settings.
+	--record-movie <file.pmv> Records the input of every frame from power-on to <file.pmv>.
+	--play-movie <file.pmv> Plays back the input movie <file.pmv>.
+	--movie-read-write      Pressing a key during playback continues recording the movie from there.
+	--check-save-state <n>  Saves a state at frame <n>, loads it into a second GBA and checks that
+	                        both stay the same for --frames frames [default: 60].
+
+Link cable addresses are either tcp:<host>:<port> or unix:<path>.
 ";
 
 
@@ -111,26 +154,81 @@ struct Args {
 	flag_version: bool,
 	flag_disasm: bool,
 	flag_thumb: bool,
-	flag_help: bool
+	flag_help: bool,
+	flag_headless: bool,
+	flag_frames: Option<u64>,
+	flag_link_host: Option<String>,
+	flag_link_players: Option<usize>,
+	flag_link_connect: Option<String>,
+	flag_link_local: Option<usize>,
+	flag_multiboot: Option<String>,
+	flag_joybus_script: Option<String>,
+	flag_uart: Option<String>,
+	flag_uart_input: Option<String>,
+	flag_screenshot: Option<String>,
+	flag_record: Option<String>,
+	flag_dump_audio: Option<String>,
+	flag_stems: bool,
+	flag_audio: Option<String>,
+	flag_check_save_state: Option<u64>,
+	flag_record_movie: Option<String>,
+	flag_play_movie: Option<String>,
+	flag_movie_read_write: bool,
+}
+
+fn arg_value(args_list: &Vec<String>, index: usize) -> String {
+	match args_list.get(index) {
+		Some(value) => value.clone(),
+		None => panic!("Expected a value after {}", args_list[index - 1])
+	}
+}
+
+fn parse_arg_value<T: std::str::FromStr>(args_list: &Vec<String>, index: usize) -> T {
+	let value = arg_value(args_list, index);
+	match value.parse() {
+		Ok(parsed) => parsed,
+		Err(_) => panic!("Invalid value for {}: {}", args_list[index - 1], value)
+	}
 }
 
 fn main() {
 	let mut args: Args = Default::default();
 	let args_list: Vec<String> = env::args().collect();
 
-	for (index, arg) in args_list.iter().enumerate() {
-		if index == 0 { continue; } // This is the executable.
+	let mut index = 1; // 0 is the executable.
+	while index < args_list.len() {
+		let arg = &args_list[index];
 		if arg.starts_with("-") {
 			match arg.as_ref() {
 				"-d" | "--disasm"	=> args.flag_disasm = true,
 				"-t" | "--thumb"	=> args.flag_thumb = true,
 				"-v" | "--version"	=> args.flag_version = true,
 				"-h" | "--help"		=> args.flag_help = true,
+				"--headless"		=> args.flag_headless = true,
+				"--frames"			=> { index += 1; args.flag_frames = Some(parse_arg_value(&args_list, index)) },
+				"--link-host"		=> { index += 1; args.flag_link_host = Some(arg_value(&args_list, index)) },
+				"--link-players"	=> { index += 1; args.flag_link_players = Some(parse_arg_value(&args_list, index)) },
+				"--link-connect"	=> { index += 1; args.flag_link_connect = Some(arg_value(&args_list, index)) },
+				"--link-local"		=> { index += 1; args.flag_link_local = Some(parse_arg_value(&args_list, index)) },
+				"--multiboot"		=> { index += 1; args.flag_multiboot = Some(arg_value(&args_list, index)) },
+				"--joybus-script"	=> { index += 1; args.flag_joybus_script = Some(arg_value(&args_list, index)) },
+				"--uart"			=> { index += 1; args.flag_uart = Some(arg_value(&args_list, index)) },
+				"--uart-input"		=> { index += 1; args.flag_uart_input = Some(arg_value(&args_list, index)) },
+				"--screenshot"		=> { index += 1; args.flag_screenshot = Some(arg_value(&args_list, index)) },
+				"--record"			=> { index += 1; args.flag_record = Some(arg_value(&args_list, index)) },
+				"--dump-audio"		=> { index += 1; args.flag_dump_audio = Some(arg_value(&args_list, index)) },
+				"--stems"			=> { args.flag_stems = true },
+				"--audio"			=> { index += 1; args.flag_audio = Some(arg_value(&args_list, index)) },
+				"--check-save-state"	=> { index += 1; args.flag_check_save_state = Some(parse_arg_value(&args_list, index)) },
+				"--record-movie"	=> { index += 1; args.flag_record_movie = Some(arg_value(&args_list, index)) },
+				"--play-movie"		=> { index += 1; args.flag_play_movie = Some(arg_value(&args_list, index)) },
+				"--movie-read-write"	=> args.flag_movie_read_write = true,
 				_ => { panic!("Unexpected option {}", arg) }
 			}
 		} else {
 			args.arg_rom = Some(arg.clone());
 		}
+		index += 1;
 	}
 
 	if args.flag_help || args_list.len() < 1{
@@ -143,23 +241,154 @@ fn main() {
 		return;
 	}
 
-	if let Some(rom_file) = args.arg_rom {
-		debug_info!("Emulating ROM: {}", rom_file);
-		if args.flag_disasm {
+	if args.flag_disasm {
+		if let Some(rom_file) = args.arg_rom {
+			debug_info!("Disassembling ROM: {}", rom_file);
 			let mut memory = load_memory(rom_file);
 			// load_bios(&mut memory);
 			disasm_gba_rom(&mut memory, args.flag_thumb);
-		} else {
-			pyrite::load_settings();
-			debug_info!("Loaded pyrite settings.");
-			let mut gba = Box::new(Gba::new());
-			load_bios(&mut gba.cpu.memory);
-			load_rom(&mut gba, rom_file);
-			debug_info!("Press H to open up the debugger.");
-			run_gba(&mut gba);
+			return;
 		}
+	} else if args.flag_check_save_state.is_some() && args.arg_rom.is_some() {
+		check_save_state(args);
+		return;
+	} else if args.arg_rom.is_some() || args.flag_multiboot.is_some() {
+		run_emulator(args);
+		return;
+	}
+
+	println!("NO ROM FILE PROVIDED.");
+	println!("{}", USAGE);
+}
+
+fn check_save_state(args: Args) {
+	pyrite::load_settings();
+	let rom_file = args.arg_rom.expect("A ROM to check.");
+	let save_at = args.flag_check_save_state.unwrap_or(0);
+	let frames = args.flag_frames.unwrap_or(60);
+
+	let mut original = Box::new(Gba::headless());
+	let mut loaded = Box::new(Gba::headless());
+	load_bios(&mut original.cpu.memory);
+	load_bios(&mut loaded.cpu.memory);
+	load_rom(&mut original, rom_file.clone());
+	load_rom(&mut loaded, rom_file);
+
+	match gba::state_check::check_save_state(&mut original, &mut loaded, save_at, frames) {
+		Ok(_) => println!("Save state from frame {} matched for {} frames.", save_at, frames),
+		Err(e) => {
+			println_err!("Save state check failed: {}", e);
+			std::process::exit(1);
+		}
+	}
+}
+
+fn run_emulator(args: Args) {
+	pyrite::load_settings();
+	debug_info!("Loaded pyrite settings.");
+	let audio_output = match args.flag_audio {
+		Some(ref description) => match AudioOutput::from_description(description) {
+			Ok(output) => Some(output),
+			Err(e) => panic!("{}", e)
+		},
+		None => None,
+	};
+	let mut gba = if args.flag_headless {
+		let mut gba = Box::new(Gba::headless());
+		if let Some(output) = audio_output {
+			gba.device.audio.start(output);
+		}
+		gba
+	} else if let Some(output) = audio_output {
+		Box::new(Gba::with_audio(output))
 	} else {
-		println!("NO ROM FILE PROVIDED.");
-		println!("{}", USAGE);
+		Box::new(Gba::new())
+	};
+	load_bios(&mut gba.cpu.memory);
+
+	if let Some(rom_file) = args.arg_rom {
+		debug_info!("Emulating ROM: {}", rom_file);
+		load_rom(&mut gba, rom_file);
+	}
+
+	if let Some(image_file) = args.flag_multiboot {
+		// The BIOS falls through to multiboot when there is no cartridge.
+		let image = load_file(&image_file);
+		match multiboot::start(image) {
+			Ok(device) => gba.device.link = Some(device),
+			Err(e) => panic!("Failed to start multiboot upload: {}", e)
+		}
+		debug_info!("Uploading multiboot image {}.", image_file);
+	} else if let Some(address) = args.flag_link_host {
+		let players = args.flag_link_players.unwrap_or(2);
+		match link::host(&address, players.saturating_sub(1)) {
+			Ok(device) => gba.device.link = Some(device),
+			Err(e) => panic!("Failed to host link cable: {}", e)
+		}
+	} else if let Some(address) = args.flag_link_connect {
+		match link::connect(&address) {
+			Ok(device) => gba.device.link = Some(device),
+			Err(e) => panic!("Failed to connect link cable: {}", e)
+		}
+	} else if let Some(players) = args.flag_link_local {
+		let players = max!(2, min!(4, players));
+		gba.link_local_players(players - 1);
+	}
+
+	if let Some(script_file) = args.flag_joybus_script {
+		match JoyBusHost::load(&script_file) {
+			Ok(host) => gba.device.joybus = Some(host),
+			Err(e) => panic!("Failed to load JOY Bus script: {}", e)
+		}
+	}
+
+	if let Some(sink) = args.flag_uart {
+		let mut uart = match UartDevice::from_description(&sink) {
+			Ok(uart) => uart,
+			Err(e) => panic!("Failed to open UART: {}", e)
+		};
+		match args.flag_uart_input {
+			Some(ref input) if input == "stdin" => uart.inject_stdin(),
+			Some(ref input) => uart.inject(&load_file(input)),
+			None => {}
+		}
+		gba.device.uart = Some(uart);
+	}
+
+	if let Some(ref path) = args.flag_record {
+		if let Err(e) = gba.start_recording(Some(&path[..])) {
+			debug_error!("{}", e);
+		}
+	}
+
+	if let Some(ref path) = args.flag_dump_audio {
+		let stems = args.flag_stems || psetting!(audio_dump_stems);
+		if let Err(e) = gba.start_audio_dump(Some(&path[..]), stems) {
+			debug_error!("{}", e);
+		}
+	}
+
+	if let Some(ref path) = args.flag_record_movie {
+		if let Err(e) = gba.start_movie_recording(Some(&path[..])) {
+			debug_error!("{}", e);
+		}
+	} else if let Some(ref path) = args.flag_play_movie {
+		if let Err(e) = gba.play_movie(&path[..], !args.flag_movie_read_write) {
+			debug_error!("{}", e);
+		}
+	}
+
+	if let Some(frames) = args.flag_frames {
+		gba.run_frames(frames);
+	} else {
+		debug_info!("Press H to open up the debugger.");
+		run_gba(&mut gba);
+	}
+
+	if let Some(path) = args.flag_screenshot {
+		match gba.save_screenshot(Some(&path[..])) {
+			Ok(_) => debug_info!("Saved screenshot to {}.", path),
+			Err(e) => debug_error!("{}", e),
+		}
 	}
 }
@@ -8,6 +8,13 @@ use std::io::prelude::*;
 
 use std::sync::Arc;
 use ::util::atomic::Atomic;
+use ::gba::hw::lcd::color::ColorProfile;
+use ::gba::device::filter::{Scaler, LcdOverlay};
+use ::gba::device::resampler::ResamplingQuality;
+use ::gba::device::audio::{AudioBackend, DEFAULT_TARGET_LATENCY};
+use ::gba::device::audio_filter::AudioOutputFilter;
+use ::gba::device::pacer::{FastForwardSpeed, FastForwardAudio};
+use ::gba::device::bindings::{KeyBinding, default_bindings};
 
 lazy_static! {
 	pub static ref ATOMIC_MASTER_VOLUME: Arc<Atomic<f32>> = Arc::new(Atomic::new(0.0));
@@ -21,9 +28,32 @@ pub struct PyriteSettings {
 	pub window_width: u32,
 	pub window_height: u32,
 
+	// VIDEO SETTINGS:
+	pub color_profile: ColorProfile,
+	pub scaler: Scaler,
+	pub scale: u32,
+	pub lcd_overlay: LcdOverlay,
+	pub frame_blending: bool,
+	pub screenshot_dir: String,
+	/// Screenshots go through the filters instead of being saved at 240x160.
+	pub screenshot_filtered: bool,
+	/// Recordings go through the filters too, at the size they had when the recording started.
+	pub recording_filtered: bool,
+	pub recording_dir: String,
+	pub audio_dump_dir: String,
+	pub save_state_dir: String,
+	pub movie_dir: String,
+	/// Audio dumps also write every sound channel to its own file.
+	pub audio_dump_stems: bool,
+
 	// SOUND SETTINGS:
 	pub master_volume: f32,
 	pub sound_enabled: bool,
+	pub audio_backend: AudioBackend,
+	pub resampling_quality: ResamplingQuality,
+	/// How much audio is buffered ahead of the output in milliseconds.
+	pub audio_latency: u32,
+	pub audio_output_filter: AudioOutputFilter,
 	pub channel1_enabled: bool,
 	pub channel2_enabled: bool,
 	pub channel3_enabled: bool,
@@ -31,6 +61,24 @@ pub struct PyriteSettings {
 	pub channela_enabled: bool,
 	pub channelb_enabled: bool,
 
+	// SPEED SETTINGS:
+	pub fast_forward_speed: FastForwardSpeed,
+	pub fast_forward_audio: FastForwardAudio,
+
+	// REWIND SETTINGS:
+	pub rewind_enabled: bool,
+	/// Frames between snapshots.
+	pub rewind_interval: u32,
+	/// How far back it's possible to go in seconds.
+	pub rewind_length: u32,
+	/// The most memory the snapshots can use in megabytes.
+	pub rewind_memory: u32,
+
+	// INPUT SETTINGS:
+	pub key_bindings: Vec<KeyBinding>,
+	/// Frames a turbo button stays pressed and then released.
+	pub turbo_rate: u32,
+
 	pub changed: bool,
 }
 
@@ -87,9 +135,28 @@ impl Default for PyriteSettings {
 			window_width: 240 * 3,
 			window_height: 160 * 3,
 
+			// VIDEO SETTINGS
+			color_profile: ColorProfile::Raw,
+			scaler: Scaler::Nearest,
+			scale: 1,
+			lcd_overlay: LcdOverlay::None,
+			frame_blending: false,
+			screenshot_dir: "data/screenshots".to_string(),
+			screenshot_filtered: false,
+			recording_filtered: false,
+			recording_dir: "data/recordings".to_string(),
+			audio_dump_dir: "data/audio".to_string(),
+			save_state_dir: "data/savestates".to_string(),
+			movie_dir: "data/movies".to_string(),
+			audio_dump_stems: false,
+
 			// SOUND SETTINGS
 			master_volume: 1.0,
 			sound_enabled: true,
+			audio_backend: AudioBackend::PortAudio,
+			resampling_quality: ResamplingQuality::Medium,
+			audio_latency: DEFAULT_TARGET_LATENCY,
+			audio_output_filter: AudioOutputFilter::None,
 			channel1_enabled: true,
 			channel2_enabled: true,
 			channel3_enabled: true,
@@ -97,6 +164,20 @@ impl Default for PyriteSettings {
 			channela_enabled: true,
 			channelb_enabled: true,
 
+			// SPEED SETTINGS
+			fast_forward_speed: FastForwardSpeed::Quadruple,
+			fast_forward_audio: FastForwardAudio::Stretch,
+
+			// REWIND SETTINGS
+			rewind_enabled: true,
+			rewind_interval: 2,
+			rewind_length: 20,
+			rewind_memory: 64,
+
+			// INPUT SETTINGS
+			key_bindings: default_bindings(),
+			turbo_rate: 2,
+
 			changed: false,
 		}
 	}
@@ -1,6 +1,7 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::mem;
+use std::cmp;
 
 /// A ring buffer that allows a single producer
 /// and a single consumer possibly on different threads.
@@ -16,10 +17,9 @@ pub struct AsyncRingBuffer<T> {
 	/// blocking.
 	writer_cursor: AtomicUsize,
 
-	// #TODO remove these
-	// these are just some variables I'm using to track
-	// how often the reader is catching up the the writer
-	// and vice versa.
+	// these track how often the reader is catching up
+	// to the writer and vice versa. The audio device shows
+	// them as underruns and overruns.
 	pub _stat_read_misses: AtomicUsize,
 	pub _stat_write_misses: AtomicUsize,
 
@@ -53,6 +53,22 @@ impl<T> AsyncRingBuffer<T> {
 		return ret;
 	}
 
+	/// The number of items that have been written but not read yet.
+	/// The other side might be in the middle of changing this so it's only an estimate.
+	pub fn len(&self) -> usize {
+		let cur_reader_cursor = self.reader_cursor.load(Ordering::Relaxed);
+		let cur_writer_cursor = self.writer_cursor.load(Ordering::Relaxed);
+		if cur_writer_cursor > cur_reader_cursor {
+			cmp::min(cur_writer_cursor - cur_reader_cursor, self.data.len())
+		} else {
+			0
+		}
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.data.len()
+	}
+
 	pub fn try_write<F>(&self, mut write_fn: F) -> bool
 		where F: FnMut(&mut T) -> bool {
 		let cur_writer_cursor = self.writer_cursor.load(Ordering::Acquire);
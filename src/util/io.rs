@@ -1,5 +1,18 @@
-use std::fs::File;
+use std::fs::{File, create_dir_all};
 use std::io::prelude::*;
+use std::path::Path;
+
+/// Creates the directory that `path` goes in, and the ones above it, if they don't exist yet.
+pub fn create_parent_dir(path: &Path) -> Result<(), String> {
+	if let Some(parent) = path.parent() {
+		if !parent.as_os_str().is_empty() {
+			if let Err(e) = create_dir_all(parent) {
+				return Err(format!("Failed to create directory {}: {}", parent.display(), e))
+			}
+		}
+	}
+	Ok(())
+}
 
 pub fn read_file_into_string<'a>(file_path: &'a str) -> Result<String, String> {
 	let mut file_string = String::new();
@@ -5,6 +5,8 @@ pub mod async_ring_buffer;
 pub mod circular_buffer;
 pub mod sync_unsafe_cell;
 pub mod atomic;
+pub mod png;
+pub mod wav;
 
 macro_rules! max {
 	($a:expr, $b:expr) => ({
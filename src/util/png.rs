@@ -0,0 +1,135 @@
+//! A small PNG writer for 8-bit RGB images, and a reader for the images it writes.
+
+use std::io;
+use std::io::prelude::*;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use flate2::read::ZlibDecoder;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Writes `pixels` (row major, `width * height` of them) as a PNG.
+pub fn write_png(writer: &mut Write, width: usize, height: usize, pixels: &[(u8, u8, u8)]) -> io::Result<()> {
+	if let Err(e) = writer.write_all(&PNG_SIGNATURE) { return Err(e) }
+
+	let mut ihdr = Vec::with_capacity(13);
+	push_u32_be(&mut ihdr, width as u32);
+	push_u32_be(&mut ihdr, height as u32);
+	ihdr.push(8); // bit depth
+	ihdr.push(2); // color type: RGB
+	ihdr.push(0); // compression method: deflate
+	ihdr.push(0); // filter method: adaptive
+	ihdr.push(0); // interlace method: none
+	if let Err(e) = write_chunk(writer, b"IHDR", &ihdr) { return Err(e) }
+
+	// Every row starts with its filter type, we always use 0 (None).
+	let mut encoder = ZlibEncoder::new(Vec::with_capacity(width * height * 3), Compression::Default);
+	let mut row = Vec::with_capacity(1 + width * 3);
+	for y in 0..height {
+		row.clear();
+		row.push(0);
+		for &(r, g, b) in pixels[(y * width)..((y + 1) * width)].iter() {
+			row.push(r);
+			row.push(g);
+			row.push(b);
+		}
+		if let Err(e) = encoder.write_all(&row) { return Err(e) }
+	}
+	let idat = match encoder.finish() {
+		Ok(data) => data,
+		Err(e) => return Err(e),
+	};
+	if let Err(e) = write_chunk(writer, b"IDAT", &idat) { return Err(e) }
+
+	write_chunk(writer, b"IEND", &[])
+}
+
+/// Reads a PNG written by `write_png` and returns its width, height and pixels.
+/// Only 8-bit RGB images without interlacing or row filters are supported.
+pub fn read_png(reader: &mut Read) -> io::Result<(usize, usize, Vec<(u8, u8, u8)>)> {
+	let mut data = Vec::new();
+	if let Err(e) = reader.read_to_end(&mut data) { return Err(e) }
+	if data.len() < 8 || data[0..8] != PNG_SIGNATURE { return Err(invalid("not a PNG file")) }
+
+	let mut width = 0;
+	let mut height = 0;
+	let mut idat = Vec::new();
+	let mut offset = 8;
+	while offset + 12 <= data.len() {
+		let length = read_u32_be(&data[offset..]) as usize;
+		let chunk_type = &data[(offset + 4)..(offset + 8)];
+		if offset + 12 + length > data.len() { return Err(invalid("truncated chunk")) }
+		let chunk = &data[(offset + 8)..(offset + 8 + length)];
+		match chunk_type {
+			b"IHDR" => {
+				if length != 13 { return Err(invalid("bad IHDR")) }
+				if chunk[8..13] != [8, 2, 0, 0, 0] { return Err(invalid("only 8-bit RGB images are supported")) }
+				width = read_u32_be(&chunk[0..4]) as usize;
+				height = read_u32_be(&chunk[4..8]) as usize;
+			},
+			b"IDAT" => idat.extend_from_slice(chunk),
+			b"IEND" => break,
+			_ => {},
+		}
+		offset += 12 + length;
+	}
+
+	let mut rows = Vec::with_capacity(height * (1 + width * 3));
+	if let Err(e) = ZlibDecoder::new(&idat[..]).read_to_end(&mut rows) { return Err(e) }
+	if rows.len() != height * (1 + width * 3) { return Err(invalid("wrong amount of image data")) }
+
+	let mut pixels = Vec::with_capacity(width * height);
+	for row in rows.chunks(1 + width * 3) {
+		if row[0] != 0 { return Err(invalid("only unfiltered rows are supported")) }
+		for rgb in row[1..].chunks(3) {
+			pixels.push((rgb[0], rgb[1], rgb[2]));
+		}
+	}
+	Ok((width, height, pixels))
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32_be(data: &[u8]) -> u32 {
+	((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32)
+}
+
+fn write_chunk(writer: &mut Write, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+	let mut header = Vec::with_capacity(8);
+	push_u32_be(&mut header, data.len() as u32);
+	header.extend_from_slice(chunk_type);
+	if let Err(e) = writer.write_all(&header) { return Err(e) }
+	if let Err(e) = writer.write_all(data) { return Err(e) }
+
+	let crc = !update_crc(update_crc(0xFFFFFFFF, chunk_type), data);
+	let mut footer = Vec::with_capacity(4);
+	push_u32_be(&mut footer, crc);
+	writer.write_all(&footer)
+}
+
+fn push_u32_be(buffer: &mut Vec<u8>, value: u32) {
+	buffer.push((value >> 24) as u8);
+	buffer.push((value >> 16) as u8);
+	buffer.push((value >> 8) as u8);
+	buffer.push(value as u8);
+}
+
+/// CRC-32 of `data`, the same one PNG chunks use.
+pub fn crc32(data: &[u8]) -> u32 {
+	!update_crc(0xFFFFFFFF, data)
+}
+
+/// CRC-32 (polynomial 0xEDB88320) as used by PNG chunks.
+fn update_crc(crc: u32, data: &[u8]) -> u32 {
+	let mut crc = crc;
+	for &byte in data {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = if (crc & 1) != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+		}
+	}
+	crc
+}
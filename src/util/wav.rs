@@ -0,0 +1,103 @@
+//! A small WAV writer for 16-bit PCM audio.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+/// Size of the RIFF and fmt headers plus the data chunk header.
+const HEADER_SIZE: u32 = 44;
+
+/// Writes 16-bit PCM samples to a WAV file.
+/// The sizes in the header are only correct after `finish` is called.
+pub struct WavWriter<W: Write + Seek> {
+	writer: W,
+	channels: u16,
+	data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+	pub fn new(mut writer: W, channels: u16, sample_rate: u32) -> io::Result<WavWriter<W>> {
+		let block_align = channels * 2;
+		let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+		header.extend_from_slice(b"RIFF");
+		push_u32_le(&mut header, HEADER_SIZE - 8); // patched in finish
+		header.extend_from_slice(b"WAVE");
+
+		header.extend_from_slice(b"fmt ");
+		push_u32_le(&mut header, 16);
+		push_u16_le(&mut header, 1); // PCM
+		push_u16_le(&mut header, channels);
+		push_u32_le(&mut header, sample_rate);
+		push_u32_le(&mut header, sample_rate * block_align as u32);
+		push_u16_le(&mut header, block_align);
+		push_u16_le(&mut header, 16); // bits per sample
+
+		header.extend_from_slice(b"data");
+		push_u32_le(&mut header, 0); // patched in finish
+
+		if let Err(e) = writer.write_all(&header) { return Err(e) }
+
+		Ok(WavWriter {
+			writer: writer,
+			channels: channels,
+			data_size: 0,
+		})
+	}
+
+	/// Writes interleaved stereo frames. Only valid for 2 channel files.
+	pub fn write_stereo(&mut self, frames: &[(i16, i16)]) -> io::Result<()> {
+		debug_assert!(self.channels == 2);
+		let mut data = Vec::with_capacity(frames.len() * 4);
+		for &(left, right) in frames.iter() {
+			push_u16_le(&mut data, left as u16);
+			push_u16_le(&mut data, right as u16);
+		}
+		self.write_data(&data)
+	}
+
+	/// Writes samples for a 1 channel file.
+	pub fn write_mono(&mut self, samples: &[i16]) -> io::Result<()> {
+		debug_assert!(self.channels == 1);
+		let mut data = Vec::with_capacity(samples.len() * 2);
+		for &sample in samples.iter() {
+			push_u16_le(&mut data, sample as u16);
+		}
+		self.write_data(&data)
+	}
+
+	fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+		if let Err(e) = self.writer.write_all(data) { return Err(e) }
+		self.data_size = self.data_size.wrapping_add(data.len() as u32);
+		Ok(())
+	}
+
+	/// Fills in the sizes in the header and flushes the writer.
+	pub fn finish(mut self) -> io::Result<W> {
+		let mut size = Vec::with_capacity(4);
+
+		push_u32_le(&mut size, HEADER_SIZE - 8 + self.data_size);
+		if let Err(e) = self.writer.seek(SeekFrom::Start(4)) { return Err(e) }
+		if let Err(e) = self.writer.write_all(&size) { return Err(e) }
+
+		size.clear();
+		push_u32_le(&mut size, self.data_size);
+		if let Err(e) = self.writer.seek(SeekFrom::Start(40)) { return Err(e) }
+		if let Err(e) = self.writer.write_all(&size) { return Err(e) }
+
+		if let Err(e) = self.writer.seek(SeekFrom::End(0)) { return Err(e) }
+		if let Err(e) = self.writer.flush() { return Err(e) }
+		Ok(self.writer)
+	}
+}
+
+fn push_u16_le(buffer: &mut Vec<u8>, value: u16) {
+	buffer.push(value as u8);
+	buffer.push((value >> 8) as u8);
+}
+
+fn push_u32_le(buffer: &mut Vec<u8>, value: u32) {
+	buffer.push(value as u8);
+	buffer.push((value >> 8) as u8);
+	buffer.push((value >> 16) as u8);
+	buffer.push((value >> 24) as u8);
+}